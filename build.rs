@@ -0,0 +1,191 @@
+//! Generates the ROM database from the CHIP-8 community database's `programs.json`
+//! (https://github.com/chip-8/chip-8-database). The copy in `database/` is used
+//! unless `CHIP8_DATABASE` names another one.
+
+// The error and JSON modules are shared with the library, which uses more of them.
+#![allow(dead_code)]
+
+#[path = "src/errors.rs"]
+mod errors;
+#[path = "src/json.rs"]
+mod json;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::json::{parse_color, Json};
+
+/// The quirks of each platform in the community database, by the database's names
+/// for them, before any changes a program makes.
+fn platform_quirks(platform: &str) -> BTreeMap<&'static str, bool> {
+    let on: &[&str] = match platform {
+        "originalChip8" | "hybridVIP" => &["vblank", "logic"],
+        "chip8x" => &["vblank", "logic"],
+        "chip48" => &["shift", "memoryIncrementByX", "jump"],
+        "superchip1" => &["shift", "memoryLeaveIUnchanged", "jump", "vblank"],
+        "superchip" | "megachip8" => &["shift", "memoryLeaveIUnchanged", "jump"],
+        "xochip" => &["wrap"],
+        _ => &[],
+    };
+    let mut quirks = BTreeMap::new();
+    for &quirk in &[
+        "shift",
+        "memoryIncrementByX",
+        "memoryLeaveIUnchanged",
+        "wrap",
+        "jump",
+        "vblank",
+        "logic",
+    ] {
+        quirks.insert(quirk, on.contains(&quirk));
+    }
+    quirks
+}
+
+/// Writes one ROM's entry as a `(hash, RomInfo)` expression.
+fn rom_entry(program: &Json, hash: &str, rom: &Json) -> String {
+    let title = match program.get("title") {
+        Some(Json::String(title)) => title.as_str(),
+        _ => "",
+    };
+    let author = match program.get("authors") {
+        Some(Json::Array(authors)) => authors
+            .iter()
+            .filter_map(|author| match author {
+                Json::String(author) => Some(author.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => String::new(),
+    };
+    let platform = match rom.get("platforms") {
+        Some(Json::Array(platforms)) => match platforms.first() {
+            Some(Json::String(platform)) => platform.as_str(),
+            _ => "",
+        },
+        _ => "",
+    };
+
+    let mut quirks = platform_quirks(platform);
+    if let Some(Json::Object(changes)) = rom
+        .get("quirkyPlatforms")
+        .and_then(|quirky| quirky.get(platform))
+    {
+        for (quirk, value) in changes {
+            if let (Some(setting), Json::Bool(value)) = (quirks.get_mut(quirk.as_str()), value) {
+                *setting = *value;
+            }
+        }
+    }
+
+    let instructions_per_frame = match rom.get("tickrate") {
+        Some(Json::Number(rate)) if *rate >= 1.0 => format!("Some({})", *rate as u32),
+        _ => "None".to_owned(),
+    };
+
+    let mut keys: Vec<(u8, &str)> = match rom.get("keys") {
+        Some(Json::Object(keys)) => keys
+            .iter()
+            .filter_map(|(action, key)| match key {
+                Json::Number(key) if (0.0..16.0).contains(key) => {
+                    Some((*key as u8, action.as_str()))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    keys.sort();
+
+    // The first pixel color is the background, and the second the foreground.
+    let colors = match rom.get("colors").and_then(|colors| colors.get("pixels")) {
+        Some(Json::Array(pixels)) => match (pixels.first(), pixels.get(1)) {
+            (Some(Json::String(background)), Some(Json::String(foreground))) => {
+                match (parse_color(foreground), parse_color(background)) {
+                    (Some(foreground), Some(background)) => format!(
+                        "Some(Colors {{ foreground: {:#X}, background: {:#X} }})",
+                        foreground, background
+                    ),
+                    _ => "None".to_owned(),
+                }
+            }
+            _ => "None".to_owned(),
+        },
+        _ => "None".to_owned(),
+    };
+
+    let mut entry = String::new();
+    write!(entry, "({:?}, RomInfo {{ ", hash.to_lowercase()).unwrap();
+    write!(entry, "title: {:?}, author: {:?}, ", title, author).unwrap();
+    write!(
+        entry,
+        "platform: Platform::{}, ",
+        if platform == "megachip8" {
+            "MegaChip8"
+        } else {
+            "Chip8"
+        }
+    )
+    .unwrap();
+    write!(
+        entry,
+        "quirks: Quirks {{ display_wait: {}, shift: {}, load_store: {}, jump: {}, logic: {}, \
+         clip: {} }}, ",
+        quirks["vblank"],
+        quirks["shift"],
+        quirks["memoryLeaveIUnchanged"],
+        quirks["jump"],
+        quirks["logic"],
+        !quirks["wrap"]
+    )
+    .unwrap();
+    write!(
+        entry,
+        "instructions_per_frame: {}, keys: &{:?}, colors: {} }})",
+        instructions_per_frame, keys, colors
+    )
+    .unwrap();
+    entry
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=CHIP8_DATABASE");
+    let path = match env::var_os("CHIP8_DATABASE") {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("database/programs.json"),
+    };
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+    let programs = match Json::parse(&text) {
+        Ok(Json::Array(programs)) => programs,
+        Ok(_) => panic!("{} isn't a list of programs", path.display()),
+        Err(e) => panic!("{} {}", path.display(), e.0),
+    };
+
+    // Entries are sorted by hash so they can be searched. A ROM listed under more
+    // than one program keeps the first.
+    let mut entries = BTreeMap::new();
+    for program in &programs {
+        if let Some(Json::Object(roms)) = program.get("roms") {
+            for (hash, rom) in roms {
+                entries
+                    .entry(hash.to_lowercase())
+                    .or_insert_with(|| rom_entry(program, hash, rom));
+            }
+        }
+    }
+
+    let mut table = String::from("&[\n");
+    for entry in entries.values() {
+        writeln!(table, "    {},", entry).unwrap();
+    }
+    table.push(']');
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("database.rs");
+    fs::write(out, table).unwrap();
+}
@@ -0,0 +1,37 @@
+/// Receives callbacks from the emulator when sound should be played.
+pub trait AudioHandler {
+    fn start_sound(&mut self);
+    fn stop_sound(&mut self);
+
+    /// Starts playing a digitized sample, replacing any sample already playing.
+    /// Only MegaChip8 programs play samples, so the default implementation ignores them.
+    fn play_sample(&mut self, _sample: &Sample) {}
+
+    /// Stops the sample started by `play_sample`, if any.
+    fn stop_sample(&mut self) {}
+}
+
+/// A digitized sound played by the MegaChip8 `060N` instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Playback rate, in samples per second.
+    pub rate: u16,
+    /// Unsigned 8-bit mono PCM data, centered around 128.
+    pub data: Vec<u8>,
+    /// Whether the sample should repeat until stopped.
+    pub looping: bool,
+}
+
+/// A request from a running program to the audio handler.
+#[derive(Clone)]
+pub(crate) enum SampleCommand {
+    Play(Sample),
+    Stop,
+}
+
+pub(crate) struct NullAudio;
+
+impl AudioHandler for NullAudio {
+    fn start_sound(&mut self) {}
+    fn stop_sound(&mut self) {}
+}
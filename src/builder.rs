@@ -0,0 +1,333 @@
+use std::rc::Rc;
+
+use crate::audio::AudioHandler;
+use crate::errors::*;
+use crate::opcodes::{IOpcodeRunner, OpcodeRunner};
+use crate::platform::{MemoryLayout, Platform, Quirks};
+use crate::romdb::ExplicitSettings;
+use crate::sprites::{self, Font};
+use crate::system::MachineConfig;
+use crate::vip::VipRunner;
+use crate::{Emulator, Timing, DEFAULT_CLOCK_SPEED, DEFAULT_TIMER_FREQUENCY};
+
+enum ClockRate {
+    Hz(u32),
+    InstructionsPerFrame(u32),
+}
+
+/// Configures and creates an `Emulator`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate chip8;
+///
+/// use chip8::{EmulatorBuilder, Platform};
+///
+/// fn main() {
+///     let emulator = EmulatorBuilder::new()
+///         .platform(Platform::MegaChip8)
+///         .instructions_per_frame(100)
+///         .rng_seed(42)
+///         .build()
+///         .expect("configuration is valid");
+///     assert_eq!(emulator.clock_speed(), 6000);
+/// }
+/// ```
+pub struct EmulatorBuilder {
+    config: MachineConfig,
+    vip: Option<(Vec<u8>, Vec<u8>)>,
+    clock: ClockRate,
+    timer_frequency: u32,
+    timing: Timing,
+    audio: Option<Box<dyn AudioHandler>>,
+    rom_database: bool,
+    explicit: ExplicitSettings,
+}
+
+impl Default for EmulatorBuilder {
+    fn default() -> EmulatorBuilder {
+        EmulatorBuilder::new()
+    }
+}
+
+impl EmulatorBuilder {
+    /// Creates a builder with the default settings.
+    pub fn new() -> EmulatorBuilder {
+        EmulatorBuilder {
+            config: MachineConfig::default(),
+            vip: None,
+            clock: ClockRate::Hz(DEFAULT_CLOCK_SPEED),
+            timer_frequency: DEFAULT_TIMER_FREQUENCY,
+            timing: Timing::default(),
+            audio: None,
+            rom_database: true,
+            explicit: ExplicitSettings::default(),
+        }
+    }
+
+    /// Sets the machine to emulate. Defaults to CHIP-8, or the platform given for the
+    /// program in the ROM database.
+    pub fn platform(mut self, platform: Platform) -> EmulatorBuilder {
+        self.explicit.platform = true;
+        self.config.platform = platform;
+        self
+    }
+
+    /// Runs programs on an emulated COSMAC VIP, using the original CHIP-8
+    /// interpreter. See `Emulator::cosmac_vip`.
+    pub fn cosmac_vip(mut self, monitor: &[u8], interpreter: &[u8]) -> EmulatorBuilder {
+        self.explicit.platform = true;
+        self.vip = Some((monitor.to_vec(), interpreter.to_vec()));
+        self
+    }
+
+    /// Sets where the call stack and display are stored. Defaults to keeping them
+    /// separate from memory.
+    pub fn memory_layout(mut self, layout: MemoryLayout) -> EmulatorBuilder {
+        self.config.layout = layout;
+        self
+    }
+
+    /// Sets the interpreter behaviors programs may depend on. Defaults to the quirks
+    /// given for the program in the ROM database, or none.
+    pub fn quirks(mut self, quirks: Quirks) -> EmulatorBuilder {
+        self.explicit.quirks = ExplicitSettings::ALL_QUIRKS;
+        self.config.quirks = quirks;
+        self
+    }
+
+    /// Sets whether `DXYN` waits for the next frame. See `Quirks::display_wait`.
+    pub fn display_wait(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.display_wait = true;
+        self.config.quirks.display_wait = enabled;
+        self
+    }
+
+    /// Sets whether `8XY6` and `8XYE` shift VX in place. See `Quirks::shift`.
+    pub fn shift_quirk(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.shift = true;
+        self.config.quirks.shift = enabled;
+        self
+    }
+
+    /// Sets whether `FX55` and `FX65` leave `I` unchanged. See `Quirks::load_store`.
+    pub fn load_store_quirk(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.load_store = true;
+        self.config.quirks.load_store = enabled;
+        self
+    }
+
+    /// Sets whether `BNNN` jumps to XNN + VX. See `Quirks::jump`.
+    pub fn jump_quirk(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.jump = true;
+        self.config.quirks.jump = enabled;
+        self
+    }
+
+    /// Sets whether `8XY1`, `8XY2` and `8XY3` reset VF. See `Quirks::logic`.
+    pub fn logic_quirk(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.logic = true;
+        self.config.quirks.logic = enabled;
+        self
+    }
+
+    /// Sets whether sprites are clipped at the edges of the display. See
+    /// `Quirks::clip`.
+    pub fn clip_quirk(mut self, enabled: bool) -> EmulatorBuilder {
+        self.explicit.quirks.clip = true;
+        self.config.quirks.clip = enabled;
+        self
+    }
+
+    /// Sets the number of instructions executed per second. Defaults to 1200 Hz, or
+    /// the speed given for the program in the ROM database.
+    pub fn clock_speed(mut self, hz: u32) -> EmulatorBuilder {
+        self.explicit.clock_speed = true;
+        self.clock = ClockRate::Hz(hz);
+        self
+    }
+
+    /// Sets the number of instructions executed per frame, as an alternative to
+    /// setting the clock speed.
+    pub fn instructions_per_frame(mut self, instructions: u32) -> EmulatorBuilder {
+        self.explicit.clock_speed = true;
+        self.clock = ClockRate::InstructionsPerFrame(instructions);
+        self
+    }
+
+    /// Sets how many times per second the delay and sound timers count down, which
+    /// is also the frame rate. Defaults to 60 Hz.
+    pub fn timer_frequency(mut self, hz: u32) -> EmulatorBuilder {
+        self.timer_frequency = hz;
+        self
+    }
+
+    /// Sets how time passes for the emulated machine. Defaults to host time.
+    pub fn timing(mut self, timing: Timing) -> EmulatorBuilder {
+        self.timing = timing;
+        self
+    }
+
+    /// Seeds the random number generator used by `CXNN`, so that runs can be
+    /// repeated exactly. By default, the generator is seeded randomly.
+    pub fn rng_seed(mut self, seed: u64) -> EmulatorBuilder {
+        self.config.rng_seed = Some(seed);
+        self
+    }
+
+    /// Sets the font used for hexadecimal digit sprites, and the address it is stored
+    /// at. Defaults to the Octo font at address 0.
+    pub fn font(mut self, font: Font, address: u16) -> EmulatorBuilder {
+        self.config.font = font;
+        self.config.font_address = address;
+        self
+    }
+
+    /// Sets the audio handler. By default, sound is not played.
+    pub fn audio_handler(mut self, audio: Box<dyn AudioHandler>) -> EmulatorBuilder {
+        self.audio = Some(audio);
+        self
+    }
+
+    /// Sets whether programs are looked up in the ROM database when they are loaded,
+    /// to choose the settings that aren't set explicitly. Defaults to true.
+    pub fn rom_database(mut self, enabled: bool) -> EmulatorBuilder {
+        self.rom_database = enabled;
+        self
+    }
+
+    /// Sets how errors caused by the program are handled. By default, every error
+    /// stops execution.
+    pub fn error_policies(mut self, policies: ErrorPolicies) -> EmulatorBuilder {
+        self.config.error_policies = policies;
+        self
+    }
+
+    /// Creates the emulator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings can't be used together, such as the VIP
+    /// memory layout with MegaChip8, or if any setting is invalid on its own.
+    pub fn build(self) -> Result<Emulator> {
+        let config = self.config;
+        if self.timer_frequency == 0 {
+            return err("timer frequency must be at least 1 Hz");
+        }
+        let clock_speed = match self.clock {
+            ClockRate::Hz(hz) => hz,
+            ClockRate::InstructionsPerFrame(n) => n.saturating_mul(self.timer_frequency),
+        };
+        if clock_speed == 0 {
+            return err("clock speed must be at least 1 Hz");
+        }
+        if config.layout == MemoryLayout::Vip && config.platform == Platform::MegaChip8 {
+            return err("the VIP memory layout can't be used with MegaChip8");
+        }
+        sprites::check_font_address(config.font_address)?;
+
+        let runner: Rc<dyn IOpcodeRunner> = match self.vip {
+            Some((monitor, interpreter)) => {
+                if config.platform != Platform::Chip8 {
+                    return err("the COSMAC VIP can only run CHIP-8 programs");
+                }
+                if config.layout != MemoryLayout::Separate {
+                    return err("the COSMAC VIP interpreter manages its own memory layout");
+                }
+                Rc::new(VipRunner::new(&monitor, &interpreter)?)
+            }
+            None => Rc::new(OpcodeRunner),
+        };
+        let mut emulator = Emulator::new(config, runner);
+        emulator.clock_speed = clock_speed;
+        emulator.timer_frequency = self.timer_frequency;
+        emulator.timing = self.timing;
+        emulator.rom_database = self.rom_database;
+        emulator.explicit = self.explicit;
+        if let Some(audio) = self.audio {
+            emulator.audio = audio;
+        }
+        Ok(emulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn invalid_combinations() {
+        let megachip_vip_layout = EmulatorBuilder::new()
+            .platform(Platform::MegaChip8)
+            .memory_layout(MemoryLayout::Vip);
+        assert!(megachip_vip_layout.build().is_err());
+        assert!(EmulatorBuilder::new().timer_frequency(0).build().is_err());
+        assert!(EmulatorBuilder::new().clock_speed(0).build().is_err());
+        assert!(EmulatorBuilder::new()
+            .font(Font::VIP, 0x1C0)
+            .build()
+            .is_err());
+        let megachip_vip = EmulatorBuilder::new()
+            .platform(Platform::MegaChip8)
+            .cosmac_vip(&[0; 0x200], &[0; 0x200]);
+        assert!(megachip_vip.build().is_err());
+    }
+
+    #[test]
+    fn seeded_emulators_repeat() {
+        let random_values = || {
+            let mut emulator = EmulatorBuilder::new().rng_seed(7).build().unwrap();
+            emulator.load_program(&[0xC0, 0xFF, 0xC1, 0xFF]).unwrap();
+            emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+            emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+            emulator.view().registers()[0..2].to_vec()
+        };
+        assert_eq!(random_values(), random_values());
+    }
+
+    #[test]
+    fn quirk_setters() {
+        let emulator = EmulatorBuilder::new()
+            .display_wait(true)
+            .shift_quirk(true)
+            .load_store_quirk(true)
+            .jump_quirk(true)
+            .logic_quirk(true)
+            .clip_quirk(true)
+            .build()
+            .unwrap();
+        let all = Quirks {
+            display_wait: true,
+            shift: true,
+            load_store: true,
+            jump: true,
+            logic: true,
+            clip: true,
+        };
+        assert_eq!(emulator.system.quirks, all);
+
+        // Each setter changes only its own quirk, on top of any set before.
+        let emulator = EmulatorBuilder::new()
+            .quirks(all)
+            .jump_quirk(false)
+            .build()
+            .unwrap();
+        assert_eq!(emulator.system.quirks, Quirks { jump: false, ..all });
+    }
+
+    #[test]
+    fn timer_frequency() {
+        let mut emulator = EmulatorBuilder::new()
+            .timer_frequency(100)
+            .instructions_per_frame(10)
+            .build()
+            .unwrap();
+        assert_eq!(emulator.clock_speed(), 1000);
+        emulator.load_program(&[0x12, 0x00]).unwrap();
+        emulator.set_delay_timer(50).unwrap();
+        emulator.execute_cycle(Duration::from_millis(25)).unwrap();
+        assert_eq!(emulator.view().delay_timer(), 48);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::observer::Observer;
+
+/// A subroutine call, from the instruction after `2NNN` to the end of its `00EE`.
+#[derive(Clone, Copy)]
+struct Call {
+    address: u16,
+    return_address: u16,
+    start: u64,
+    end: u64,
+    depth: usize,
+}
+
+/// An observer that records each subroutine call as it happens, so that a program's
+/// structure over time can be viewed with standard tools.
+///
+/// Times are emulated: each instruction is taken to last one cycle at `clock_speed`,
+/// whatever the timing of the emulator.
+pub struct CallGraph {
+    clock_speed: u32,
+    instructions: u64,
+    stack: Vec<Call>,
+    calls: Vec<Call>,
+    frames: Vec<u64>,
+    stacks: HashMap<Vec<u16>, u64>,
+    path: Vec<u16>,
+}
+
+impl CallGraph {
+    pub fn new(clock_speed: u32) -> CallGraph {
+        CallGraph {
+            clock_speed,
+            instructions: 0,
+            stack: Vec::new(),
+            calls: Vec::new(),
+            frames: Vec::new(),
+            stacks: HashMap::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Converts a number of instructions to microseconds.
+    fn micros(&self, instructions: u64) -> f64 {
+        instructions as f64 * 1_000_000.0 / f64::from(self.clock_speed)
+    }
+
+    /// Writes the calls in the Chrome trace event format, which can be opened in
+    /// `chrome://tracing`, Perfetto or Speedscope. Each call is a span, and each frame
+    /// (timer tick) is an instant event. Calls that haven't returned yet end at the
+    /// last instruction executed.
+    pub fn write_trace_events(&self, out: &mut dyn Write) -> io::Result<()> {
+        let open = self.stack.iter().map(|call| Call {
+            end: self.instructions,
+            ..*call
+        });
+        let mut calls = self.calls.clone();
+        calls.extend(open);
+        calls.sort_by_key(|call| (call.start, call.depth));
+
+        writeln!(out, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        write!(
+            out,
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\
+             \"args\":{{\"name\":\"CHIP-8\"}}}}"
+        )?;
+        for call in calls {
+            write!(
+                out,
+                ",\n{{\"name\":\"0x{:03X}\",\"cat\":\"subroutine\",\"ph\":\"X\",\
+                 \"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":1,\
+                 \"args\":{{\"return_address\":\"0x{:03X}\"}}}}",
+                call.address,
+                self.micros(call.start),
+                self.micros(call.end - call.start),
+                call.return_address
+            )?;
+        }
+        for &frame in self.frames.iter() {
+            write!(
+                out,
+                ",\n{{\"name\":\"frame\",\"cat\":\"frame\",\"ph\":\"i\",\"s\":\"g\",\
+                 \"ts\":{:.3},\"pid\":1,\"tid\":1}}",
+                self.micros(frame)
+            )?;
+        }
+        writeln!(out, "\n]}}")
+    }
+
+    /// Writes the number of instructions executed in each call stack, in the folded
+    /// format read by flame graph tools: the subroutines from the outermost, separated
+    /// by semicolons, then a space and the count.
+    pub fn write_folded_stacks(&self, out: &mut dyn Write) -> io::Result<()> {
+        let stacks: BTreeMap<&Vec<u16>, &u64> = self.stacks.iter().collect();
+        for (path, count) in stacks {
+            write!(out, "main")?;
+            for address in path {
+                write!(out, ";0x{:03X}", address)?;
+            }
+            writeln!(out, " {}", count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Observer for CallGraph {
+    fn instruction_executed(&mut self, _pc: u16, _opcode: u16) {
+        self.instructions += 1;
+        match self.stacks.get_mut(self.path.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.path.clone(), 1);
+            }
+        }
+    }
+
+    fn subroutine_called(&mut self, address: u16, return_address: u16) {
+        self.stack.push(Call {
+            address,
+            return_address,
+            start: self.instructions,
+            end: self.instructions,
+            depth: self.stack.len(),
+        });
+        self.path.push(address);
+    }
+
+    fn subroutine_returned(&mut self, _return_address: u16) {
+        if let Some(mut call) = self.stack.pop() {
+            call.end = self.instructions;
+            self.calls.push(call);
+            self.path.pop();
+        }
+    }
+
+    fn timers_ticked(&mut self, _delay_timer: u8, _sound_timer: u8) {
+        self.frames.push(self.instructions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{Emulator, StopCondition};
+
+    fn record(program: &[u8], cycles: u64) -> Rc<RefCell<CallGraph>> {
+        let mut emulator = Emulator::default();
+        emulator.load_program(program).unwrap();
+        let graph = Rc::new(RefCell::new(CallGraph::new(1000)));
+        emulator.add_observer(Box::new(Rc::clone(&graph)));
+        emulator
+            .run_until(&[StopCondition::Cycles(cycles)])
+            .unwrap();
+        graph
+    }
+
+    #[test]
+    fn folded_stacks() {
+        // CALL 0x206; JP 0x204; 0x206: CALL 0x20A; RET; 0x20A: RET
+        let graph = record(
+            &[
+                0x22, 0x06, 0x12, 0x04, 0x12, 0x04, 0x22, 0x0A, 0x00, 0xEE, 0x00, 0xEE,
+            ],
+            7,
+        );
+        let mut folded = Vec::new();
+        graph.borrow().write_folded_stacks(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 4\nmain;0x206 2\nmain;0x206;0x20A 1\n"
+        );
+    }
+
+    #[test]
+    fn trace_events() {
+        // CALL 0x204; JP 0x202; 0x204: RET
+        let graph = record(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE], 2);
+        graph.borrow_mut().timers_ticked(0, 0);
+        graph.borrow_mut().subroutine_called(0x300, 0x204);
+        let mut events = Vec::new();
+        graph.borrow().write_trace_events(&mut events).unwrap();
+        let events = String::from_utf8(events).unwrap();
+        let lines: Vec<&str> = events.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[2],
+            "{\"name\":\"0x204\",\"cat\":\"subroutine\",\"ph\":\"X\",\"ts\":1000.000,\
+             \"dur\":1000.000,\"pid\":1,\"tid\":1,\"args\":{\"return_address\":\"0x202\"}},"
+        );
+        // The call that hasn't returned ends at the last instruction.
+        assert!(lines[3].contains("\"name\":\"0x300\""));
+        assert!(lines[3].contains("\"ts\":2000.000,\"dur\":0.000"));
+        assert!(lines[4].contains("\"ph\":\"i\""));
+        assert_eq!(lines[5], "]}");
+    }
+}
@@ -0,0 +1,368 @@
+use crate::errors::*;
+use crate::json::{parse_color, Json};
+use crate::octo::assemble;
+use crate::platform::Quirks;
+use crate::romdb::Colors;
+
+/// An Octo cartridge: a GIF image with a program's Octo source and the options it
+/// runs with stored in the low two bits of each pixel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cartridge {
+    /// The program's Octo source.
+    pub source: String,
+    /// The assembled program.
+    pub rom: Vec<u8>,
+    /// The number of instructions to run per frame.
+    pub instructions_per_frame: Option<u32>,
+    pub quirks: Quirks,
+    pub colors: Option<Colors>,
+    /// Options the cartridge turns on that this emulator doesn't support, such as
+    /// quirks it doesn't emulate.
+    pub unsupported: Vec<String>,
+}
+
+impl Cartridge {
+    /// Whether a file is a GIF image, and so may be a cartridge.
+    pub fn is_cartridge(data: &[u8]) -> bool {
+        data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+    }
+
+    /// Reads a cartridge and assembles its program.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image isn't a valid GIF, doesn't hold a cartridge, or
+    /// its program doesn't assemble.
+    pub fn from_gif(data: &[u8]) -> Result<Cartridge> {
+        // Each byte is stored in four pixels, most significant bits first, starting
+        // with the length of the payload as a 32-bit big-endian number.
+        let pixels: Vec<u8> = decode_gif(data)?.concat();
+        let bytes: Vec<u8> = pixels
+            .chunks(4)
+            .filter(|chunk| chunk.len() == 4)
+            .map(|chunk| chunk.iter().fold(0, |byte, pixel| byte << 2 | (pixel & 3)))
+            .collect();
+        if bytes.len() < 4 {
+            return err("the image is too small to be a cartridge");
+        }
+        let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let payload = match bytes.get(4..4 + length) {
+            Some(payload) => payload,
+            None => return err("the image doesn't hold a cartridge"),
+        };
+        let payload = match std::str::from_utf8(payload) {
+            Ok(payload) => payload,
+            Err(_) => return err("the cartridge's payload isn't text"),
+        };
+        let json = match Json::parse(payload) {
+            Ok(json) => json,
+            Err(e) => return Err(Error(format!("the cartridge's payload {}", e.0))),
+        };
+        let source = match json.get("program") {
+            Some(Json::String(source)) => source.clone(),
+            _ => return err("the cartridge doesn't hold a program"),
+        };
+        let rom = match assemble(&source) {
+            Ok(assembly) => assembly.rom,
+            Err(e) => return Err(Error(format!("the cartridge's program: {}", e))),
+        };
+
+        let mut cartridge = Cartridge {
+            source,
+            rom,
+            instructions_per_frame: None,
+            quirks: Quirks::default(),
+            colors: None,
+            unsupported: Vec::new(),
+        };
+        let options = match json.get("options") {
+            Some(Json::Object(options)) => options.as_slice(),
+            _ => &[],
+        };
+        let color = |name| match options.iter().find(|(key, _)| key == name) {
+            Some((_, Json::String(color))) => parse_color(color),
+            _ => None,
+        };
+        if let (Some(foreground), Some(background)) = (color("fillColor"), color("backgroundColor"))
+        {
+            cartridge.colors = Some(Colors {
+                foreground,
+                background,
+            });
+        }
+        for (key, value) in options {
+            match (key.as_str(), value) {
+                ("tickrate", Json::Number(rate)) if *rate >= 1.0 => {
+                    cartridge.instructions_per_frame = Some(*rate as u32);
+                }
+                ("vBlankQuirks", Json::Bool(on)) => cartridge.quirks.display_wait = *on,
+                ("shiftQuirks", Json::Bool(on)) => cartridge.quirks.shift = *on,
+                ("loadStoreQuirks", Json::Bool(on)) => cartridge.quirks.load_store = *on,
+                ("jumpQuirks", Json::Bool(on)) => cartridge.quirks.jump = *on,
+                ("logicQuirks", Json::Bool(on)) => cartridge.quirks.logic = *on,
+                ("clipQuirks", Json::Bool(on)) => cartridge.quirks.clip = *on,
+                (_, Json::Bool(true)) if key.ends_with("Quirks") => {
+                    cartridge.unsupported.push(key.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok(cartridge)
+    }
+}
+
+/// Decodes the frames of a GIF image into color indices, a row at a time.
+fn decode_gif(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if !Cartridge::is_cartridge(data) {
+        return err("the file isn't a GIF image");
+    }
+    let truncated = || Error("the GIF image is truncated".to_string());
+    if data.len() < 13 {
+        return Err(truncated());
+    }
+    let mut position = 13;
+    let flags = data[10];
+    if flags & 0x80 != 0 {
+        position += 3 << ((flags & 7) + 1);
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        match data.get(position) {
+            // An extension, which is skipped.
+            Some(0x21) => {
+                position = skip_sub_blocks(data, position + 2).ok_or_else(truncated)?;
+            }
+            Some(0x2C) => {
+                let descriptor = data
+                    .get(position + 1..position + 10)
+                    .ok_or_else(truncated)?;
+                let width = usize::from(u16::from_le_bytes([descriptor[4], descriptor[5]]));
+                let height = usize::from(u16::from_le_bytes([descriptor[6], descriptor[7]]));
+                let flags = descriptor[8];
+                position += 10;
+                if flags & 0x80 != 0 {
+                    position += 3 << ((flags & 7) + 1);
+                }
+                let min_code_size = *data.get(position).ok_or_else(truncated)?;
+                let end = skip_sub_blocks(data, position + 1).ok_or_else(truncated)?;
+                let mut compressed = Vec::new();
+                let mut block = position + 1;
+                while data[block] != 0 {
+                    let len = usize::from(data[block]);
+                    compressed.extend_from_slice(&data[block + 1..block + 1 + len]);
+                    block += len + 1;
+                }
+                position = end;
+
+                let mut pixels = decompress(&compressed, min_code_size)?;
+                pixels.resize(width * height, 0);
+                if flags & 0x40 != 0 {
+                    pixels = deinterlace(&pixels, width, height);
+                }
+                frames.push(pixels);
+            }
+            Some(0x3B) => return Ok(frames),
+            Some(_) => return err("the GIF image is corrupt"),
+            None => return Err(truncated()),
+        }
+    }
+}
+
+/// Returns the position after a series of data sub-blocks.
+fn skip_sub_blocks(data: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let len = usize::from(*data.get(position)?);
+        position += len + 1;
+        if len == 0 {
+            return if position <= data.len() {
+                Some(position)
+            } else {
+                None
+            };
+        }
+    }
+}
+
+/// Decompresses GIF image data, which uses LZW with variable-length codes of up to 12
+/// bits.
+fn decompress(data: &[u8], min_code_size: u8) -> Result<Vec<u8>> {
+    if min_code_size > 11 {
+        return err("the GIF image is corrupt");
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    let initial: Vec<Vec<u8>> = (0..clear + 2).map(|code| vec![code as u8]).collect();
+    let mut table = initial.clone();
+    let mut code_size = u32::from(min_code_size) + 1;
+    let mut previous: Option<usize> = None;
+    let mut output = Vec::new();
+
+    let mut bit = 0;
+    while bit + code_size as usize <= data.len() * 8 {
+        let mut code = 0;
+        for i in 0..code_size as usize {
+            let b = bit + i;
+            code |= usize::from((data[b / 8] >> (b % 8)) & 1) << i;
+        }
+        bit += code_size as usize;
+
+        if code == clear {
+            table = initial.clone();
+            code_size = u32::from(min_code_size) + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let entry = match (table.get(code), previous) {
+            (Some(entry), _) => entry.clone(),
+            (None, Some(previous)) if code == table.len() => {
+                let mut entry = table[previous].clone();
+                entry.push(entry[0]);
+                entry
+            }
+            _ => return err("the GIF image is corrupt"),
+        };
+        output.extend_from_slice(&entry);
+        if let Some(previous) = previous {
+            if table.len() < 4096 {
+                let mut next = table[previous].clone();
+                next.push(entry[0]);
+                table.push(next);
+            }
+        }
+        if table.len() == 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        previous = Some(code);
+    }
+    Ok(output)
+}
+
+/// Puts the rows of an interlaced image in order.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut rows = Vec::new();
+    for &(start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)].iter() {
+        rows.extend((start..height).step_by(step));
+    }
+    let mut ordered = vec![0; pixels.len()];
+    for (source, &row) in rows.iter().enumerate() {
+        ordered[row * width..(row + 1) * width]
+            .copy_from_slice(&pixels[source * width..(source + 1) * width]);
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes pixels with a four color palette as a GIF frame. To keep the encoder
+    /// simple, the code table is cleared after every two codes, so codes stay 3 bits.
+    fn frame(pixels: &[u8], width: u16, height: u16) -> Vec<u8> {
+        let mut codes = Vec::new();
+        for pair in pixels.chunks(2) {
+            codes.push(4);
+            codes.extend(pair.iter().map(|&p| u32::from(p)));
+        }
+        codes.push(5);
+        let mut compressed = Vec::new();
+        for (i, code) in codes.iter().enumerate() {
+            for b in 0..3 {
+                let bit = i * 3 + b;
+                if bit / 8 == compressed.len() {
+                    compressed.push(0);
+                }
+                compressed[bit / 8] |= (((code >> b) & 1) as u8) << (bit % 8);
+            }
+        }
+
+        let mut gif = vec![0x2C, 0, 0, 0, 0];
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        gif.extend_from_slice(&[0, 2]);
+        for block in compressed.chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.push(0);
+        gif
+    }
+
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| vec![byte >> 6, byte >> 4 & 3, byte >> 2 & 3, byte & 3])
+            .collect();
+        // The payload is split across two frames.
+        pixels.resize(2 * 32 * 32, 0);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&[32, 0, 32, 0, 0x81, 0, 0]);
+        gif.extend_from_slice(&[
+            0, 0, 0, 0x55, 0x55, 0x55, 0xAA, 0xAA, 0xAA, 0xFF, 0xFF, 0xFF,
+        ]);
+        // A graphic control extension.
+        gif.extend_from_slice(&[0x21, 0xF9, 4, 0, 10, 0, 0, 0]);
+        gif.extend(frame(&pixels[..1024], 32, 32));
+        gif.extend(frame(&pixels[1024..], 32, 32));
+        gif.push(0x3B);
+        gif
+    }
+
+    #[test]
+    fn options() {
+        let gif = cartridge(
+            r##"{"key": "", "program": ": main\n\tv0 := 1 # \"one\"\n", "options": {
+                "tickrate": 20, "fillColor": "#FFCC00", "backgroundColor": "#996600",
+                "vBlankQuirks": true, "jumpQuirks": true, "shiftQuirks": false,
+                "loadStoreQuirks": true, "logicQuirks": true, "clipQuirks": true,
+                "vfOrderQuirks": true}}"##,
+        );
+        assert!(Cartridge::is_cartridge(&gif));
+        let cartridge = Cartridge::from_gif(&gif).unwrap();
+        assert_eq!(cartridge.source, ": main\n\tv0 := 1 # \"one\"\n");
+        assert_eq!(cartridge.rom, [0x60, 0x01]);
+        assert_eq!(cartridge.instructions_per_frame, Some(20));
+        assert_eq!(
+            cartridge.quirks,
+            Quirks {
+                display_wait: true,
+                shift: false,
+                load_store: true,
+                jump: true,
+                logic: true,
+                clip: true,
+            }
+        );
+        assert_eq!(
+            cartridge.colors,
+            Some(Colors {
+                foreground: 0xFFFF_CC00,
+                background: 0xFF99_6600,
+            })
+        );
+        assert_eq!(cartridge.unsupported, ["vfOrderQuirks"]);
+    }
+
+    #[test]
+    fn invalid_cartridges() {
+        assert!(Cartridge::from_gif(b"GIF89a").is_err());
+        let error = Cartridge::from_gif(&cartridge(r#"{"program": "jump nowhere"}"#));
+        assert_eq!(
+            error.unwrap_err().0,
+            "the cartridge's program: line 1: `nowhere` is never defined"
+        );
+        assert!(Cartridge::from_gif(&cartridge(r#"{"program": 1"#)).is_err());
+    }
+
+    #[test]
+    fn lzw() {
+        // A repeated pixel, which uses a code before it is in the table.
+        assert_eq!(decompress(&[0x8C, 0x5F], 2).unwrap(), [1, 1, 1, 1, 1, 1],);
+    }
+}
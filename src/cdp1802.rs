@@ -0,0 +1,473 @@
+/// Memory and I/O as seen by a CDP1802.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    /// Handles an `OUT n` instruction, for n in 1 to 7.
+    fn output(&mut self, port: u8, value: u8);
+    /// Handles an `INP n` instruction, for n in 1 to 7.
+    fn input(&mut self, port: u8) -> u8;
+    /// Reads external flag EF1 to EF4, numbered 1 to 4.
+    fn flag(&self, n: u8) -> bool;
+}
+
+/// The RCA CDP1802 CPU used in the COSMAC VIP.
+///
+/// Timing is measured in machine cycles of 8 clock periods each. Most instructions
+/// take two machine cycles, and long branches and skips take three.
+#[derive(Clone, Debug)]
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub p: u8,
+    pub x: u8,
+    pub d: u8,
+    pub df: bool,
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Cdp1802 {
+        Cdp1802::new()
+    }
+}
+
+impl Cdp1802 {
+    /// Creates a CPU in the state it is in after a reset.
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.r[usize::from(self.p)]
+    }
+
+    /// Services an interrupt request, returning the number of machine cycles used.
+    /// Does nothing if interrupts are disabled.
+    pub fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+        self.t = (self.x << 4) | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    /// Performs a DMA output cycle, reading the byte at R0 and incrementing R0.
+    pub fn dma_out<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /// Executes a single instruction, returning the number of machine cycles used.
+    /// While idle, a single cycle passes without executing anything.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let opcode = self.fetch(bus);
+        let (i, n) = (opcode >> 4, opcode & 0xF);
+        let rn = usize::from(n);
+        match i {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = bus.read(self.r[rn]),
+            0x1 => self.r[rn] = self.r[rn].wrapping_add(1),
+            0x2 => self.r[rn] = self.r[rn].wrapping_sub(1),
+            0x3 => {
+                let taken = self.condition(bus, n);
+                self.short_branch(bus, taken);
+            }
+            0x4 => {
+                self.d = bus.read(self.r[rn]);
+                self.r[rn] = self.r[rn].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[rn], self.d),
+            0x6 => self.io(bus, n),
+            0x7 => self.misc(bus, n),
+            0x8 => self.d = self.r[rn] as u8,
+            0x9 => self.d = (self.r[rn] >> 8) as u8,
+            0xA => self.r[rn] = (self.r[rn] & 0xFF00) | u16::from(self.d),
+            0xB => self.r[rn] = (self.r[rn] & 0x00FF) | (u16::from(self.d) << 8),
+            0xC => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => self.alu(bus, n),
+        }
+        2
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let p = usize::from(self.p);
+        let value = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    /// Evaluates the condition of a short branch. Bit 3 of n inverts the condition.
+    fn condition<B: Bus>(&self, bus: &B, n: u8) -> bool {
+        let result = match n & 0x7 {
+            0x0 => true,
+            0x1 => self.q,
+            0x2 => self.d == 0,
+            0x3 => self.df,
+            f => bus.flag(f - 3),
+        };
+        result != (n & 0x8 != 0)
+    }
+
+    fn short_branch<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let p = usize::from(self.p);
+        if taken {
+            let target = bus.read(self.r[p]);
+            self.r[p] = (self.r[p] & 0xFF00) | u16::from(target);
+        } else {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    fn long_branch<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        let p = usize::from(self.p);
+        // C4 is NOP, and C8 is an unconditional skip (LSKP). The remaining
+        // instructions with bit 2 set are skips rather than branches.
+        let (skip, condition) = match n {
+            0x4 => return,
+            0x0 => (false, true),
+            0x1 => (false, self.q),
+            0x2 => (false, self.d == 0),
+            0x3 => (false, self.df),
+            0x5 => (true, !self.q),
+            0x6 => (true, self.d != 0),
+            0x7 => (true, !self.df),
+            0x8 => (true, true),
+            0x9 => (false, !self.q),
+            0xA => (false, self.d != 0),
+            0xB => (false, !self.df),
+            0xC => (true, self.ie),
+            0xD => (true, self.q),
+            0xE => (true, self.d == 0),
+            _ => (true, self.df),
+        };
+        if skip {
+            if condition {
+                self.r[p] = self.r[p].wrapping_add(2);
+            }
+        } else if condition {
+            let hi = bus.read(self.r[p]);
+            let lo = bus.read(self.r[p].wrapping_add(1));
+            self.r[p] = (u16::from(hi) << 8) | u16::from(lo);
+        } else {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    fn io<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        let x = usize::from(self.x);
+        match n {
+            0x0 => self.r[x] = self.r[x].wrapping_add(1),
+            0x1..=0x7 => {
+                let value = bus.read(self.r[x]);
+                bus.output(n, value);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            0x8 => {} // undefined on the 1802
+            _ => {
+                let value = bus.input(n - 8);
+                bus.write(self.r[x], value);
+                self.d = value;
+            }
+        }
+    }
+
+    fn misc<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        let x = usize::from(self.x);
+        match n {
+            0x0 | 0x1 => {
+                let value = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0x0;
+            }
+            0x2 => {
+                self.d = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            0x3 => {
+                bus.write(self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            0x4 => {
+                let m = bus.read(self.r[x]);
+                self.add(m, self.df);
+            }
+            0x5 => {
+                let m = bus.read(self.r[x]);
+                self.subtract(m, self.d, self.df);
+            }
+            0x6 => {
+                let carry = self.d & 0x01 != 0;
+                self.d = (self.d >> 1) | if self.df { 0x80 } else { 0 };
+                self.df = carry;
+            }
+            0x7 => {
+                let m = bus.read(self.r[x]);
+                self.subtract(self.d, m, self.df);
+            }
+            0x8 => bus.write(self.r[x], self.t),
+            0x9 => {
+                self.t = (self.x << 4) | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false,
+            0xB => self.q = true,
+            0xC => {
+                let m = self.fetch(bus);
+                self.add(m, self.df);
+            }
+            0xD => {
+                let m = self.fetch(bus);
+                self.subtract(m, self.d, self.df);
+            }
+            0xE => {
+                let carry = self.d & 0x80 != 0;
+                self.d = (self.d << 1) | if self.df { 0x01 } else { 0 };
+                self.df = carry;
+            }
+            _ => {
+                let m = self.fetch(bus);
+                self.subtract(self.d, m, self.df);
+            }
+        }
+    }
+
+    fn alu<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        match n {
+            0x6 => {
+                self.df = self.d & 0x01 != 0;
+                self.d >>= 1;
+            }
+            0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            _ => {
+                // F0-F7 operate on M(R(X)), and F8-FF on the immediate byte that follows.
+                let m = if n < 0x8 {
+                    bus.read(self.r[usize::from(self.x)])
+                } else {
+                    self.fetch(bus)
+                };
+                match n & 0x7 {
+                    0x0 => self.d = m,
+                    0x1 => self.d |= m,
+                    0x2 => self.d &= m,
+                    0x3 => self.d ^= m,
+                    0x4 => self.add(m, false),
+                    0x5 => self.subtract(m, self.d, true),
+                    _ => self.subtract(self.d, m, true),
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, m: u8, carry: bool) {
+        let result = u16::from(self.d) + u16::from(m) + u16::from(carry);
+        self.d = result as u8;
+        self.df = result > 0xFF;
+    }
+
+    /// Computes a - b, where DF is set when there is no borrow. A clear `no_borrow`
+    /// subtracts an additional one.
+    fn subtract(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let result = i16::from(a) - i16::from(b) - if no_borrow { 0 } else { 1 };
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBus {
+        memory: [u8; 256],
+        flags: [bool; 4],
+        output: Vec<(u8, u8)>,
+    }
+
+    impl TestBus {
+        fn with_program(program: &[u8]) -> TestBus {
+            let mut memory = [0; 256];
+            memory[..program.len()].copy_from_slice(program);
+            TestBus {
+                memory,
+                flags: [false; 4],
+                output: vec![],
+            }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[usize::from(addr & 0xFF)]
+        }
+        fn write(&mut self, addr: u16, value: u8) {
+            self.memory[usize::from(addr & 0xFF)] = value;
+        }
+        fn output(&mut self, port: u8, value: u8) {
+            self.output.push((port, value));
+        }
+        fn input(&mut self, port: u8) -> u8 {
+            0x10 + port
+        }
+        fn flag(&self, n: u8) -> bool {
+            self.flags[usize::from(n - 1)]
+        }
+    }
+
+    fn run(cpu: &mut Cdp1802, bus: &mut TestBus, instructions: usize) -> u32 {
+        (0..instructions).map(|_| cpu.step(bus)).sum()
+    }
+
+    #[test]
+    fn load_and_store() {
+        // LDI 42; PLO 3; GLO 3; PHI 4; LDI 80; PLO 5; GHI 4; STR 5
+        let mut bus =
+            TestBus::with_program(&[0xF8, 0x42, 0xA3, 0x83, 0xB4, 0xF8, 0x80, 0xA5, 0x94, 0x55]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(run(&mut cpu, &mut bus, 8), 16);
+        assert_eq!(cpu.r[3], 0x0042);
+        assert_eq!(cpu.r[4], 0x4200);
+        assert_eq!(bus.memory[0x80], 0x42);
+    }
+
+    #[test]
+    fn arithmetic() {
+        // LDI F0; ADI 20 => D = 10, DF = 1
+        let mut bus = TestBus::with_program(&[0xF8, 0xF0, 0xFC, 0x20, 0xFF, 0x11, 0x7F, 0x00]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // SMI 11 => D = FF, DF = 0 (borrow)
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0xFF, false));
+        // SMBI 00 with borrow => D = FE, DF = 1
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0xFE, true));
+    }
+
+    #[test]
+    fn shifts() {
+        // LDI 81; SHR; SHRC; SHL; SHLC
+        let mut bus = TestBus::with_program(&[0xF8, 0x81, 0xF6, 0x76, 0xFE, 0x7E]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0xA0, false));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn branches() {
+        // LDI 00; BZ 06; LDI FF; B1 0A; LBR 0020
+        let mut bus = TestBus::with_program(&[
+            0xF8, 0x00, 0x32, 0x06, 0xF8, 0xFF, 0x34, 0x0A, 0xC0, 0x00, 0x20,
+        ]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.pc(), 0x06);
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.pc(), 0x08);
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.pc(), 0x20);
+
+        bus.flags[0] = true;
+        cpu.r[0] = 0x06;
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.pc(), 0x0A);
+    }
+
+    #[test]
+    fn long_skips() {
+        // LSKP; (skipped 2 bytes); LSNZ with D = 0
+        let mut bus = TestBus::with_program(&[0xC8, 0xFF, 0xFF, 0xC6, 0x00]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.pc(), 0x03);
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.pc(), 0x04);
+    }
+
+    #[test]
+    fn io() {
+        // SEX 2; OUT 2; INP 3
+        let mut bus = TestBus::with_program(&[0xE2, 0x62, 0x6B]);
+        bus.memory[0x40] = 0x07;
+        let mut cpu = Cdp1802::new();
+        cpu.r[2] = 0x40;
+        run(&mut cpu, &mut bus, 3);
+        assert_eq!(bus.output, vec![(2, 0x07)]);
+        assert_eq!(cpu.r[2], 0x41);
+        assert_eq!(bus.memory[0x41], 0x13);
+        assert_eq!(cpu.d, 0x13);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut bus = TestBus::with_program(&[0xC4, 0xC4]);
+        bus.memory[0x60] = 0x70; // RET
+        let mut cpu = Cdp1802::new();
+        cpu.r[1] = 0x60;
+        cpu.r[2] = 0x80;
+        cpu.x = 3;
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!((cpu.p, cpu.x, cpu.t, cpu.ie), (1, 2, 0x30, false));
+        assert_eq!(cpu.interrupt(), 0, "interrupts are disabled");
+
+        // SAV; then RET restores X and P from the stack.
+        bus.memory[0x60] = 0x78;
+        bus.memory[0x61] = 0x70;
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.p, cpu.x, cpu.ie), (0, 3, true));
+        assert_eq!(cpu.r[2], 0x81);
+    }
+
+    #[test]
+    fn idle_until_dma() {
+        let mut bus = TestBus::with_program(&[0x00]);
+        bus.memory[0x10] = 0xAB;
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 1);
+        assert!(cpu.idle);
+        assert_eq!(cpu.step(&mut bus), 1);
+        cpu.r[0] = 0x10;
+        assert_eq!(cpu.dma_out(&mut bus), 0xAB);
+        assert!(!cpu.idle);
+    }
+}
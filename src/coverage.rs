@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::observer::Observer;
+use crate::system::PROGRAM_START;
+
+/// A way a byte of memory was used while a program ran.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteUse {
+    /// Executed as part of an instruction.
+    Code,
+    /// Read as sprite data by `DXYN`.
+    Sprite,
+    /// Read as data by another instruction, such as `FX65`.
+    Read,
+    /// Written by an instruction, such as `FX33` or `FX55`.
+    Written,
+}
+
+impl ByteUse {
+    pub const ALL: [ByteUse; 4] = [
+        ByteUse::Code,
+        ByteUse::Sprite,
+        ByteUse::Read,
+        ByteUse::Written,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ByteUse::Code => "code",
+            ByteUse::Sprite => "sprite",
+            ByteUse::Read => "read",
+            ByteUse::Written => "written",
+        }
+    }
+
+    /// The character marking the use in hex dumps.
+    fn symbol(self) -> char {
+        match self {
+            ByteUse::Code => 'C',
+            ByteUse::Sprite => 'S',
+            ByteUse::Read => 'R',
+            ByteUse::Written => 'W',
+        }
+    }
+}
+
+impl fmt::Display for ByteUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An observer that records how each byte of memory was used, to tell code from
+/// data and to find the parts of a program that were never reached.
+#[derive(Default)]
+pub struct Coverage {
+    uses: BTreeMap<usize, u8>,
+    opcode: u16,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    fn mark(&mut self, address: usize, byte_use: ByteUse) {
+        *self.uses.entry(address).or_insert(0) |= byte_use.bit();
+    }
+
+    /// Whether a byte was used in a way.
+    pub fn is(&self, address: usize, byte_use: ByteUse) -> bool {
+        self.uses.get(&address).cloned().unwrap_or(0) & byte_use.bit() != 0
+    }
+
+    /// The ways a byte was used, which is empty if it was never touched.
+    pub fn uses(&self, address: usize) -> Vec<ByteUse> {
+        let uses = self.uses.get(&address).cloned().unwrap_or(0);
+        ByteUse::ALL
+            .iter()
+            .cloned()
+            .filter(|byte_use| uses & byte_use.bit() != 0)
+            .collect()
+    }
+
+    /// Writes the coverage as JSON. The bytes of a program `program_len` bytes long,
+    /// and any other bytes that were used, are grouped into ranges of bytes used in the
+    /// same ways, each with its first and last address. Bytes of the program that were
+    /// never touched have no uses.
+    pub fn write_json(&self, out: &mut dyn Write, program_len: usize) -> io::Result<()> {
+        let program = PROGRAM_START..PROGRAM_START + program_len;
+        let mut addresses: Vec<usize> = program.clone().collect();
+        addresses.extend(self.uses.keys().filter(|addr| !program.contains(addr)));
+        addresses.sort_unstable();
+
+        let mut counts = [0; 4];
+        let mut untouched = 0;
+        for addr in program.clone() {
+            let uses = self.uses(addr);
+            if uses.is_empty() {
+                untouched += 1;
+            }
+            for byte_use in uses {
+                counts[byte_use as usize] += 1;
+            }
+        }
+
+        writeln!(out, "{{")?;
+        writeln!(
+            out,
+            "  \"program\": {{\"start\": {}, \"end\": {}}},",
+            program.start,
+            program.end.max(program.start + 1) - 1
+        )?;
+        write!(out, "  \"program_bytes\": {{")?;
+        for byte_use in ByteUse::ALL.iter() {
+            write!(out, "\"{}\": {}, ", byte_use, counts[*byte_use as usize])?;
+        }
+        writeln!(out, "\"untouched\": {}}},", untouched)?;
+        write!(out, "  \"ranges\": [")?;
+        let mut first = true;
+        let mut i = 0;
+        while i < addresses.len() {
+            let start = addresses[i];
+            let uses = self.uses.get(&start).cloned().unwrap_or(0);
+            let mut end = start;
+            while i + 1 < addresses.len()
+                && addresses[i + 1] == end + 1
+                && self.uses.get(&(end + 1)).cloned().unwrap_or(0) == uses
+            {
+                end += 1;
+                i += 1;
+            }
+            i += 1;
+
+            let names: Vec<String> = self
+                .uses(start)
+                .iter()
+                .map(|byte_use| format!("\"{}\"", byte_use))
+                .collect();
+            write!(
+                out,
+                "{}\n    {{\"start\": {}, \"end\": {}, \"uses\": [{}]}}",
+                if first { "" } else { "," },
+                start,
+                end,
+                names.join(", ")
+            )?;
+            first = false;
+        }
+        writeln!(out, "\n  ]\n}}")
+    }
+
+    /// Writes a hex dump of a program, 16 bytes to a line, with a character for each
+    /// byte showing how it was used.
+    pub fn write_hex_dump(&self, out: &mut dyn Write, program: &[u8]) -> io::Result<()> {
+        writeln!(
+            out,
+            "; C code, S sprite, R read, W written, + more than one, . untouched"
+        )?;
+        for (row, bytes) in program.chunks(16).enumerate() {
+            let start = PROGRAM_START + row * 16;
+            let mut hex = String::new();
+            let mut marks = String::new();
+            for (offset, byte) in bytes.iter().enumerate() {
+                hex.push_str(&format!("{:02X} ", byte));
+                marks.push(match self.uses(start + offset).as_slice() {
+                    [] => '.',
+                    [byte_use] => byte_use.symbol(),
+                    _ => '+',
+                });
+            }
+            writeln!(out, "{:04X}  {:<48} {}", start, hex, marks)?;
+        }
+        Ok(())
+    }
+}
+
+impl Observer for Coverage {
+    fn instruction_executed(&mut self, pc: u16, opcode: u16) {
+        self.opcode = opcode;
+        self.mark(usize::from(pc), ByteUse::Code);
+        self.mark(usize::from(pc) + 1, ByteUse::Code);
+    }
+
+    fn memory_read(&mut self, address: usize, _value: u8) {
+        let byte_use = if self.opcode & 0xF000 == 0xD000 {
+            ByteUse::Sprite
+        } else {
+            ByteUse::Read
+        };
+        self.mark(address, byte_use);
+    }
+
+    fn memory_written(&mut self, address: usize, _value: u8) {
+        self.mark(address, ByteUse::Written);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{Emulator, StopCondition};
+
+    // LD I, 0x20C; DRW V0, V0, 1; LD B, V0; LD V1, [I]; JP 0x208; 0x20C: sprite, unused
+    const PROGRAM: [u8; 14] = [
+        0xA2, 0x0C, 0xD0, 0x01, 0xF0, 0x33, 0xF1, 0x65, 0x12, 0x08, 0x00, 0x00, 0x80, 0xFF,
+    ];
+
+    fn record() -> Coverage {
+        let mut emulator = Emulator::default();
+        emulator.load_program(&PROGRAM).unwrap();
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        emulator.add_observer(Box::new(Rc::clone(&coverage)));
+        emulator.run_until(&[StopCondition::Cycles(6)]).unwrap();
+        drop(emulator);
+        Rc::try_unwrap(coverage).ok().unwrap().into_inner()
+    }
+
+    #[test]
+    fn byte_uses() {
+        let coverage = record();
+        assert_eq!(coverage.uses(0x200), [ByteUse::Code]);
+        assert_eq!(coverage.uses(0x209), [ByteUse::Code]);
+        assert_eq!(coverage.uses(0x20A), []);
+        assert_eq!(
+            coverage.uses(0x20C),
+            [ByteUse::Sprite, ByteUse::Read, ByteUse::Written]
+        );
+        assert_eq!(coverage.uses(0x20D), [ByteUse::Read, ByteUse::Written]);
+        assert_eq!(coverage.uses(0x20E), [ByteUse::Written]);
+        assert!(coverage.is(0x206, ByteUse::Code));
+        assert!(!coverage.is(0x206, ByteUse::Sprite));
+
+        let mut dump = Vec::new();
+        coverage.write_hex_dump(&mut dump, &PROGRAM).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(
+            dump.lines().nth(1).unwrap(),
+            "0200  A2 0C D0 01 F0 33 F1 65 12 08 00 00 80 FF        CCCCCCCCCC..++"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let coverage = record();
+        let mut json = Vec::new();
+        coverage.write_json(&mut json, PROGRAM.len()).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(
+            "\"program_bytes\": {\"code\": 10, \"sprite\": 1, \"read\": 2, \"written\": 2, \
+             \"untouched\": 2},"
+        ));
+        assert!(json.contains("{\"start\": 512, \"end\": 521, \"uses\": [\"code\"]},"));
+        assert!(json.contains("{\"start\": 522, \"end\": 523, \"uses\": []},"));
+        assert!(json.contains("{\"start\": 526, \"end\": 526, \"uses\": [\"written\"]}\n"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use crate::Emulator;
+
+/// Pauses a program at the breakpoints set in its Octo source with `:breakpoint`,
+/// until the host resumes it.
+#[derive(Default)]
+pub struct SourceDebugger {
+    /// The names of the breakpoints, by address.
+    breakpoints: BTreeMap<u16, String>,
+    paused: bool,
+}
+
+impl SourceDebugger {
+    /// Sets the breakpoints on an emulator. `breakpoints` holds their names by
+    /// address, as in `Assembly::breakpoints`.
+    pub fn new(emulator: &mut Emulator, breakpoints: BTreeMap<u16, String>) -> SourceDebugger {
+        for &addr in breakpoints.keys() {
+            emulator.add_breakpoint(addr);
+        }
+        SourceDebugger {
+            breakpoints,
+            paused: false,
+        }
+    }
+
+    /// Whether the program is stopped at a breakpoint.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops the program, after the emulator reached a breakpoint. Returns a
+    /// description of the breakpoint and the machine state to show the user.
+    pub fn pause(&mut self, emulator: &Emulator) -> String {
+        self.paused = true;
+        let pc = emulator.view().program_counter();
+        let breakpoint = match self.breakpoints.get(&pc) {
+            Some(name) => format!("breakpoint {} at 0x{:03X}", name, pc),
+            None => format!("breakpoint at 0x{:03X}", pc),
+        };
+        format!(
+            "{}\n{}\npress Enter to continue",
+            breakpoint,
+            emulator.view()
+        )
+    }
+
+    /// Lets the program continue from the breakpoint it stopped at.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, ExecutionStatus, StopCondition};
+    use std::time::Duration;
+
+    #[test]
+    fn pauses_at_source_breakpoints() {
+        let assembly = assemble(": main v0 := 1 :breakpoint set v1 := 2 jump main").unwrap();
+        let mut emulator = Emulator::default();
+        let mut debugger = SourceDebugger::new(&mut emulator, assembly.breakpoints);
+        emulator.load_program(&assembly.rom).unwrap();
+
+        let stop = emulator.run_until(&[StopCondition::Breakpoint]).unwrap();
+        assert_eq!(stop, StopCondition::Breakpoint);
+        assert_eq!(emulator.view().program_counter(), 0x202);
+        assert!(!debugger.is_paused());
+        let message = debugger.pause(&emulator);
+        assert!(message.starts_with("breakpoint set at 0x202\n"));
+        assert!(message.ends_with("\npress Enter to continue"));
+        assert!(debugger.is_paused());
+
+        // Resuming runs the instruction at the breakpoint, and stops there again on
+        // the next time around the loop.
+        debugger.resume();
+        assert!(!debugger.is_paused());
+        let status = emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Executed);
+        assert_eq!(emulator.view().registers()[1], 2);
+        let stop = emulator.run_until(&[StopCondition::Breakpoint]).unwrap();
+        assert_eq!(stop, StopCondition::Breakpoint);
+        assert_eq!(emulator.view().program_counter(), 0x202);
+    }
+}
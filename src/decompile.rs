@@ -0,0 +1,713 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::disasm;
+use crate::flow::ControlFlowGraph;
+use crate::system::PROGRAM_START;
+
+/// The language a program is decompiled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// C-like pseudo-code, meant for reading.
+    PseudoCode,
+    /// Octo's high-level assembly language, which assembles back to the same program.
+    Octo,
+}
+
+/// A byte of the program, in order of address. Bytes no path reaches are data.
+enum Item {
+    Code { addr: u16, opcode: u16, len: u16 },
+    Data { addr: u16, byte: u8 },
+}
+
+/// A statement of the decompiled program. Control flow that can't be expressed as an
+/// `if` or a loop is left as jumps to labels.
+enum Stmt {
+    Op {
+        addr: u16,
+        opcode: u16,
+        bytes: Vec<u8>,
+    },
+    /// A skip followed by a single statement, run unless the skip is taken.
+    IfThen {
+        addr: u16,
+        skip: u16,
+        body: Box<Stmt>,
+    },
+    /// A skip over a jump past the `then` block, which is run if the skip is taken. An
+    /// `else` block is jumped over at the end of the `then` block.
+    If {
+        addr: u16,
+        skip: u16,
+        then: Vec<Stmt>,
+        otherwise: Option<Vec<Stmt>>,
+    },
+    /// Statements ending in a jump back to the first.
+    Loop {
+        addr: u16,
+        body: Vec<Stmt>,
+    },
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Stmt {
+    fn addr(&self) -> u16 {
+        match *self {
+            Stmt::Op { addr, .. }
+            | Stmt::IfThen { addr, .. }
+            | Stmt::If { addr, .. }
+            | Stmt::Loop { addr, .. }
+            | Stmt::Data { addr, .. } => addr,
+        }
+    }
+}
+
+fn is_skip(opcode: u16) -> bool {
+    match opcode >> 12 {
+        0x3 | 0x4 => true,
+        0x5 | 0x9 => opcode & 0xF == 0,
+        0xE => opcode & 0xFF == 0x9E || opcode & 0xFF == 0xA1,
+        _ => false,
+    }
+}
+
+fn jump_target(item: &Item) -> Option<u16> {
+    match *item {
+        Item::Code { opcode, .. } if opcode >> 12 == 0x1 => Some(opcode & 0xFFF),
+        _ => None,
+    }
+}
+
+fn item_addr(item: &Item) -> u16 {
+    match *item {
+        Item::Code { addr, .. } | Item::Data { addr, .. } => addr,
+    }
+}
+
+/// Decompiles a program loaded at 0x200. Code is found by following the program's
+/// control flow. Skips over single instructions become `if` statements, skips over
+/// jumps become `if` blocks with an optional `else`, jumps back become loops, and call
+/// targets become subroutines. Everything else is kept as it is, so the Octo output
+/// assembles to the original program. MegaChip8 opcodes are only recognized if
+/// `megachip` is set.
+pub fn decompile(program: &[u8], megachip: bool, syntax: Syntax) -> String {
+    let graph = ControlFlowGraph::analyze(program, megachip);
+    let code: BTreeMap<u16, u16> = graph
+        .blocks()
+        .iter()
+        .flat_map(|block| block.instructions.iter().cloned())
+        .collect();
+
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < program.len() {
+        let addr = (PROGRAM_START + offset) as u16;
+        match code.get(&addr) {
+            Some(&opcode) => {
+                let mut len = 2;
+                if megachip && opcode & 0xFF00 == 0x0100 && offset + 4 <= program.len() {
+                    len = 4;
+                }
+                items.push(Item::Code { addr, opcode, len });
+                offset += usize::from(len);
+            }
+            None => {
+                items.push(Item::Data {
+                    addr,
+                    byte: program[offset],
+                });
+                offset += 1;
+            }
+        }
+    }
+
+    let stmts = structure(&items, program, 0, items.len());
+    let end = (PROGRAM_START + program.len()) as u16;
+    let mut writer = Writer::new(syntax, megachip, &items, &stmts);
+    writer.write_block(&stmts, 0);
+    writer.write_labels_at(end, 0);
+    writer.output
+}
+
+/// The address after the item at an index, or the end of the program.
+fn addr_of(items: &[Item], index: usize, program: &[u8]) -> u16 {
+    match items.get(index) {
+        Some(item) => item_addr(item),
+        None => (PROGRAM_START + program.len()) as u16,
+    }
+}
+
+/// Finds the index of the item at an address, between two indices inclusive.
+fn index_of(items: &[Item], program: &[u8], addr: u16, from: usize, to: usize) -> Option<usize> {
+    (from..=to).find(|&index| addr_of(items, index, program) == addr)
+}
+
+/// Recovers the structure of the items between two indices.
+fn structure(items: &[Item], program: &[u8], start: usize, end: usize) -> Vec<Stmt> {
+    let mut stmts = Vec::new();
+    let mut k = start;
+    while k < end {
+        let (addr, opcode) = match items[k] {
+            Item::Data { addr, .. } => {
+                let mut bytes = Vec::new();
+                while k < end {
+                    match items[k] {
+                        Item::Data { byte, .. } => bytes.push(byte),
+                        Item::Code { .. } => break,
+                    }
+                    k += 1;
+                }
+                stmts.push(Stmt::Data { addr, bytes });
+                continue;
+            }
+            Item::Code { addr, opcode, .. } => (addr, opcode),
+        };
+
+        // The last jump back to this instruction closes a loop.
+        if let Some(again) = (k..end)
+            .rev()
+            .find(|&q| jump_target(&items[q]) == Some(addr))
+        {
+            stmts.push(Stmt::Loop {
+                addr,
+                body: structure(items, program, k, again),
+            });
+            k = again + 1;
+            continue;
+        }
+
+        if is_skip(opcode) && k + 1 < end {
+            if let Item::Code {
+                opcode: next, len, ..
+            } = items[k + 1]
+            {
+                // A skip over a forward jump starts an `if` block.
+                let target = jump_target(&items[k + 1]);
+                let block_end = target
+                    .filter(|&target| target > addr + 2)
+                    .and_then(|target| index_of(items, program, target, k + 2, end));
+                if let Some(m) = block_end {
+                    let else_end = if m > k + 2 {
+                        jump_target(&items[m - 1])
+                            .filter(|&target| target > addr_of(items, m, program))
+                            .and_then(|target| index_of(items, program, target, m, end))
+                    } else {
+                        None
+                    };
+                    match else_end {
+                        Some(e) => {
+                            stmts.push(Stmt::If {
+                                addr,
+                                skip: opcode,
+                                then: structure(items, program, k + 2, m - 1),
+                                otherwise: Some(structure(items, program, m, e)),
+                            });
+                            k = e;
+                        }
+                        None => {
+                            stmts.push(Stmt::If {
+                                addr,
+                                skip: opcode,
+                                then: structure(items, program, k + 2, m),
+                                otherwise: None,
+                            });
+                            k = m;
+                        }
+                    }
+                    continue;
+                }
+
+                // Skips only cover a single instruction, so a skip over another skip
+                // isn't nested.
+                if !is_skip(next) && len == 2 {
+                    stmts.push(Stmt::IfThen {
+                        addr,
+                        skip: opcode,
+                        body: Box::new(op(&items[k + 1], program)),
+                    });
+                    k += 2;
+                    continue;
+                }
+            }
+        }
+
+        stmts.push(op(&items[k], program));
+        k += 1;
+    }
+    stmts
+}
+
+fn op(item: &Item, program: &[u8]) -> Stmt {
+    match *item {
+        Item::Code { addr, opcode, len } => {
+            let start = usize::from(addr) - PROGRAM_START;
+            Stmt::Op {
+                addr,
+                opcode,
+                bytes: program[start..start + usize::from(len)].to_vec(),
+            }
+        }
+        Item::Data { .. } => unreachable!(),
+    }
+}
+
+/// Writes statements in one of the syntaxes, with labels for the addresses that are
+/// referred to.
+struct Writer {
+    syntax: Syntax,
+    megachip: bool,
+    labels: BTreeMap<u16, String>,
+    written: BTreeSet<u16>,
+    output: String,
+}
+
+impl Writer {
+    fn new(syntax: Syntax, megachip: bool, items: &[Item], stmts: &[Stmt]) -> Writer {
+        let mut references = Vec::new();
+        collect_references(stmts, &mut references);
+        let code: BTreeSet<u16> = items
+            .iter()
+            .filter_map(|item| match *item {
+                Item::Code { addr, .. } => Some(addr),
+                Item::Data { .. } => None,
+            })
+            .collect();
+        // Labels can only be written where a statement or a byte of data starts.
+        let mut starts = BTreeSet::new();
+        collect_starts(stmts, &mut starts);
+        if let Some(item) = items.last() {
+            let end = match *item {
+                Item::Code { addr, len, .. } => addr + len,
+                Item::Data { addr, .. } => addr + 1,
+            };
+            starts.insert(end);
+        }
+
+        let mut labels = BTreeMap::new();
+        labels.insert(PROGRAM_START as u16, "main".to_string());
+        // Subroutines are named before other labels, so they keep their names.
+        references.sort_by_key(|&(_, opcode)| opcode >> 12 != 0x2);
+        for (target, opcode) in references {
+            if !starts.contains(&target) || labels.contains_key(&target) {
+                continue;
+            }
+            let prefix = match opcode >> 12 {
+                0x2 => "sub",
+                0xA if !code.contains(&target) => "data",
+                0xB => "table",
+                _ => "label",
+            };
+            labels.insert(target, format!("{}_{:03X}", prefix, target));
+        }
+        Writer {
+            syntax,
+            megachip,
+            labels,
+            written: BTreeSet::new(),
+            output: String::new(),
+        }
+    }
+
+    /// The label for an address, or the address itself if it has none.
+    fn target(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        for _ in 0..=depth {
+            self.output.push_str("    ");
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn comment(&self) -> &'static str {
+        match self.syntax {
+            Syntax::PseudoCode => "//",
+            Syntax::Octo => "#",
+        }
+    }
+
+    fn write_labels_at(&mut self, addr: u16, depth: usize) {
+        if self.written.contains(&addr) {
+            return;
+        }
+        if let Some(label) = self.labels.get(&addr).cloned() {
+            self.written.insert(addr);
+            if label.starts_with("sub_") && !self.output.is_empty() {
+                self.output.push('\n');
+            }
+            let line = match self.syntax {
+                Syntax::PseudoCode => format!("{}:", label),
+                Syntax::Octo => format!(": {}", label),
+            };
+            // Labels are outdented from the statements they name.
+            for _ in 0..depth {
+                self.output.push_str("    ");
+            }
+            self.output.push_str(&line);
+            self.output.push('\n');
+        }
+    }
+
+    fn write_block(&mut self, stmts: &[Stmt], depth: usize) {
+        for stmt in stmts {
+            self.write_stmt(stmt, depth);
+        }
+    }
+
+    fn write_stmt(&mut self, stmt: &Stmt, depth: usize) {
+        if let Stmt::Data { .. } = stmt {
+        } else {
+            self.write_labels_at(stmt.addr(), depth);
+        }
+        match stmt {
+            Stmt::Op { opcode, bytes, .. } => {
+                let text = self.instruction(*opcode, bytes);
+                self.line(depth, &text);
+            }
+            Stmt::IfThen { skip, body, .. } => {
+                let (opcode, bytes) = match **body {
+                    Stmt::Op {
+                        opcode, ref bytes, ..
+                    } => (opcode, bytes),
+                    _ => unreachable!(),
+                };
+                let body = self.instruction(opcode, bytes);
+                let text = match self.syntax {
+                    Syntax::PseudoCode => format!("if ({}) {}", self.condition(*skip, false), body),
+                    Syntax::Octo => format!("if {} then {}", self.condition(*skip, false), body),
+                };
+                self.line(depth, &text);
+            }
+            Stmt::If {
+                skip,
+                then,
+                otherwise,
+                ..
+            } => {
+                let condition = self.condition(*skip, true);
+                let (open, middle, close) = match self.syntax {
+                    Syntax::PseudoCode => (format!("if ({}) {{", condition), "} else {", "}"),
+                    Syntax::Octo => (format!("if {} begin", condition), "else", "end"),
+                };
+                self.line(depth, &open);
+                self.write_block(then, depth + 1);
+                if let Some(otherwise) = otherwise {
+                    self.line(depth, middle);
+                    self.write_block(otherwise, depth + 1);
+                }
+                self.line(depth, close);
+            }
+            Stmt::Loop { body, .. } => {
+                let (open, close) = match self.syntax {
+                    Syntax::PseudoCode => ("loop {", "}"),
+                    Syntax::Octo => ("loop", "again"),
+                };
+                self.line(depth, open);
+                self.write_block(body, depth + 1);
+                self.line(depth, close);
+            }
+            Stmt::Data { addr, bytes } => {
+                let mut row: Vec<String> = Vec::new();
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let addr = addr + offset as u16;
+                    if self.labels.contains_key(&addr) || row.len() == 8 {
+                        self.write_data(&row, depth);
+                        row.clear();
+                        self.write_labels_at(addr, depth);
+                    }
+                    row.push(format!("0x{:02X}", byte));
+                }
+                self.write_data(&row, depth);
+            }
+        }
+    }
+
+    fn write_data(&mut self, row: &[String], depth: usize) {
+        if row.is_empty() {
+            return;
+        }
+        let text = match self.syntax {
+            Syntax::PseudoCode => format!("bytes({})", row.join(", ")),
+            Syntax::Octo => row.join(" "),
+        };
+        self.line(depth, &text);
+    }
+
+    /// The condition under which a skip instruction skips, or doesn't if `skips` isn't
+    /// set.
+    fn condition(&self, opcode: u16, skips: bool) -> String {
+        let x = (opcode >> 8) & 0xF;
+        let y = (opcode >> 4) & 0xF;
+        let nn = opcode & 0xFF;
+        let (equal, other) = match opcode >> 12 {
+            0x3 => (true, nn.to_string()),
+            0x4 => (false, nn.to_string()),
+            0x5 => (true, format!("v{:x}", y)),
+            0x9 => (false, format!("v{:x}", y)),
+            _ => {
+                let pressed = (opcode & 0xFF == 0x9E) == skips;
+                return match (self.syntax, pressed) {
+                    (Syntax::PseudoCode, true) => format!("key_down(v{:x})", x),
+                    (Syntax::PseudoCode, false) => format!("!key_down(v{:x})", x),
+                    (Syntax::Octo, true) => format!("v{:x} key", x),
+                    (Syntax::Octo, false) => format!("v{:x} -key", x),
+                };
+            }
+        };
+        let operator = if equal == skips { "==" } else { "!=" };
+        format!("v{:x} {} {}", x, operator, other)
+    }
+
+    /// The text of a single instruction.
+    fn instruction(&self, opcode: u16, bytes: &[u8]) -> String {
+        let x = (opcode >> 8) & 0xF;
+        let y = (opcode >> 4) & 0xF;
+        let n = opcode & 0xF;
+        let nn = opcode & 0xFF;
+        let nnn = opcode & 0xFFF;
+        let octo = self.syntax == Syntax::Octo;
+        let text = match (opcode >> 12, x, y, n) {
+            _ if disasm::OpcodeClass::of(opcode, self.megachip) == disasm::OpcodeClass::Unknown
+                || is_skip(opcode)
+                || (self.megachip && opcode >> 12 == 0 && opcode & 0xFF00 != 0x0000) =>
+            {
+                None
+            }
+            (0x0, 0x0, 0xE, 0x0) => Some(if octo { "clear" } else { "clear()" }.to_string()),
+            (0x0, 0x0, 0xE, 0xE) => Some("return".to_string()),
+            (0x0, 0x0, 0xF, 0xD) => Some(if octo { "exit" } else { "exit()" }.to_string()),
+            (0x0, _, _, _) => {
+                if octo || self.megachip {
+                    None
+                } else {
+                    Some(format!("native(0x{:03X})", nnn))
+                }
+            }
+            (0x1, _, _, _) => Some(if octo {
+                format!("jump {}", self.target(nnn))
+            } else {
+                format!("goto {}", self.target(nnn))
+            }),
+            (0x2, _, _, _) => Some(match (self.labels.get(&nnn), octo) {
+                (Some(label), true) => label.clone(),
+                (None, true) => format!(":call 0x{:03X}", nnn),
+                (Some(label), false) => format!("{}()", label),
+                (None, false) => format!("call(0x{:03X})", nnn),
+            }),
+            (0x6, _, _, _) => Some(format!("v{:x} {} {}", x, assign(octo), nn)),
+            (0x7, _, _, _) => Some(format!("v{:x} += {}", x, nn)),
+            (0x8, _, _, op) => Some(match (op, octo) {
+                (0x0, _) => format!("v{:x} {} v{:x}", x, assign(octo), y),
+                (0x1, _) => format!("v{:x} |= v{:x}", x, y),
+                (0x2, _) => format!("v{:x} &= v{:x}", x, y),
+                (0x3, _) => format!("v{:x} ^= v{:x}", x, y),
+                (0x4, _) => format!("v{:x} += v{:x}", x, y),
+                (0x5, _) => format!("v{:x} -= v{:x}", x, y),
+                (0x6, true) => format!("v{:x} >>= v{:x}", x, y),
+                (0x6, false) => format!("v{:x} = v{:x} >> 1", x, y),
+                (0x7, true) => format!("v{:x} =- v{:x}", x, y),
+                (0x7, false) => format!("v{:x} = v{:x} - v{:x}", x, y, x),
+                (_, true) => format!("v{:x} <<= v{:x}", x, y),
+                (_, false) => format!("v{:x} = v{:x} << 1", x, y),
+            }),
+            (0xA, _, _, _) => Some(format!("i {} {}", assign(octo), self.target(nnn))),
+            (0xB, _, _, _) => Some(if octo {
+                format!("jump0 {}", self.target(nnn))
+            } else {
+                format!("goto {} + v0", self.target(nnn))
+            }),
+            (0xC, _, _, _) => Some(if octo {
+                format!("v{:x} := random 0x{:02X}", x, nn)
+            } else {
+                format!("v{:x} = random() & 0x{:02X}", x, nn)
+            }),
+            (0xD, _, _, _) => Some(if octo {
+                format!("sprite v{:x} v{:x} {}", x, y, n)
+            } else {
+                format!("draw(v{:x}, v{:x}, {})", x, y, n)
+            }),
+            (0xF, _, 0x0, 0x7) => Some(format!("v{:x} {} delay", x, assign(octo))),
+            (0xF, _, 0x0, 0xA) => Some(if octo {
+                format!("v{:x} := key", x)
+            } else {
+                format!("v{:x} = wait_key()", x)
+            }),
+            (0xF, _, 0x1, 0x5) => Some(format!("delay {} v{:x}", assign(octo), x)),
+            (0xF, _, 0x1, 0x8) => Some(if octo {
+                format!("buzzer := v{:x}", x)
+            } else {
+                format!("sound = v{:x}", x)
+            }),
+            (0xF, _, 0x1, 0xE) => Some(format!("i += v{:x}", x)),
+            (0xF, _, 0x2, 0x9) => Some(if octo {
+                format!("i := hex v{:x}", x)
+            } else {
+                format!("i = digit(v{:x})", x)
+            }),
+            (0xF, _, 0x3, 0x3) => Some(if octo {
+                format!("bcd v{:x}", x)
+            } else {
+                format!("bcd(v{:x})", x)
+            }),
+            (0xF, _, 0x5, 0x5) => Some(if octo {
+                format!("save v{:x}", x)
+            } else {
+                format!("save(v0..v{:x})", x)
+            }),
+            (0xF, _, 0x6, 0x5) => Some(if octo {
+                format!("load v{:x}", x)
+            } else {
+                format!("load(v0..v{:x})", x)
+            }),
+            _ => None,
+        };
+        match text {
+            Some(text) => text,
+            // Anything else is written as the bytes of the instruction.
+            None => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                let raw = match self.syntax {
+                    Syntax::PseudoCode => format!("bytes({})", hex.join(", ")),
+                    Syntax::Octo => hex.join(" "),
+                };
+                format!(
+                    "{}  {} {}",
+                    raw,
+                    self.comment(),
+                    disasm::disassemble(opcode, self.megachip)
+                )
+            }
+        }
+    }
+}
+
+fn assign(octo: bool) -> &'static str {
+    if octo {
+        ":="
+    } else {
+        "="
+    }
+}
+
+/// Collects the addresses that instructions jump to, call or point `I` at, with the
+/// opcode that refers to each.
+fn collect_references(stmts: &[Stmt], references: &mut Vec<(u16, u16)>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Op { opcode, .. } => match opcode >> 12 {
+                0x1 | 0x2 | 0xA | 0xB => references.push((opcode & 0xFFF, *opcode)),
+                _ => {}
+            },
+            Stmt::IfThen { body, .. } => {
+                collect_references(std::slice::from_ref(&**body), references)
+            }
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                collect_references(then, references);
+                if let Some(otherwise) = otherwise {
+                    collect_references(otherwise, references);
+                }
+            }
+            Stmt::Loop { body, .. } => collect_references(body, references),
+            Stmt::Data { .. } => {}
+        }
+    }
+}
+
+/// Collects the addresses where statements and bytes of data start.
+fn collect_starts(stmts: &[Stmt], starts: &mut BTreeSet<u16>) {
+    for stmt in stmts {
+        starts.insert(stmt.addr());
+        match stmt {
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                collect_starts(then, starts);
+                if let Some(otherwise) = otherwise {
+                    collect_starts(otherwise, starts);
+                }
+            }
+            Stmt::Loop { body, .. } => collect_starts(body, starts),
+            Stmt::Data { addr, bytes } => {
+                starts.extend((0..bytes.len()).map(|offset| addr + offset as u16));
+            }
+            Stmt::Op { .. } | Stmt::IfThen { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: LD I, 0x214; LD V0, 0
+    // 0x204: loop: SNE V0, 10; JP 0x20E; CALL 0x210; ADD V0, 1; JP 0x204
+    // 0x20E: JP 0x20E
+    // 0x210: DRW V0, V1, 1; RET
+    // 0x214: sprite
+    const PROGRAM: [u8; 22] = [
+        0xA2, 0x14, 0x60, 0x00, 0x40, 0x0A, 0x12, 0x0E, 0x22, 0x10, 0x70, 0x01, 0x12, 0x04, 0x12,
+        0x0E, 0xD0, 0x11, 0x00, 0xEE, 0x80, 0xC0,
+    ];
+
+    #[test]
+    fn octo() {
+        assert_eq!(
+            decompile(&PROGRAM, false, Syntax::Octo),
+            ": main
+    i := data_214
+    v0 := 0
+    loop
+        if v0 == 10 then jump label_20E
+        sub_210
+        v0 += 1
+    again
+: label_20E
+    loop
+    again
+
+: sub_210
+    sprite v0 v1 1
+    return
+: data_214
+    0x80 0xC0
+"
+        );
+    }
+
+    #[test]
+    fn pseudo_code() {
+        // SE V0, 1; JP 0x208; LD V1, 2; JP 0x20A; LD V1, 3; EXIT
+        let program = [
+            0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0A, 0x61, 0x03, 0x00, 0xFD,
+        ];
+        assert_eq!(
+            decompile(&program, false, Syntax::PseudoCode),
+            "main:
+    if (v0 == 1) {
+        v1 = 2
+    } else {
+        v1 = 3
+    }
+    exit()
+"
+        );
+        // A skip over a skip isn't an `if`.
+        let program = [0x30, 0x01, 0x40, 0x02, 0x00, 0xFD];
+        assert_eq!(
+            decompile(&program, false, Syntax::Octo),
+            ": main
+    0x30 0x01  # SE V0, 0x01
+    if v0 == 2 then exit
+"
+        );
+    }
+}
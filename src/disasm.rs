@@ -0,0 +1,267 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::coverage::{ByteUse, Coverage};
+use crate::errors::*;
+use crate::system::PROGRAM_START;
+
+/// The kind of operation an instruction performs, used to filter traces and reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpcodeClass {
+    /// Jumps, calls, returns, skips and exiting.
+    Flow,
+    /// Loading constants and register arithmetic.
+    Arithmetic,
+    /// Setting `I`, and loading or storing through it.
+    Memory,
+    /// Drawing, and MegaChip8 display settings.
+    Display,
+    /// Reading and setting the delay and sound timers.
+    Timer,
+    /// Reading the keypad.
+    Input,
+    /// MegaChip8 sample playback.
+    Sound,
+    /// Random numbers.
+    Random,
+    /// Machine code calls with `0NNN`.
+    System,
+    /// Opcodes the platform doesn't define.
+    Unknown,
+}
+
+impl OpcodeClass {
+    pub const ALL: [OpcodeClass; 10] = [
+        OpcodeClass::Flow,
+        OpcodeClass::Arithmetic,
+        OpcodeClass::Memory,
+        OpcodeClass::Display,
+        OpcodeClass::Timer,
+        OpcodeClass::Input,
+        OpcodeClass::Sound,
+        OpcodeClass::Random,
+        OpcodeClass::System,
+        OpcodeClass::Unknown,
+    ];
+
+    /// Classifies an opcode. MegaChip8 opcodes are only recognized if `megachip` is set.
+    pub fn of(opcode: u16, megachip: bool) -> OpcodeClass {
+        match Instruction::decode(opcode, megachip) {
+            Instruction::Known { class, .. } => class,
+            Instruction::Unknown => OpcodeClass::Unknown,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OpcodeClass::Flow => "flow",
+            OpcodeClass::Arithmetic => "arithmetic",
+            OpcodeClass::Memory => "memory",
+            OpcodeClass::Display => "display",
+            OpcodeClass::Timer => "timer",
+            OpcodeClass::Input => "input",
+            OpcodeClass::Sound => "sound",
+            OpcodeClass::Random => "random",
+            OpcodeClass::System => "system",
+            OpcodeClass::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for OpcodeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OpcodeClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OpcodeClass> {
+        match OpcodeClass::ALL.iter().find(|class| class.name() == s) {
+            Some(class) => Ok(*class),
+            None => err(&format!("unknown opcode class: {}", s)),
+        }
+    }
+}
+
+enum Instruction {
+    Known {
+        class: OpcodeClass,
+        mnemonic: String,
+    },
+    Unknown,
+}
+
+impl Instruction {
+    fn decode(opcode: u16, megachip: bool) -> Instruction {
+        use self::OpcodeClass::*;
+
+        let x = (opcode >> 8) & 0xF;
+        let y = (opcode >> 4) & 0xF;
+        let n = opcode & 0xF;
+        let nn = opcode & 0xFF;
+        let nnn = opcode & 0xFFF;
+        let (class, mnemonic) = match ((opcode >> 12), x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => (Display, "CLS".to_owned()),
+            (0x0, 0x0, 0xE, 0xE) => (Flow, "RET".to_owned()),
+            (0x0, 0x0, 0xF, 0xD) => (Flow, "EXIT".to_owned()),
+            (0x0, 0x0, 0x1, 0x0) if megachip => (Display, "MEGAOFF".to_owned()),
+            (0x0, 0x0, 0x1, 0x1) if megachip => (Display, "MEGAON".to_owned()),
+            (0x0, 0x1, _, _) if megachip => (Memory, format!("LDHI 0x{:02X}", nn)),
+            (0x0, 0x2, _, _) if megachip => (Display, format!("LDPAL {}", nn)),
+            (0x0, 0x3, _, _) if megachip => (Display, format!("SPRW {}", nn)),
+            (0x0, 0x4, _, _) if megachip => (Display, format!("SPRH {}", nn)),
+            (0x0, 0x6, 0x0, _) if megachip => (Sound, format!("DIGISND {}", n)),
+            (0x0, 0x7, 0x0, 0x0) if megachip => (Sound, "STOPSND".to_owned()),
+            (0x0, 0x8, 0x0, _) if megachip => (Display, format!("BMODE {}", n)),
+            (0x0, 0x9, _, _) if megachip => (Display, format!("CCOL {}", nn)),
+            (0x0, _, _, _) => (System, format!("SYS 0x{:03X}", nnn)),
+            (0x1, _, _, _) => (Flow, format!("JP 0x{:03X}", nnn)),
+            (0x2, _, _, _) => (Flow, format!("CALL 0x{:03X}", nnn)),
+            (0x3, _, _, _) => (Flow, format!("SE V{:X}, 0x{:02X}", x, nn)),
+            (0x4, _, _, _) => (Flow, format!("SNE V{:X}, 0x{:02X}", x, nn)),
+            (0x5, _, _, 0x0) => (Flow, format!("SE V{:X}, V{:X}", x, y)),
+            (0x6, _, _, _) => (Arithmetic, format!("LD V{:X}, 0x{:02X}", x, nn)),
+            (0x7, _, _, _) => (Arithmetic, format!("ADD V{:X}, 0x{:02X}", x, nn)),
+            (0x8, _, _, op) => {
+                let name = match op {
+                    0x0 => "LD",
+                    0x1 => "OR",
+                    0x2 => "AND",
+                    0x3 => "XOR",
+                    0x4 => "ADD",
+                    0x5 => "SUB",
+                    0x6 => "SHR",
+                    0x7 => "SUBN",
+                    0xE => "SHL",
+                    _ => return Instruction::Unknown,
+                };
+                (Arithmetic, format!("{} V{:X}, V{:X}", name, x, y))
+            }
+            (0x9, _, _, 0x0) => (Flow, format!("SNE V{:X}, V{:X}", x, y)),
+            (0xA, _, _, _) => (Memory, format!("LD I, 0x{:03X}", nnn)),
+            (0xB, _, _, _) => (Flow, format!("JP V0, 0x{:03X}", nnn)),
+            (0xC, _, _, _) => (Random, format!("RND V{:X}, 0x{:02X}", x, nn)),
+            (0xD, _, _, _) => (Display, format!("DRW V{:X}, V{:X}, {}", x, y, n)),
+            (0xE, _, 0x9, 0xE) => (Input, format!("SKP V{:X}", x)),
+            (0xE, _, 0xA, 0x1) => (Input, format!("SKNP V{:X}", x)),
+            (0xF, _, 0x0, 0x7) => (Timer, format!("LD V{:X}, DT", x)),
+            (0xF, _, 0x0, 0xA) => (Input, format!("LD V{:X}, K", x)),
+            (0xF, _, 0x1, 0x5) => (Timer, format!("LD DT, V{:X}", x)),
+            (0xF, _, 0x1, 0x8) => (Timer, format!("LD ST, V{:X}", x)),
+            (0xF, _, 0x1, 0xE) => (Memory, format!("ADD I, V{:X}", x)),
+            (0xF, _, 0x2, 0x9) => (Memory, format!("LD F, V{:X}", x)),
+            (0xF, _, 0x3, 0x3) => (Memory, format!("LD B, V{:X}", x)),
+            (0xF, _, 0x5, 0x5) => (Memory, format!("LD [I], V{:X}", x)),
+            (0xF, _, 0x6, 0x5) => (Memory, format!("LD V{:X}, [I]", x)),
+            _ => return Instruction::Unknown,
+        };
+        Instruction::Known { class, mnemonic }
+    }
+}
+
+/// Returns the assembly mnemonic for an opcode, in the syntax of Cowgod's CHIP-8
+/// technical reference. Opcodes that aren't instructions are shown as data, with
+/// `DW`. MegaChip8 opcodes are only recognized if `megachip` is set.
+pub fn disassemble(opcode: u16, megachip: bool) -> String {
+    match Instruction::decode(opcode, megachip) {
+        Instruction::Known { mnemonic, .. } => mnemonic,
+        Instruction::Unknown => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+/// Disassembles a program loaded at 0x200, with a line for each instruction. Without
+/// coverage every pair of bytes is taken to be an instruction. With coverage, bytes
+/// that were never executed are listed as data with `DB`, along with how they were
+/// used, so that sprites and variables aren't mistaken for code.
+pub fn disassemble_program(program: &[u8], coverage: Option<&Coverage>, megachip: bool) -> String {
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < program.len() {
+        let addr = PROGRAM_START + offset;
+        let is_code = match coverage {
+            Some(coverage) => coverage.is(addr, ByteUse::Code),
+            None => true,
+        };
+        if is_code && offset + 1 < program.len() {
+            let opcode = u16::from(program[offset]) << 8 | u16::from(program[offset + 1]);
+            listing.push_str(&format!(
+                "{:04X}  {:04X}  {}\n",
+                addr,
+                opcode,
+                disassemble(opcode, megachip)
+            ));
+            offset += 2;
+        } else {
+            let byte = program[offset];
+            let mut line = format!("{:04X}  {:02X}    DB 0x{:02X}", addr, byte, byte);
+            if let Some(coverage) = coverage {
+                let uses: Vec<&str> = coverage.uses(addr).iter().map(|u| u.name()).collect();
+                if !uses.is_empty() {
+                    line.push_str(&format!("  ; {}", uses.join(", ")));
+                }
+            }
+            listing.push_str(&line);
+            listing.push('\n');
+            offset += 1;
+        }
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Observer;
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(disassemble(0x00E0, false), "CLS");
+        assert_eq!(disassemble(0x6A12, false), "LD VA, 0x12");
+        assert_eq!(disassemble(0x8AB6, false), "SHR VA, VB");
+        assert_eq!(disassemble(0xD125, false), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xF365, false), "LD V3, [I]");
+        assert_eq!(disassemble(0x8AB8, false), "DW 0x8AB8");
+        assert_eq!(disassemble(0x0011, false), "SYS 0x011");
+        assert_eq!(disassemble(0x0011, true), "MEGAON");
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(OpcodeClass::of(0x2300, false), OpcodeClass::Flow);
+        assert_eq!(OpcodeClass::of(0xF00A, false), OpcodeClass::Input);
+        assert_eq!(OpcodeClass::of(0xF018, false), OpcodeClass::Timer);
+        assert_eq!(OpcodeClass::of(0x5121, false), OpcodeClass::Unknown);
+        assert_eq!(
+            "display".parse::<OpcodeClass>().unwrap(),
+            OpcodeClass::Display
+        );
+        assert!("graphics".parse::<OpcodeClass>().is_err());
+    }
+
+    #[test]
+    fn program_listing() {
+        // LD I, 0x206; JP 0x202; sprite
+        let program = [0xA2, 0x06, 0x12, 0x02, 0x00, 0x00, 0x80];
+        assert_eq!(
+            disassemble_program(&program, None, false),
+            "0200  A206  LD I, 0x206\n\
+             0202  1202  JP 0x202\n\
+             0204  0000  SYS 0x000\n\
+             0206  80    DB 0x80\n"
+        );
+
+        let mut coverage = Coverage::new();
+        coverage.instruction_executed(0x200, 0xA206);
+        coverage.instruction_executed(0x202, 0x1202);
+        coverage.instruction_executed(0x204, 0xD001);
+        coverage.memory_read(0x206, 0x80);
+        assert_eq!(
+            disassemble_program(&program[..4], Some(&coverage), false),
+            "0200  A206  LD I, 0x206\n0202  1202  JP 0x202\n"
+        );
+        let listing = disassemble_program(&[0, 0, 0, 0, 0, 0, 0x80], Some(&Coverage::new()), false);
+        assert_eq!(listing.lines().last(), Some("0206  80    DB 0x80"));
+    }
+}
@@ -0,0 +1,223 @@
+use std::ops::Index;
+
+/// Dimensions of the standard CHIP-8 display.
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+/// Dimensions of the MegaChip8 display.
+pub const MEGACHIP_WIDTH: usize = 256;
+pub const MEGACHIP_HEIGHT: usize = 192;
+
+/// Colors used for unlit and lit pixels outside of MegaChip mode, as ARGB.
+const DEFAULT_BACKGROUND: u32 = 0xFF00_0000;
+const DEFAULT_FOREGROUND: u32 = 0xFFF6_EABE;
+
+/// How a MegaChip sprite pixel is combined with the pixel already on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    Add,
+    Multiply,
+}
+
+impl BlendMode {
+    pub fn from_nibble(n: u8) -> Option<BlendMode> {
+        match n {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Alpha25),
+            2 => Some(BlendMode::Alpha50),
+            3 => Some(BlendMode::Alpha75),
+            4 => Some(BlendMode::Add),
+            5 => Some(BlendMode::Multiply),
+            _ => None,
+        }
+    }
+}
+
+/// An indexed-color framebuffer.
+///
+/// Every pixel holds an index into a 256-entry palette. For the standard CHIP-8
+/// display, index 0 is an unlit pixel and index 1 is a lit pixel. In MegaChip mode,
+/// index 0 is transparent and the remaining entries are loaded by the program.
+///
+/// Because MegaChip blend modes can produce colors that are not in the palette, the
+/// final ARGB color of every pixel is tracked alongside its palette index.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    colors: Vec<u32>,
+    palette: [u32; 256],
+}
+
+impl Display {
+    pub(crate) fn new(width: usize, height: usize) -> Display {
+        let mut palette = [0; 256];
+        palette[0] = DEFAULT_BACKGROUND;
+        palette[1] = DEFAULT_FOREGROUND;
+        Display {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            colors: vec![DEFAULT_BACKGROUND; width * height],
+            palette,
+        }
+    }
+
+    /// Width of the display in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the display in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets the palette index of the pixel at the given position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is outside of the display.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[self.offset(x, y)]
+    }
+
+    /// Whether the pixel at the given position is lit (has a non-zero palette index).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is outside of the display.
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    /// Gets the ARGB color of the pixel at the given position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is outside of the display.
+    pub fn color(&self, x: usize, y: usize) -> u32 {
+        self.colors[self.offset(x, y)]
+    }
+
+    /// Gets the current palette, as ARGB colors.
+    pub fn palette(&self) -> &[u32] {
+        &self.palette
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for p in self.pixels.iter_mut() {
+            *p = 0;
+        }
+        let background = self.palette[0];
+        for c in self.colors.iter_mut() {
+            *c = background;
+        }
+    }
+
+    pub(crate) fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width * height];
+        self.colors = vec![self.palette[0]; width * height];
+    }
+
+    pub(crate) fn set_palette_color(&mut self, index: u8, argb: u32) {
+        self.palette[usize::from(index)] = argb;
+    }
+
+    /// Toggles a pixel of the standard display, returning whether a lit pixel was
+    /// turned off.
+    pub(crate) fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let was_lit = self.is_lit(x, y);
+        self.set(x, y, !was_lit);
+        was_lit
+    }
+
+    /// Sets a pixel of the standard display to be lit or unlit.
+    pub(crate) fn set(&mut self, x: usize, y: usize, lit: bool) {
+        let offset = self.offset(x, y);
+        let index = if lit { 1 } else { 0 };
+        self.pixels[offset] = index;
+        self.colors[offset] = self.palette[usize::from(index)];
+    }
+
+    /// Draws a palette color over a pixel using the given blend mode.
+    pub(crate) fn blend(&mut self, x: usize, y: usize, index: u8, mode: BlendMode) {
+        let offset = self.offset(x, y);
+        let src = self.palette[usize::from(index)];
+        let dst = self.colors[offset];
+        self.pixels[offset] = index;
+        self.colors[offset] = blend_argb(src, dst, mode);
+    }
+
+    fn offset(&self, x: usize, y: usize) -> usize {
+        assert!(x < self.width && y < self.height);
+        y * self.width + x
+    }
+}
+
+/// Rows of the display, as palette indices.
+impl Index<usize> for Display {
+    type Output = [u8];
+
+    fn index(&self, y: usize) -> &[u8] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+}
+
+fn blend_argb(src: u32, dst: u32, mode: BlendMode) -> u32 {
+    let channel = |shift: u32| -> u32 {
+        let s = (src >> shift) & 0xFF;
+        let d = (dst >> shift) & 0xFF;
+        let c = match mode {
+            BlendMode::Normal => s,
+            BlendMode::Alpha25 => (s + 3 * d) / 4,
+            BlendMode::Alpha50 => (s + d) / 2,
+            BlendMode::Alpha75 => (3 * s + d) / 4,
+            BlendMode::Add => (s + d).min(0xFF),
+            BlendMode::Multiply => s * d / 0xFF,
+        };
+        c << shift
+    };
+    0xFF00_0000 | channel(16) | channel(8) | channel(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_pixels() {
+        let mut display = Display::new(LORES_WIDTH, LORES_HEIGHT);
+        assert!(!display.toggle(3, 4));
+        assert!(display.is_lit(3, 4));
+        assert_eq!(display.color(3, 4), DEFAULT_FOREGROUND);
+        assert!(display.toggle(3, 4));
+        assert!(!display.is_lit(3, 4));
+        assert_eq!(display.color(3, 4), DEFAULT_BACKGROUND);
+    }
+
+    #[test]
+    fn rows() {
+        let mut display = Display::new(LORES_WIDTH, LORES_HEIGHT);
+        display.toggle(1, 2);
+        assert_eq!(display[2].len(), LORES_WIDTH);
+        assert_eq!(&display[2][0..3], &[0, 1, 0]);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let (src, dst) = (0xFF80_40FF, 0xFF40_80FF);
+        assert_eq!(blend_argb(src, dst, BlendMode::Normal), src);
+        assert_eq!(blend_argb(src, dst, BlendMode::Alpha50), 0xFF60_60FF);
+        assert_eq!(blend_argb(src, dst, BlendMode::Alpha25), 0xFF50_70FF);
+        assert_eq!(blend_argb(src, dst, BlendMode::Add), 0xFFC0_C0FF);
+        assert_eq!(blend_argb(src, dst, BlendMode::Multiply), 0xFF20_20FF);
+    }
+}
@@ -1,8 +1,50 @@
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Error(pub String);
 
-pub type Result = std::result::Result<(), Error>;
+pub type Result<T = ()> = std::result::Result<T, Error>;
 
 pub fn err<T>(details: &str) -> std::result::Result<T, Error> {
     Err(Error(details.to_owned()))
 }
+
+/// What to do when a program does something invalid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop execution by returning an error.
+    #[default]
+    Strict,
+    /// Record a warning, then continue as with `Lenient`.
+    Warn,
+    /// Continue as other emulators commonly do, by wrapping addresses around the end
+    /// of memory or skipping unknown instructions.
+    Lenient,
+}
+
+/// The policy to apply to each class of error a program can cause.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorPolicies {
+    /// Instructions that don't exist on the emulated platform.
+    pub unknown_opcode: ErrorPolicy,
+    /// Accesses to memory below the program area or past the end of memory.
+    pub invalid_address: ErrorPolicy,
+    /// Sprites that extend past the end of memory.
+    pub sprite_overflow: ErrorPolicy,
+    /// Operands out of range for an instruction, such as key values or digits above
+    /// 0xF, or unknown blend modes.
+    pub invalid_operand: ErrorPolicy,
+    /// Calls made with the call stack full, and returns with it empty.
+    pub stack: ErrorPolicy,
+}
+
+impl ErrorPolicies {
+    /// Applies the same policy to every class of error.
+    pub fn all(policy: ErrorPolicy) -> ErrorPolicies {
+        ErrorPolicies {
+            unknown_opcode: policy,
+            invalid_address: policy,
+            sprite_overflow: policy,
+            invalid_operand: policy,
+            stack: policy,
+        }
+    }
+}
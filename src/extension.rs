@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::display::Display;
+use crate::errors::*;
+use crate::platform::Platform;
+use crate::system::SystemState;
+
+/// Implements instructions in opcode space that the emulator leaves unused.
+///
+/// Extensions are only consulted for opcodes the emulated platform doesn't define,
+/// and for `0NNN` machine code calls that have no native routine. They are tried in
+/// the order they were added, until one handles the opcode.
+pub trait OpcodeExtension {
+    /// Executes an opcode, returning whether it was handled. When called, the program
+    /// counter already points at the following instruction.
+    fn execute(&mut self, machine: &mut Machine, opcode: u16) -> std::result::Result<bool, Error>;
+}
+
+/// A host implementation of a machine code subroutine, called by `0NNN` instead of
+/// the machine code at `NNN`.
+pub trait NativeRoutine {
+    /// Runs the routine. When called, the program counter already points at the
+    /// instruction following the `0NNN` call.
+    fn call(&mut self, machine: &mut Machine) -> Result;
+}
+
+impl<F: FnMut(&mut Machine) -> Result> NativeRoutine for F {
+    fn call(&mut self, machine: &mut Machine) -> Result {
+        self(machine)
+    }
+}
+
+/// Access to the state of the emulated machine, given to extensions and native routines.
+pub struct Machine<'a> {
+    system: &'a mut SystemState,
+}
+
+impl<'a> Machine<'a> {
+    pub(crate) fn new(system: &'a mut SystemState) -> Machine<'a> {
+        Machine { system }
+    }
+
+    /// The platform being emulated.
+    pub fn platform(&self) -> Platform {
+        self.system.platform
+    }
+
+    /// Gets the value of register `VX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if x is greater than 15.
+    pub fn register(&self, x: u8) -> u8 {
+        self.system.registers[usize::from(x)]
+    }
+
+    /// Sets the value of register `VX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if x is greater than 15.
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.system.registers[usize::from(x)] = value;
+    }
+
+    /// Gets the address register, `I`.
+    pub fn address_register(&self) -> u32 {
+        self.system.address_register
+    }
+
+    /// Sets the address register, `I`.
+    pub fn set_address_register(&mut self, value: u32) {
+        self.system.address_register = value & self.system.address_mask();
+    }
+
+    /// Gets the address of the next instruction.
+    pub fn program_counter(&self) -> u16 {
+        self.system.program_counter
+    }
+
+    /// Sets the address of the next instruction.
+    pub fn set_program_counter(&mut self, value: u16) {
+        self.system.program_counter = value;
+    }
+
+    /// Gets the delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.system.delay_timer
+    }
+
+    /// Sets the delay timer.
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.system.delay_timer = value;
+    }
+
+    /// Gets the sound timer. A tone plays while it's above zero.
+    pub fn sound_timer(&self) -> u8 {
+        self.system.sound_timer
+    }
+
+    /// Sets the sound timer.
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.system.sound_timer = value;
+    }
+
+    /// Whether a keypad key is held down.
+    ///
+    /// # Panics
+    ///
+    /// Panics if key is greater than 15.
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.system.keys[usize::from(key)]
+    }
+
+    /// All of memory, including the interpreter area below 0x200.
+    pub fn memory(&self) -> &[u8] {
+        &self.system.memory
+    }
+
+    /// Writes bytes to memory. With the VIP memory layout, writes to the stack or
+    /// display regions also update the stack or display.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if programs may not access the whole range, as for
+    /// `Emulator::write_memory`.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result {
+        self.system.check_address(addr, bytes.len())?;
+        self.system.write_memory(addr, bytes);
+        Ok(())
+    }
+
+    /// Pushes a return address onto the call stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stack is full.
+    pub fn push_stack(&mut self, addr: u16) -> Result {
+        self.system.push_stack(addr)
+    }
+
+    /// Pops a return address from the call stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stack is empty.
+    pub fn pop_stack(&mut self) -> std::result::Result<u16, Error> {
+        self.system.pop_stack()
+    }
+
+    /// The display being drawn to.
+    pub fn display(&self) -> &Display {
+        &self.system.display
+    }
+
+    /// Sets a pixel of the standard display to be lit or unlit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is outside of the display.
+    pub fn set_pixel(&mut self, x: usize, y: usize, lit: bool) {
+        self.system.display.set(x, y, lit);
+        self.system.display_updated();
+    }
+
+    /// Clears the display.
+    pub fn clear_display(&mut self) {
+        self.system.display.clear();
+        self.system.display_updated();
+    }
+}
+
+/// The extensions and native routines registered with an emulator. Clones share
+/// them, along with any state they keep, so that forked emulators keep running the
+/// same routines.
+#[derive(Clone, Default)]
+pub(crate) struct Extensions {
+    opcodes: Vec<Rc<RefCell<Box<dyn OpcodeExtension>>>>,
+    routines: HashMap<u16, Rc<RefCell<Box<dyn NativeRoutine>>>>,
+}
+
+impl Extensions {
+    pub fn add_opcode_extension(&mut self, extension: Box<dyn OpcodeExtension>) {
+        self.opcodes.push(Rc::new(RefCell::new(extension)));
+    }
+
+    pub fn set_native_routine(&mut self, addr: u16, routine: Box<dyn NativeRoutine>) {
+        self.routines.insert(addr, Rc::new(RefCell::new(routine)));
+    }
+
+    /// Calls the native routine for an address, if there is one.
+    pub fn call_routine(&mut self, system: &mut SystemState, addr: u16) -> Option<Result> {
+        let routine = self.routines.get(&addr)?;
+        Some(routine.borrow_mut().call(&mut Machine::new(system)))
+    }
+
+    /// Offers an opcode to each extension in turn, returning whether one handled it.
+    pub fn execute(
+        &mut self,
+        system: &mut SystemState,
+        opcode: u16,
+    ) -> std::result::Result<bool, Error> {
+        let mut machine = Machine::new(system);
+        for extension in self.opcodes.iter() {
+            if extension.borrow_mut().execute(&mut machine, opcode)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::{IOpcodeRunner, OpcodeRunner};
+
+    /// Implements `5XY1` as adding VY to VX without carry.
+    struct AddExtension;
+
+    impl OpcodeExtension for AddExtension {
+        fn execute(
+            &mut self,
+            machine: &mut Machine,
+            opcode: u16,
+        ) -> std::result::Result<bool, Error> {
+            if opcode & 0xF00F != 0x5001 {
+                return Ok(false);
+            }
+            let (x, y) = (((opcode >> 8) & 0xF) as u8, ((opcode >> 4) & 0xF) as u8);
+            let sum = machine.register(x).wrapping_add(machine.register(y));
+            machine.set_register(x, sum);
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn extensions_handle_unused_opcodes() {
+        let mut system = SystemState::new();
+        let mut extensions = Extensions::default();
+        extensions.add_opcode_extension(Box::new(AddExtension));
+        system.registers[1] = 3;
+        system.registers[2] = 4;
+        OpcodeRunner
+            .run(&mut system, &mut extensions, 0x5121)
+            .unwrap();
+        assert_eq!(system.registers[1], 7);
+
+        // Defined opcodes are not offered to extensions.
+        OpcodeRunner
+            .run(&mut system, &mut extensions, 0x5120)
+            .unwrap();
+        assert_eq!(system.registers[1], 7);
+        assert!(OpcodeRunner
+            .run(&mut system, &mut extensions, 0x5122)
+            .is_err());
+    }
+
+    #[test]
+    fn native_routines() {
+        let mut system = SystemState::new();
+        let mut extensions = Extensions::default();
+        extensions.set_native_routine(
+            0x123,
+            Box::new(|machine: &mut Machine| {
+                machine.set_register(0, 0x42);
+                machine.write_memory(0x300, &[1, 2])
+            }),
+        );
+        OpcodeRunner
+            .run(&mut system, &mut extensions, 0x0123)
+            .unwrap();
+        assert_eq!(system.registers[0], 0x42);
+        assert_eq!(&system.memory[0x300..0x302], &[1, 2]);
+
+        // Calls without a routine are ignored.
+        OpcodeRunner
+            .run(&mut system, &mut extensions, 0x0124)
+            .unwrap();
+    }
+
+    #[test]
+    fn routines_cannot_write_below_program() {
+        let mut system = SystemState::new();
+        let mut extensions = Extensions::default();
+        extensions.set_native_routine(
+            0x123,
+            Box::new(|machine: &mut Machine| machine.write_memory(0x1FF, &[0xFF])),
+        );
+        assert!(OpcodeRunner
+            .run(&mut system, &mut extensions, 0x0123)
+            .is_err());
+        assert_eq!(system.memory[0x1FF], 0);
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disasm::{self, OpcodeClass};
+use crate::system::PROGRAM_START;
+
+/// How control passes from one basic block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Falling through to the next instruction, including when a skip isn't taken.
+    Next,
+    /// A skip instruction skipping the next instruction.
+    Skip,
+    /// A `1NNN` jump.
+    Jump,
+    /// A `2NNN` call.
+    Call,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that is only entered at the top and only left at the bottom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The instructions, with their addresses.
+    pub instructions: Vec<(u16, u16)>,
+}
+
+impl BasicBlock {
+    /// The address of the first instruction.
+    pub fn start(&self) -> u16 {
+        self.instructions[0].0
+    }
+}
+
+/// A problem found while following the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    /// A `BNNN` jump, whose target depends on `V0`, so can't be followed.
+    ComputedJump { address: u16 },
+    /// Execution continues past the last byte of the program.
+    FallsOffEnd { address: u16 },
+    /// A jump or call to an address outside the program.
+    OutsideProgram { address: u16, target: u16 },
+    /// An opcode that isn't an instruction was reached.
+    UnknownOpcode { address: u16, opcode: u16 },
+    /// Bytes of the program that no path reaches, which may be data.
+    Unreachable { start: u16, end: u16 },
+    /// The bytes of a MegaChip8 program past 0xFFFF, which aren't analyzed.
+    PastAddressRange { len: usize },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::ComputedJump { address } => {
+                write!(f, "0x{:03X}: computed jump can't be followed", address)
+            }
+            Issue::FallsOffEnd { address } => write!(
+                f,
+                "0x{:03X}: execution runs past the end of the program",
+                address
+            ),
+            Issue::OutsideProgram { address, target } => write!(
+                f,
+                "0x{:03X}: jumps to 0x{:03X}, outside the program",
+                address, target
+            ),
+            Issue::UnknownOpcode { address, opcode } => {
+                write!(f, "0x{:03X}: unknown opcode 0x{:04X}", address, opcode)
+            }
+            Issue::Unreachable { start, end } => {
+                write!(f, "0x{:03X}-0x{:03X}: never reached", start, end)
+            }
+            Issue::PastAddressRange { len } => write!(
+                f,
+                "0x10000-0x{:X}: past the 16-bit address range, not analyzed",
+                0x10000 + len - 1
+            ),
+        }
+    }
+}
+
+/// What follows an instruction.
+struct Successors {
+    targets: Vec<(u16, EdgeKind)>,
+    /// Whether the instruction ends a basic block.
+    ends_block: bool,
+}
+
+/// The control-flow graph of a program, found by following its jumps, calls and skips
+/// from where it's loaded, without running it. Calls are assumed to return.
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    edges: Vec<Edge>,
+    issues: Vec<Issue>,
+    /// The blocks that end in a problem.
+    flagged: BTreeSet<u16>,
+    megachip: bool,
+}
+
+impl ControlFlowGraph {
+    /// Analyzes a program loaded at 0x200. MegaChip8 opcodes are only recognized if
+    /// `megachip` is set. Only the first 0xFE00 bytes, up to address 0xFFFF, are
+    /// analyzed.
+    pub fn analyze(program: &[u8], megachip: bool) -> ControlFlowGraph {
+        let (program, rest) = program.split_at(program.len().min(0x10000 - PROGRAM_START));
+        let end = PROGRAM_START + program.len();
+        let opcode_at = |addr: usize| {
+            if addr >= PROGRAM_START && addr + 1 < end {
+                Some(
+                    u16::from(program[addr - PROGRAM_START]) << 8
+                        | u16::from(program[addr + 1 - PROGRAM_START]),
+                )
+            } else {
+                None
+            }
+        };
+
+        let mut instructions = BTreeMap::new();
+        let mut successors = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut issues = Vec::new();
+        let entry = PROGRAM_START as u16;
+        let mut flagged = BTreeSet::new();
+        let mut pending = vec![(entry, entry, EdgeKind::Next)];
+        leaders.insert(entry);
+        while let Some((from, addr, kind)) = pending.pop() {
+            if instructions.contains_key(&addr) {
+                continue;
+            }
+            let opcode = match opcode_at(usize::from(addr)) {
+                Some(opcode) => opcode,
+                None => {
+                    flagged.insert(from);
+                    issues.push(match kind {
+                        EdgeKind::Jump | EdgeKind::Call => Issue::OutsideProgram {
+                            address: from,
+                            target: addr,
+                        },
+                        _ => Issue::FallsOffEnd { address: addr },
+                    });
+                    continue;
+                }
+            };
+            instructions.insert(addr, opcode);
+            let issue_count = issues.len();
+            let next = successors_of(addr, opcode, megachip, &mut issues);
+            if issues.len() > issue_count {
+                flagged.insert(addr);
+            }
+            for &(target, kind) in next.targets.iter() {
+                if next.ends_block || kind != EdgeKind::Next {
+                    leaders.insert(target);
+                }
+                pending.push((addr, target, kind));
+            }
+            successors.insert(addr, next);
+        }
+
+        // Split the instructions into blocks at each leader.
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut edges = Vec::new();
+        let mut current: Vec<(u16, u16)> = Vec::new();
+        for (&addr, &opcode) in instructions.iter() {
+            if let Some(&(last, _)) = current.last() {
+                let after = u32::from(last) + u32::from(instruction_len(last, &instructions));
+                if leaders.contains(&addr) || u32::from(addr) != after {
+                    edges.extend(block_edges(&current, &successors));
+                    blocks.push(BasicBlock {
+                        instructions: current,
+                    });
+                    current = Vec::new();
+                }
+            }
+            current.push((addr, opcode));
+            if successors[&addr].ends_block {
+                edges.extend(block_edges(&current, &successors));
+                blocks.push(BasicBlock {
+                    instructions: current,
+                });
+                current = Vec::new();
+            }
+        }
+        if !current.is_empty() {
+            edges.extend(block_edges(&current, &successors));
+            blocks.push(BasicBlock {
+                instructions: current,
+            });
+        }
+
+        // Flagged instructions are replaced by the blocks they end, and edges that
+        // leave the program are dropped.
+        let starts: BTreeSet<u16> = blocks.iter().map(|block| block.start()).collect();
+        edges.retain(|edge| starts.contains(&edge.to));
+        let flagged = blocks
+            .iter()
+            .filter(|block| flagged.contains(&block.instructions.last().unwrap().0))
+            .map(|block| block.start())
+            .collect();
+
+        // Find the bytes no instruction covers.
+        let mut reached = vec![false; program.len()];
+        for &addr in instructions.keys() {
+            let start = usize::from(addr) - PROGRAM_START;
+            let len = usize::from(instruction_len(addr, &instructions));
+            for byte in reached.iter_mut().skip(start).take(len) {
+                *byte = true;
+            }
+        }
+        let mut offset = 0;
+        while offset < reached.len() {
+            if reached[offset] {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            while offset < reached.len() && !reached[offset] {
+                offset += 1;
+            }
+            issues.push(Issue::Unreachable {
+                start: (PROGRAM_START + start) as u16,
+                end: (PROGRAM_START + offset - 1) as u16,
+            });
+        }
+        issues.sort_by_key(|issue| match *issue {
+            Issue::ComputedJump { address }
+            | Issue::FallsOffEnd { address }
+            | Issue::OutsideProgram { address, .. }
+            | Issue::UnknownOpcode { address, .. } => address,
+            Issue::Unreachable { start, .. } => start,
+            Issue::PastAddressRange { .. } => 0xFFFF,
+        });
+        issues.dedup();
+        if !rest.is_empty() {
+            issues.push(Issue::PastAddressRange { len: rest.len() });
+        }
+
+        ControlFlowGraph {
+            blocks,
+            edges,
+            issues,
+            flagged,
+            megachip,
+        }
+    }
+
+    /// The basic blocks, in order of address.
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// The edges between blocks, from the start of one block to the start of another.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Problems found, in order of address.
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+
+    /// Writes the graph in Graphviz DOT format. Blocks that end in a computed jump, leave
+    /// the program or reach an unknown opcode are drawn in red.
+    pub fn write_dot(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "digraph program {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for &(addr, opcode) in block.instructions.iter() {
+                label.push_str(&format!(
+                    "{:03X}  {}\\l",
+                    addr,
+                    disasm::disassemble(opcode, self.megachip)
+                ));
+            }
+            let flagged = self.flagged.contains(&block.start());
+            writeln!(
+                out,
+                "    b{:03X} [label=\"{}\"{}];",
+                block.start(),
+                label,
+                if flagged { ", color=red" } else { "" }
+            )?;
+        }
+        for edge in self.edges.iter() {
+            let style = match edge.kind {
+                EdgeKind::Next => "",
+                EdgeKind::Skip => " [label=\"skip\"]",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Call => " [label=\"call\", style=dashed]",
+            };
+            writeln!(out, "    b{:03X} -> b{:03X}{};", edge.from, edge.to, style)?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// The number of bytes in the instruction at an address.
+fn instruction_len(addr: u16, instructions: &BTreeMap<u16, u16>) -> u16 {
+    // MegaChip8's LDHI is followed by a word of the address.
+    match instructions.get(&addr) {
+        Some(&opcode) if opcode & 0xFF00 == 0x0100 => 4,
+        _ => 2,
+    }
+}
+
+/// The edges leaving a block, from the successors of its last instruction.
+fn block_edges(block: &[(u16, u16)], successors: &BTreeMap<u16, Successors>) -> Vec<Edge> {
+    let from = block[0].0;
+    let (last, _) = *block.last().unwrap();
+    successors[&last]
+        .targets
+        .iter()
+        .map(|&(to, kind)| Edge { from, to, kind })
+        .collect()
+}
+
+/// The instructions that can follow an instruction. Those past 0xFFFF are left out,
+/// as that part of the program isn't analyzed.
+fn successors_of(addr: u16, opcode: u16, megachip: bool, issues: &mut Vec<Issue>) -> Successors {
+    let next = addr.checked_add(2);
+    let skip = addr.checked_add(4);
+    let nnn = opcode & 0xFFF;
+    let ends = |targets: Vec<(Option<u16>, EdgeKind)>| Successors {
+        targets: targets
+            .into_iter()
+            .filter_map(|(target, kind)| Some((target?, kind)))
+            .collect(),
+        ends_block: true,
+    };
+    if OpcodeClass::of(opcode, megachip) == OpcodeClass::Unknown {
+        issues.push(Issue::UnknownOpcode {
+            address: addr,
+            opcode,
+        });
+        return ends(vec![]);
+    }
+    match opcode >> 12 {
+        0x0 if opcode == 0x00EE || opcode == 0x00FD => ends(vec![]),
+        0x0 if megachip && opcode & 0xFF00 == 0x0100 => Successors {
+            targets: skip
+                .map(|skip| (skip, EdgeKind::Next))
+                .into_iter()
+                .collect(),
+            ends_block: false,
+        },
+        0x1 => ends(vec![(Some(nnn), EdgeKind::Jump)]),
+        0x2 => ends(vec![(Some(nnn), EdgeKind::Call), (next, EdgeKind::Next)]),
+        0x3 | 0x4 | 0x5 | 0x9 | 0xE => ends(vec![(next, EdgeKind::Next), (skip, EdgeKind::Skip)]),
+        0xB => {
+            issues.push(Issue::ComputedJump { address: addr });
+            ends(vec![])
+        }
+        _ => Successors {
+            targets: next
+                .map(|next| (next, EdgeKind::Next))
+                .into_iter()
+                .collect(),
+            ends_block: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_blocks() {
+        // 0x200: CALL 0x20A; SE V0, 1; JP 0x200; JP V0, 0x300
+        // 0x208: unreachable
+        // 0x20A: LD V0, 1; RET
+        // 0x20E: unreachable
+        let program = [
+            0x22, 0x0A, 0x30, 0x01, 0x12, 0x00, 0xB3, 0x00, 0x00, 0x00, 0x60, 0x01, 0x00, 0xEE,
+            0xFF, 0xFF,
+        ];
+        let graph = ControlFlowGraph::analyze(&program, false);
+        let starts: Vec<u16> = graph.blocks().iter().map(|b| b.start()).collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x20A]);
+        assert_eq!(
+            graph.blocks()[4].instructions,
+            [(0x20A, 0x6001), (0x20C, 0x00EE)]
+        );
+        assert!(graph.edges().contains(&Edge {
+            from: 0x200,
+            to: 0x20A,
+            kind: EdgeKind::Call,
+        }));
+        assert!(graph.edges().contains(&Edge {
+            from: 0x202,
+            to: 0x206,
+            kind: EdgeKind::Skip,
+        }));
+        assert_eq!(
+            graph.issues(),
+            [
+                Issue::ComputedJump { address: 0x206 },
+                Issue::Unreachable {
+                    start: 0x208,
+                    end: 0x209,
+                },
+                Issue::Unreachable {
+                    start: 0x20E,
+                    end: 0x20F,
+                },
+            ]
+        );
+
+        let mut dot = Vec::new();
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("    b206 [label=\"206  JP V0, 0x300\\l\", color=red];\n"));
+        assert!(dot.contains("    b200 -> b20A [label=\"call\", style=dashed];\n"));
+        assert!(dot.contains("    b200 -> b202;\n"));
+    }
+
+    #[test]
+    fn falls_off_end() {
+        // LD V0, 1; SNE V0, 1
+        let graph = ControlFlowGraph::analyze(&[0x60, 0x01, 0x40, 0x01], false);
+        assert_eq!(graph.blocks().len(), 1);
+        assert_eq!(
+            graph.issues(),
+            [
+                Issue::FallsOffEnd { address: 0x204 },
+                Issue::FallsOffEnd { address: 0x206 },
+            ]
+        );
+        assert_eq!(
+            graph.issues()[0].to_string(),
+            "0x204: execution runs past the end of the program"
+        );
+
+        // JP 0x100
+        let graph = ControlFlowGraph::analyze(&[0x11, 0x00], false);
+        assert_eq!(
+            graph.issues(),
+            [Issue::OutsideProgram {
+                address: 0x200,
+                target: 0x100,
+            }]
+        );
+    }
+
+    #[test]
+    fn past_address_range() {
+        // A MegaChip8 program of LD V0, 0 that runs on past 0xFFFF.
+        let program: Vec<u8> = [0x60, 0x00]
+            .iter()
+            .cycle()
+            .cloned()
+            .take(0x10010 - PROGRAM_START)
+            .collect();
+        let graph = ControlFlowGraph::analyze(&program, true);
+        assert_eq!(graph.blocks().len(), 1);
+        assert_eq!(
+            graph.blocks()[0].instructions.last(),
+            Some(&(0xFFFE, 0x6000))
+        );
+        assert_eq!(graph.issues(), [Issue::PastAddressRange { len: 0x10 }]);
+        assert_eq!(
+            graph.issues()[0].to_string(),
+            "0x10000-0x1000F: past the 16-bit address range, not analyzed"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+
+use crate::display::Display;
+use crate::platform::Platform;
+use crate::system::SystemState;
+
+/// A read-only view of the state of the emulated machine.
+///
+/// The `Display` implementation formats the CPU state over a few lines, suitable for
+/// including in bug reports.
+pub struct MachineView<'a> {
+    system: &'a SystemState,
+}
+
+impl<'a> MachineView<'a> {
+    pub(crate) fn new(system: &'a SystemState) -> MachineView<'a> {
+        MachineView { system }
+    }
+
+    /// The platform being emulated.
+    pub fn platform(&self) -> Platform {
+        self.system.platform
+    }
+
+    /// The registers `V0` to `VF`.
+    pub fn registers(&self) -> &'a [u8; 16] {
+        &self.system.registers
+    }
+
+    /// The address register, `I`.
+    pub fn address_register(&self) -> u32 {
+        self.system.address_register
+    }
+
+    /// The address of the next instruction.
+    pub fn program_counter(&self) -> u16 {
+        self.system.program_counter
+    }
+
+    /// The number of return addresses on the call stack.
+    pub fn stack_pointer(&self) -> u8 {
+        self.system.stack_pointer
+    }
+
+    /// The return addresses on the call stack, from the bottom of the stack.
+    pub fn stack(&self) -> &'a [u16] {
+        &self.system.stack[..usize::from(self.system.stack_pointer)]
+    }
+
+    /// The delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.system.delay_timer
+    }
+
+    /// The sound timer. A tone plays while it's above zero.
+    pub fn sound_timer(&self) -> u8 {
+        self.system.sound_timer
+    }
+
+    /// All of memory, including the interpreter area below 0x200.
+    pub fn memory(&self) -> &'a [u8] {
+        &self.system.memory
+    }
+
+    /// Which keypad keys are held down.
+    pub fn keys(&self) -> &'a [bool; 16] {
+        &self.system.keys
+    }
+
+    /// The display shown to the user.
+    pub fn display(&self) -> &'a Display {
+        self.system.visible_display()
+    }
+
+    /// Whether the program is blocked on `FX0A` until a key is pressed.
+    pub fn waiting_for_key(&self) -> bool {
+        self.system.waiting_for_key
+    }
+
+    /// Whether the program exited with `00FD`.
+    pub fn halted(&self) -> bool {
+        self.system.halted
+    }
+
+    /// The instruction at the program counter, if it lies within memory.
+    pub fn next_opcode(&self) -> Option<u16> {
+        let pc = usize::from(self.system.program_counter);
+        self.system
+            .memory
+            .get(pc..pc + 2)
+            .map(|bytes| (u16::from(bytes[0]) << 8) | u16::from(bytes[1]))
+    }
+}
+
+impl<'a> fmt::Debug for MachineView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MachineView")
+            .field("platform", &self.platform())
+            .field("registers", self.registers())
+            .field("address_register", &self.address_register())
+            .field("program_counter", &self.program_counter())
+            .field("stack", &self.stack())
+            .field("delay_timer", &self.delay_timer())
+            .field("sound_timer", &self.sound_timer())
+            .field("keys", self.keys())
+            .field("waiting_for_key", &self.waiting_for_key())
+            .field("halted", &self.halted())
+            .finish()
+    }
+}
+
+impl<'a> fmt::Display for MachineView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC=0x{:04X} I=0x{:04X} SP={} DT={} ST={}",
+            self.program_counter(),
+            self.address_register(),
+            self.stack_pointer(),
+            self.delay_timer(),
+            self.sound_timer()
+        )?;
+        if let Some(opcode) = self.next_opcode() {
+            write!(f, " next=0x{:04X}", opcode)?;
+        }
+        for (half, values) in self.registers().chunks(8).enumerate() {
+            writeln!(f)?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "V{:X}={:02X}", half * 8 + i, value)?;
+            }
+        }
+        write!(f, "\nstack:")?;
+        for addr in self.stack() {
+            write!(f, " 0x{:04X}", addr)?;
+        }
+        if self.waiting_for_key() {
+            write!(f, "\nwaiting for key")?;
+        }
+        if self.halted() {
+            write!(f, "\nhalted")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump() {
+        let mut system = SystemState::new();
+        system.load_program(&[0x12, 0x34]).unwrap();
+        system.registers[0xA] = 0x42;
+        system.address_register = 0x300;
+        system.push_stack(0x202).unwrap();
+        let view = MachineView::new(&system);
+        assert_eq!(view.stack(), &[0x202]);
+        assert_eq!(
+            view.to_string(),
+            "PC=0x0200 I=0x0300 SP=1 DT=0 ST=0 next=0x1234\n\
+             V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00\n\
+             V8=00 V9=00 VA=42 VB=00 VC=00 VD=00 VE=00 VF=00\n\
+             stack: 0x0202"
+        );
+    }
+}
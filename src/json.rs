@@ -0,0 +1,195 @@
+use crate::errors::*;
+
+/// Parses a color written as `#RRGGBB` into ARGB.
+pub(crate) fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .map(|rgb| 0xFF00_0000 | rgb)
+}
+
+/// The parts of JSON needed to read cartridges and the ROM database.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn parse(text: &str) -> Result<Json> {
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position < parser.chars.len() {
+            return err("isn't valid JSON");
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl JsonParser {
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Result<char> {
+        match self.peek() {
+            Some(c) => {
+                self.position += 1;
+                Ok(c)
+            }
+            None => err("ends early"),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result {
+        if self.next()? == expected {
+            Ok(())
+        } else {
+            err("isn't valid JSON")
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.position += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    self.expect('"')?;
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        '}' => return Ok(Json::Object(members)),
+                        _ => return err("isn't valid JSON"),
+                    }
+                }
+            }
+            Some('[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        ']' => return Ok(Json::Array(items)),
+                        _ => return err("isn't valid JSON"),
+                    }
+                }
+            }
+            Some('"') => {
+                self.position += 1;
+                self.string().map(Json::String)
+            }
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            _ => {
+                let start = self.position;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                match number.parse() {
+                    Ok(number) => Ok(Json::Number(number)),
+                    Err(_) => err("isn't valid JSON"),
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of a string, after the opening quote.
+    fn string(&mut self) -> Result<String> {
+        let mut string = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(string),
+                '\\' => match self.next()? {
+                    'n' => string.push('\n'),
+                    't' => string.push('\t'),
+                    'r' => string.push('\r'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'u' => {
+                        let mut unit = self.code_unit()?;
+                        if (0xD800..0xDC00).contains(&unit) {
+                            // A surrogate pair.
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.code_unit()?;
+                            unit = 0x10000
+                                + ((unit - 0xD800) << 10)
+                                + (low.wrapping_sub(0xDC00) & 0x3FF);
+                        }
+                        string.push(std::char::from_u32(unit).unwrap_or('\u{FFFD}'));
+                    }
+                    c => string.push(c),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn code_unit(&mut self) -> Result<u32> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            match self.next()?.to_digit(16) {
+                Some(digit) => unit = unit << 4 | digit,
+                None => return err("isn't valid JSON"),
+            }
+        }
+        Ok(unit)
+    }
+}
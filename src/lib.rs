@@ -1,15 +1,133 @@
+mod audio;
+mod builder;
+mod callgraph;
+mod cartridge;
+mod cdp1802;
+mod coverage;
+mod debugger;
+mod decompile;
+mod disasm;
+mod display;
 mod errors;
+mod extension;
+mod flow;
+mod inspect;
+mod json;
+mod lint;
+mod observer;
+mod octo;
 mod opcodes;
+mod platform;
+mod profile;
+mod romdb;
 mod sprites;
 mod system;
+mod timing;
+mod trace;
+mod vip;
 
-use std::time::Duration;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+pub use crate::audio::{AudioHandler, Sample};
+use crate::audio::{NullAudio, SampleCommand};
+pub use crate::builder::EmulatorBuilder;
+pub use crate::callgraph::CallGraph;
+pub use crate::cartridge::Cartridge;
+pub use crate::coverage::{ByteUse, Coverage};
+pub use crate::debugger::SourceDebugger;
+pub use crate::decompile::{decompile, Syntax};
+pub use crate::disasm::{disassemble, disassemble_program, OpcodeClass};
+pub use crate::display::{BlendMode, Display};
 pub use crate::errors::*;
+use crate::extension::Extensions;
+pub use crate::extension::{Machine, NativeRoutine, OpcodeExtension};
+pub use crate::flow::{BasicBlock, ControlFlowGraph, Edge, EdgeKind, Issue};
+pub use crate::inspect::MachineView;
+pub use crate::lint::{lint, Finding, InstructionSet, LintReport, Severity};
+pub use crate::observer::Observer;
+pub use crate::octo::{assemble, assemble_file, Assembly, SourceError};
 use crate::opcodes::{IOpcodeRunner, OpcodeRunner};
-use crate::system::SystemState;
+pub use crate::platform::{MemoryLayout, Platform, Quirks};
+pub use crate::profile::{Profiler, SubroutineProfile};
+use crate::romdb::ExplicitSettings;
+pub use crate::romdb::{lookup_rom, Colors, RomInfo};
+pub use crate::sprites::Font;
+use crate::system::{MachineConfig, SystemState};
+pub use crate::timing::Timing;
+pub use crate::trace::{compare_traces, Divergence, TraceEntry, TraceFilter, Tracer};
+use crate::vip::VipRunner;
 
-const TIMER_DELTA: Duration = Duration::from_nanos(16_666_666); // 60hz
+const DEFAULT_TIMER_FREQUENCY: u32 = 60;
+const DEFAULT_CLOCK_SPEED: u32 = 1200;
+
+/// What happened during an emulation cycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionStatus {
+    /// An instruction was executed.
+    Executed,
+    /// The program is blocked on `FX0A` until a key is pressed.
+    WaitingForKey,
+    /// The program is blocked after drawing until the next frame, because of the
+    /// display wait quirk.
+    WaitingForVblank,
+    /// The program exited with `00FD`. No more instructions will be executed.
+    Halted,
+    /// The next instruction, at the given address, has a breakpoint. It is executed
+    /// by the following cycle.
+    Breakpoint(u16),
+    /// The program executed a jump to itself, and will do nothing more until an
+    /// interrupt, which CHIP-8 doesn't have. Only the timers are still running.
+    IdleLoop,
+}
+
+/// A condition that stops `Emulator::run_until`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopCondition {
+    /// The program counter reached the given address.
+    PcReached(u16),
+    /// The byte at the given address has the given value.
+    MemoryEquals { address: usize, value: u8 },
+    /// The given number of cycles were run.
+    Cycles(u64),
+    /// The given number of frames (timer ticks, at 60 Hz by default) passed.
+    Frames(u64),
+    /// The given amount of real time passed.
+    Timeout(Duration),
+    /// The program is blocked on `FX0A` until a key is pressed.
+    WaitingForKey,
+    /// The display shown to the user changed.
+    DisplayChanged,
+    /// The program executed a jump to itself.
+    IdleLoop,
+    /// A breakpoint was reached.
+    Breakpoint,
+    /// The program exited with `00FD`. This always stops execution, even if it
+    /// isn't one of the requested conditions.
+    Halted,
+}
+
+/// A copy of the complete state of an emulated machine, taken with
+/// `Emulator::save_state`. It holds everything needed to continue execution
+/// deterministically, including the state of the random number generator, but none
+/// of the host's audio handler, extensions or breakpoints.
+#[derive(Clone)]
+pub struct MachineState {
+    config: MachineConfig,
+    system: SystemState,
+    opcode_runner: Rc<dyn IOpcodeRunner>,
+    program_loaded: bool,
+    rom_info: Option<&'static RomInfo>,
+    resuming_from_breakpoint: bool,
+    timing: Timing,
+    delta_since_timers: Duration,
+    cycles_since_timers: u64,
+    timer_frequency: u32,
+    clock_speed: u32,
+    cycles: u64,
+    frames: u64,
+}
 
 /// A CHIP-8 emulator.
 ///
@@ -37,40 +155,170 @@ const TIMER_DELTA: Duration = Duration::from_nanos(16_666_666); // 60hz
 /// }
 /// ```
 pub struct Emulator {
+    config: MachineConfig,
     system: SystemState,
-    opcode_runner: Box<dyn IOpcodeRunner>,
+    opcode_runner: Rc<dyn IOpcodeRunner>,
     audio: Box<dyn AudioHandler>,
+    extensions: Extensions,
+    observers: Vec<Box<dyn Observer>>,
+    tracer: Option<Tracer>,
+    breakpoints: HashSet<u16>,
+    resuming_from_breakpoint: bool,
     sound_playing: bool,
     program_loaded: bool,
+    rom_info: Option<&'static RomInfo>,
+    rom_database: bool,
+    explicit: ExplicitSettings,
+    timing: Timing,
     delta_since_timers: Duration,
+    cycles_since_timers: u64,
+    timer_frequency: u32,
+    clock_speed: u32,
+    cycles: u64,
+    frames: u64,
 }
 
 impl Default for Emulator {
     fn default() -> Emulator {
-        Emulator::new(Box::new(OpcodeRunner))
+        Emulator::new(MachineConfig::default(), Rc::new(OpcodeRunner))
     }
 }
 
-pub trait AudioHandler {
-    fn start_sound(&mut self);
-    fn stop_sound(&mut self);
-}
-
 impl Emulator {
-    fn new(opcode_runner: Box<dyn IOpcodeRunner>) -> Emulator {
+    fn new(config: MachineConfig, opcode_runner: Rc<dyn IOpcodeRunner>) -> Emulator {
+        let mut system = SystemState::with_config(config);
+        opcode_runner.init(&mut system);
         Emulator {
-            system: SystemState::new(),
+            config,
+            system,
             opcode_runner,
             audio: Box::new(NullAudio),
+            extensions: Extensions::default(),
+            observers: Vec::new(),
+            tracer: None,
+            breakpoints: HashSet::new(),
+            resuming_from_breakpoint: false,
             sound_playing: false,
             program_loaded: false,
+            rom_info: None,
+            rom_database: true,
+            explicit: ExplicitSettings::default(),
+            timing: Timing::default(),
             delta_since_timers: Duration::from_micros(0),
+            cycles_since_timers: 0,
+            timer_frequency: DEFAULT_TIMER_FREQUENCY,
+            clock_speed: DEFAULT_CLOCK_SPEED,
+            cycles: 0,
+            frames: 0,
         }
     }
 
+    /// Creates an emulator for the given platform, which programs in the ROM database
+    /// won't change.
+    pub fn with_platform(platform: Platform) -> Emulator {
+        let config = MachineConfig {
+            platform,
+            ..Default::default()
+        };
+        let mut emulator = Emulator::new(config, Rc::new(OpcodeRunner));
+        emulator.explicit.platform = true;
+        emulator
+    }
+
+    /// Creates an emulator that runs programs on an emulated COSMAC VIP, using the
+    /// original CHIP-8 interpreter. Timing, quirks and machine code calls through
+    /// `0NNN` all behave as on the real machine, at the cost of speed.
+    ///
+    /// Each emulation cycle runs the VIP until the interpreter is ready to fetch the
+    /// next CHIP-8 instruction, or for a frame if the instruction takes longer. An
+    /// `FX0A` waiting for a key reports `ExecutionStatus::WaitingForKey` each frame. The display is the 64x128 output of the VIP's video
+    /// chip, on which the interpreter draws each row four times. Frames are counted
+    /// by the video chip, and the timers are the ones the interpreter keeps and
+    /// decrements in its interrupt routine, so the timing setting has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the monitor ROM is not 512 bytes, or if the interpreter
+    /// is longer than 512 bytes.
+    pub fn cosmac_vip(monitor: &[u8], interpreter: &[u8]) -> std::result::Result<Emulator, Error> {
+        let runner = VipRunner::new(monitor, interpreter)?;
+        let mut emulator = Emulator::new(MachineConfig::default(), Rc::new(runner));
+        emulator.explicit.platform = true;
+        Ok(emulator)
+    }
+
+    /// Creates an emulator that continues from a saved state, with no audio handler,
+    /// extensions, observers or breakpoints.
+    pub fn from_state(state: MachineState) -> Emulator {
+        let mut emulator = Emulator::new(state.config, Rc::clone(&state.opcode_runner));
+        emulator.restore_state(state);
+        emulator
+    }
+
+    /// Copies the state of the emulated machine.
+    pub fn save_state(&self) -> MachineState {
+        MachineState {
+            config: self.config,
+            system: self.system.clone(),
+            opcode_runner: Rc::clone(&self.opcode_runner),
+            program_loaded: self.program_loaded,
+            rom_info: self.rom_info,
+            resuming_from_breakpoint: self.resuming_from_breakpoint,
+            timing: self.timing,
+            delta_since_timers: self.delta_since_timers,
+            cycles_since_timers: self.cycles_since_timers,
+            timer_frequency: self.timer_frequency,
+            clock_speed: self.clock_speed,
+            cycles: self.cycles,
+            frames: self.frames,
+        }
+    }
+
+    /// Replaces the state of the emulated machine with a saved state. The audio
+    /// handler, extensions, observers and breakpoints are kept.
+    pub fn restore_state(&mut self, state: MachineState) {
+        self.config = state.config;
+        self.system = state.system;
+        self.opcode_runner = state.opcode_runner;
+        self.program_loaded = state.program_loaded;
+        self.rom_info = state.rom_info;
+        self.resuming_from_breakpoint = state.resuming_from_breakpoint;
+        self.timing = state.timing;
+        self.delta_since_timers = state.delta_since_timers;
+        self.cycles_since_timers = state.cycles_since_timers;
+        self.timer_frequency = state.timer_frequency;
+        self.clock_speed = state.clock_speed;
+        self.cycles = state.cycles;
+        self.frames = state.frames;
+        self.system.observing = !self.observers.is_empty();
+        self.system.events.clear();
+        // Sound restarts on the next cycle if the restored sound timer is running.
+        self.stop_sound();
+        self.audio.stop_sample();
+    }
+
+    /// Creates a copy of the emulator, which continues exactly as this one would given
+    /// the same input. The copy has the same breakpoints, but no audio handler or
+    /// observers, since those belong to the host.
+    ///
+    /// The copy shares this emulator's opcode extensions and native routines rather
+    /// than copying them, so any state they keep is shared too: running one emulator
+    /// changes what the other's extensions do next. Only extensions without state,
+    /// or whose state doesn't affect the machine, keep the two independent.
+    pub fn fork(&self) -> Emulator {
+        let mut emulator = Emulator::from_state(self.save_state());
+        emulator.breakpoints = self.breakpoints.clone();
+        emulator.extensions = self.extensions.clone();
+        emulator
+    }
+
     /// Loads a program into the emulator. If a program was previously loaded, the
     /// emulator must be reset first.
     ///
+    /// If the program is in the ROM database, the platform, quirks and clock speed it
+    /// needs are used, unless they were chosen when creating the emulator or set since.
+    /// Changing the platform resets the emulator.
+    ///
     /// # Errors
     ///
     /// If the program is invalid, such as being too long, an error is returned.
@@ -81,22 +329,168 @@ impl Emulator {
     /// Panics if a program has already been loaded.
     pub fn load_program(&mut self, program: &[u8]) -> Result {
         assert!(!self.program_loaded);
+        let rom_info = if self.rom_database {
+            romdb::lookup_rom(program)
+        } else {
+            None
+        };
+        self.load_known_program(program, rom_info)
+    }
+
+    /// Loads a program, using what the ROM database knows about it.
+    pub(crate) fn load_known_program(
+        &mut self,
+        program: &[u8],
+        rom_info: Option<&'static RomInfo>,
+    ) -> Result {
+        if let Some(info) = rom_info {
+            // The program is checked against the platform it will run on before any
+            // settings change, so the emulator is left as it was if it doesn't fit.
+            let config = MachineConfig {
+                platform: self.rom_platform(info),
+                ..self.config
+            };
+            if program.len() > config.max_program_size() {
+                return err("program too long");
+            }
+            self.apply_rom_info(info);
+        }
         self.system.load_program(program)?;
+        self.rom_info = rom_info;
         self.program_loaded = true;
         Ok(())
     }
 
+    /// The platform a program from the ROM database runs on.
+    fn rom_platform(&self, info: &RomInfo) -> Platform {
+        let platform_allowed =
+            info.platform == Platform::Chip8 || self.config.layout == MemoryLayout::Separate;
+        if !self.explicit.platform && platform_allowed {
+            info.platform
+        } else {
+            self.config.platform
+        }
+    }
+
+    fn apply_rom_info(&mut self, info: &RomInfo) {
+        let platform = self.rom_platform(info);
+        if platform != self.config.platform {
+            self.config.platform = platform;
+            self.reset();
+        }
+        self.config.quirks = self.explicit.merge_quirks(self.config.quirks, info.quirks);
+        self.system.quirks = self.config.quirks;
+        if let (false, Some(instructions)) =
+            (self.explicit.clock_speed, info.instructions_per_frame)
+        {
+            self.clock_speed = instructions.saturating_mul(self.timer_frequency);
+        }
+    }
+
+    /// What the ROM database knows about the loaded program, if it is in it.
+    pub fn rom_info(&self) -> Option<&'static RomInfo> {
+        self.rom_info
+    }
+
+    /// Sets whether programs are looked up in the ROM database when they are loaded.
+    /// Defaults to true.
+    pub fn set_rom_database(&mut self, enabled: bool) {
+        self.rom_database = enabled;
+    }
+
     /// Perform a hard reset of the emulator state. A program must be reloaded
     /// before executing any cycles.
     pub fn reset(&mut self) {
-        self.system = SystemState::default();
+        self.system = SystemState::with_config(self.config);
+        self.opcode_runner.init(&mut self.system);
+        self.system.observing = !self.observers.is_empty();
         self.program_loaded = false;
-        self.sound_playing = false;
-        self.audio.stop_sound();
+        self.rom_info = None;
+        self.resuming_from_breakpoint = false;
+        self.cycles = 0;
+        self.frames = 0;
+        self.stop_sound();
+        self.audio.stop_sample();
+    }
+
+    /// Sets where the call stack and display are stored, and resets the emulator.
+    ///
+    /// # Errors
+    ///
+    /// The VIP memory layout only supports the standard CHIP-8 display, so an error
+    /// is returned if it is used with the MegaChip8 platform. It also can't be used
+    /// with the COSMAC VIP backend, whose interpreter lays out memory itself.
+    pub fn set_memory_layout(&mut self, layout: MemoryLayout) -> Result {
+        if layout == MemoryLayout::Vip && self.config.platform == Platform::MegaChip8 {
+            return err("the VIP memory layout can't be used with MegaChip8");
+        }
+        if layout != MemoryLayout::Separate && self.system.vip.is_some() {
+            return err("the COSMAC VIP interpreter manages its own memory layout");
+        }
+        self.config.layout = layout;
+        self.reset();
+        Ok(())
+    }
+
+    /// Sets the font used for the hexadecimal digit sprites located by `FX29`, and the
+    /// address it is stored at, and resets the emulator. By default, the Octo font is
+    /// stored at address 0.
+    ///
+    /// Programs running on the COSMAC VIP backend always use the font in the monitor ROM.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the font would not fit below the program start at 0x200.
+    pub fn set_font(&mut self, font: Font, address: u16) -> Result {
+        sprites::check_font_address(address)?;
+        self.config.font = font;
+        self.config.font_address = address;
+        self.reset();
+        Ok(())
+    }
+
+    /// Sets how errors caused by the program are handled. By default, every error
+    /// stops execution.
+    pub fn set_error_policies(&mut self, policies: ErrorPolicies) {
+        self.config.error_policies = policies;
+        self.system.error_policies = policies;
+    }
+
+    /// Takes the warnings recorded for errors whose policy is `ErrorPolicy::Warn`.
+    /// Only a limited number of warnings are kept, so this should be called regularly.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.system.warnings)
+    }
+
+    /// Sets the interpreter behaviors programs may depend on.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.explicit.quirks = ExplicitSettings::ALL_QUIRKS;
+        self.config.quirks = quirks;
+        self.system.quirks = quirks;
+    }
+
+    /// Adds a breakpoint, which stops execution before the instruction at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at `addr`, if any.
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Removes all breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
     }
 
     /// Executes a single emulation cycle of executing an instruction
-    /// and ticking timers.
+    /// and ticking timers, and returns what happened.
+    ///
+    /// No instruction is executed while the program is blocked waiting for a key or
+    /// for the next frame, after it has halted, or when stopping at a breakpoint.
+    /// Timers keep running while the program waits, except at a breakpoint with
+    /// emulated timing, where the cycle is only counted once execution resumes.
     ///
     /// # Errors
     ///
@@ -112,44 +506,449 @@ impl Emulator {
     ///
     /// Panics if a program has not been loaded, or if a reset has occurred and a
     /// new program has not been loaded.
-    pub fn execute_cycle(&mut self, delta_time: Duration) -> Result {
+    pub fn execute_cycle(&mut self, delta_time: Duration) -> Result<ExecutionStatus> {
         assert!(self.program_loaded);
-        if !self.system.waiting_for_key {
-            // Tick timers if necessary.
-            self.delta_since_timers += delta_time;
-            while self.delta_since_timers >= TIMER_DELTA {
-                self.system.tick_timers();
-                self.delta_since_timers -= TIMER_DELTA;
+        if self.system.halted {
+            return Ok(ExecutionStatus::Halted);
+        }
+
+        // Tick timers if necessary. Each tick is the start of a new frame. On the VIP,
+        // the interpreter runs its own timers, and frames are counted after the step.
+        let ticks = match self.timing {
+            _ if self.system.vip.is_some() => 0,
+            Timing::Host => {
+                let timer_delta = Duration::from_secs(1) / self.timer_frequency;
+                self.delta_since_timers += delta_time;
+                let mut ticks = 0;
+                while self.delta_since_timers >= timer_delta {
+                    self.delta_since_timers -= timer_delta;
+                    ticks += 1;
+                }
+                ticks
+            }
+            // The cycle that stopped at a breakpoint is counted when execution resumes.
+            Timing::Emulated if self.resuming_from_breakpoint => 0,
+            Timing::Emulated => {
+                self.cycles_since_timers += u64::from(self.timer_frequency);
+                let ticks = self.cycles_since_timers / u64::from(self.clock_speed);
+                self.cycles_since_timers %= u64::from(self.clock_speed);
+                ticks
+            }
+            // Here, cycles are VIP machine cycles, charged for what this cycle will do.
+            Timing::Vip if self.resuming_from_breakpoint => 0,
+            Timing::Vip => {
+                let frame = u64::from(timing::VIP_FRAME_CYCLES);
+                if self.system.waiting_for_vblank {
+                    // Skip to the start of the next frame.
+                    self.cycles_since_timers = frame;
+                }
+                self.cycles_since_timers += u64::from(if self.system.waiting_for_key {
+                    timing::VIP_KEY_WAIT_CYCLES
+                } else {
+                    let opcode = self.view().next_opcode().unwrap_or(0);
+                    timing::vip_instruction_cycles(&self.system, opcode)
+                });
+                let ticks = self.cycles_since_timers / frame;
+                self.cycles_since_timers %= frame;
+                ticks
+            }
+        };
+        for _ in 0..ticks {
+            self.system.tick_timers();
+            for observer in self.observers.iter_mut() {
+                observer.timers_ticked(self.system.delay_timer, self.system.sound_timer);
+            }
+            self.system.waiting_for_vblank = false;
+            self.frames += 1;
+        }
+        // The VIP interpreter waits for keys itself, so it keeps running.
+        if self.system.waiting_for_key && self.system.vip.is_none() {
+            self.update_sound();
+            return Ok(ExecutionStatus::WaitingForKey);
+        }
+        if self.system.waiting_for_vblank {
+            self.update_sound();
+            return Ok(ExecutionStatus::WaitingForVblank);
+        }
+
+        // On the VIP, an instruction such as `FX0A` can take more than one cycle. It
+        // is only counted, traced and reported in the cycle it starts.
+        let starting = match &self.system.vip {
+            Some(vip) => !vip.mid_instruction(),
+            None => true,
+        };
+        let pc = self.system.program_counter;
+        if starting && self.breakpoints.contains(&pc) && !self.resuming_from_breakpoint {
+            self.resuming_from_breakpoint = true;
+            return Ok(ExecutionStatus::Breakpoint(pc));
+        }
+        self.resuming_from_breakpoint = false;
+
+        // Run opcode
+        if let (true, Some(tracer)) = (starting, &mut self.tracer) {
+            tracer.trace(self.cycles, &self.system)?;
+        }
+        if starting {
+            self.cycles += 1;
+        }
+        let vip_frames = self.system.vip.as_ref().map(|vip| vip.frames());
+        if self.system.observing && starting {
+            let opcode = self.view().next_opcode().unwrap_or(0);
+            let result = self
+                .opcode_runner
+                .step(&mut self.system, &mut self.extensions);
+            if let Err(e) = result {
+                self.system.events.clear();
+                return Err(e);
+            }
+            self.notify_executed(pc, opcode);
+        } else {
+            self.opcode_runner
+                .step(&mut self.system, &mut self.extensions)?;
+        }
+        if let (Some(before), Some(vip)) = (vip_frames, &self.system.vip) {
+            let frames = vip.frames() - before;
+            for _ in 0..frames {
+                for observer in self.observers.iter_mut() {
+                    observer.timers_ticked(self.system.delay_timer, self.system.sound_timer);
+                }
+            }
+            self.frames += frames;
+        }
+
+        self.update_sound();
+        match self.system.sample_command.take() {
+            Some(SampleCommand::Play(sample)) => self.audio.play_sample(&sample),
+            Some(SampleCommand::Stop) => self.audio.stop_sample(),
+            None => {}
+        }
+
+        Ok(if self.system.halted {
+            ExecutionStatus::Halted
+        } else if self.system.waiting_for_key {
+            ExecutionStatus::WaitingForKey
+        } else if self.system.waiting_for_vblank {
+            ExecutionStatus::WaitingForVblank
+        } else if self.system.program_counter == pc {
+            ExecutionStatus::IdleLoop
+        } else {
+            ExecutionStatus::Executed
+        })
+    }
+
+    /// Passes the instruction just executed, and the events it caused, to observers.
+    fn notify_executed(&mut self, pc: u16, opcode: u16) {
+        for observer in self.observers.iter_mut() {
+            observer.instruction_executed(pc, opcode);
+        }
+        for event in self.system.events.drain(..) {
+            for observer in self.observers.iter_mut() {
+                event.dispatch(observer.as_mut());
+            }
+        }
+    }
+
+    /// Starts or stops the tone to match the sound timer.
+    fn update_sound(&mut self) {
+        if self.system.sound_timer > 0 && !self.sound_playing && !self.system.halted {
+            self.sound_playing = true;
+            self.audio.start_sound();
+            for observer in self.observers.iter_mut() {
+                observer.sound_started();
+            }
+        } else if self.system.sound_timer == 0 || self.system.halted {
+            self.stop_sound();
+        }
+    }
+
+    fn stop_sound(&mut self) {
+        if self.sound_playing {
+            self.sound_playing = false;
+            self.audio.stop_sound();
+            for observer in self.observers.iter_mut() {
+                observer.sound_stopped();
             }
+        }
+    }
+
+    /// Sets the number of cycles per second assumed by `run_until` and by emulated
+    /// timing, which determines how fast the timers run relative to the program.
+    /// Defaults to 1200 Hz.
+    ///
+    /// # Panics
+    ///
+    /// Panics if hz is 0.
+    pub fn set_clock_speed(&mut self, hz: u32) {
+        assert!(hz > 0);
+        self.explicit.clock_speed = true;
+        self.clock_speed = hz;
+    }
+
+    /// The number of cycles per second assumed by `run_until` and by emulated timing.
+    pub fn clock_speed(&self) -> u32 {
+        self.clock_speed
+    }
+
+    /// Sets how time passes for the emulated machine. Defaults to `Timing::Host`.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// How time passes for the emulated machine.
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Sets how many times per second the delay and sound timers count down, which is
+    /// also the frame rate. Defaults to 60 Hz.
+    ///
+    /// # Panics
+    ///
+    /// Panics if hz is 0.
+    pub fn set_timer_frequency(&mut self, hz: u32) {
+        assert!(hz > 0);
+        self.timer_frequency = hz;
+    }
 
-            // Run opcode
-            let opcode = self.system.next_opcode();
-            self.opcode_runner.run(&mut self.system, opcode)?;
-
-            // Play sounds
-            if self.system.sound_timer > 0 && !self.sound_playing {
-                self.sound_playing = true;
-                self.audio.start_sound();
-            } else if self.system.sound_timer == 0 && self.sound_playing {
-                self.sound_playing = false;
-                self.audio.stop_sound();
+    /// How many times per second the delay and sound timers count down.
+    pub fn timer_frequency(&self) -> u32 {
+        self.timer_frequency
+    }
+
+    /// The number of instructions executed since the emulator was reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The number of frames (timer ticks, at 60 Hz by default) since the emulator was
+    /// reset.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Runs cycles at the clock speed set with `set_clock_speed`, without waiting
+    /// between them, until one of the conditions is met. Returns the condition that
+    /// stopped execution. Conditions are checked after every cycle, so at least one
+    /// cycle is always run.
+    ///
+    /// Without a `Cycles`, `Frames` or `Timeout` condition, this may never return.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the program causes one, as with `execute_cycle`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a program has not been loaded.
+    pub fn run_until(&mut self, conditions: &[StopCondition]) -> Result<StopCondition> {
+        let delta = Duration::from_secs(1) / self.clock_speed;
+        let (start_frames, start_time) = (self.frames, Instant::now());
+        let initial_display = if conditions.contains(&StopCondition::DisplayChanged) {
+            Some(self.get_display().clone())
+        } else {
+            None
+        };
+        let mut run = 0;
+        loop {
+            let status = self.execute_cycle(delta)?;
+            run += 1;
+            if status == ExecutionStatus::Halted {
+                return Ok(StopCondition::Halted);
             }
+            for &condition in conditions {
+                let met = match condition {
+                    StopCondition::PcReached(pc) => self.system.program_counter == pc,
+                    StopCondition::MemoryEquals { address, value } => {
+                        self.system.memory.get(address) == Some(&value)
+                    }
+                    // Cycles spent blocked count towards the budget too, so that a
+                    // program waiting for a key is still stopped.
+                    StopCondition::Cycles(n) => run >= n,
+                    StopCondition::Frames(n) => self.frames - start_frames >= n,
+                    StopCondition::Timeout(timeout) => start_time.elapsed() >= timeout,
+                    StopCondition::WaitingForKey => status == ExecutionStatus::WaitingForKey,
+                    StopCondition::DisplayChanged => {
+                        initial_display.as_ref() != Some(self.get_display())
+                    }
+                    StopCondition::IdleLoop => status == ExecutionStatus::IdleLoop,
+                    StopCondition::Breakpoint => {
+                        matches!(status, ExecutionStatus::Breakpoint(_))
+                    }
+                    StopCondition::Halted => false,
+                };
+                if met {
+                    return Ok(condition);
+                }
+            }
+        }
+    }
+
+    /// Gets a reference to the system display. In MegaChip mode, this is the last
+    /// frame presented by the program, and its size changes when the program switches
+    /// in or out of MegaChip mode.
+    pub fn get_display(&self) -> &Display {
+        self.system.visible_display()
+    }
+
+    /// Gets a read-only view of the machine state, such as the registers and memory.
+    pub fn view(&self) -> MachineView<'_> {
+        MachineView::new(&self.system)
+    }
+
+    /// Sets register `VX`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if x is greater than 15, or when running on the COSMAC VIP
+    /// backend, which keeps the registers in the interpreter's own memory.
+    pub fn set_register(&mut self, x: u8, value: u8) -> Result {
+        self.check_pokeable()?;
+        if x > 0xF {
+            return err(&format!("invalid register: V{}", x));
         }
+        self.system.registers[usize::from(x)] = value;
         Ok(())
     }
 
-    /// Gets a reference to the system display
-    pub fn get_display(&self) -> &[[bool; 64]] {
-        &self.system.display
+    /// Sets the address register, `I`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address is larger than the platform can address, or
+    /// when running on the COSMAC VIP backend.
+    pub fn set_address_register(&mut self, value: u32) -> Result {
+        self.check_pokeable()?;
+        if value & self.system.address_mask() != value {
+            return err(&format!("invalid address: 0x{:X}", value));
+        }
+        self.system.address_register = value;
+        Ok(())
+    }
+
+    /// Sets the address of the next instruction. Instructions are decoded as they
+    /// are executed, so execution continues from the new address immediately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if programs may not access the address, or when running on
+    /// the COSMAC VIP backend.
+    pub fn set_program_counter(&mut self, pc: u16) -> Result {
+        self.check_pokeable()?;
+        self.system.check_address(usize::from(pc), 2)?;
+        self.system.program_counter = pc;
+        self.resuming_from_breakpoint = false;
+        Ok(())
+    }
+
+    /// Sets the delay timer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when running on the COSMAC VIP backend.
+    pub fn set_delay_timer(&mut self, value: u8) -> Result {
+        self.check_pokeable()?;
+        self.system.delay_timer = value;
+        Ok(())
+    }
+
+    /// Sets the sound timer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when running on the COSMAC VIP backend.
+    pub fn set_sound_timer(&mut self, value: u8) -> Result {
+        self.check_pokeable()?;
+        self.system.sound_timer = value;
+        Ok(())
+    }
+
+    /// Writes bytes to memory. With the VIP memory layout, writes to the stack or
+    /// display regions also update the stack or display. Patched instructions take
+    /// effect the next time they are executed. Observers aren't told about these
+    /// writes, as the program didn't make them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if programs may not access the whole range.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result {
+        self.system.check_address(addr, bytes.len())?;
+        let observing = self.system.observing;
+        self.system.observing = false;
+        self.system.write_memory(addr, bytes);
+        self.system.observing = observing;
+        Ok(())
+    }
+
+    /// Pushes a return address onto the call stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stack is full, or when running on the COSMAC VIP backend.
+    pub fn push_stack(&mut self, addr: u16) -> Result {
+        self.check_pokeable()?;
+        self.system.push_stack(addr)
+    }
+
+    /// Pops a return address from the call stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stack is empty, or when running on the COSMAC VIP backend.
+    pub fn pop_stack(&mut self) -> Result<u16> {
+        self.check_pokeable()?;
+        self.system.pop_stack()
+    }
+
+    // The COSMAC VIP backend keeps the interpreter's state in the emulated machine,
+    // and overwrites the emulator's copy of it after every instruction.
+    fn check_pokeable(&self) -> Result {
+        if self.system.vip.is_some() {
+            return err("the COSMAC VIP interpreter's state can only be changed through memory");
+        }
+        Ok(())
     }
 
     /// Set the audio handler that will receive callbacks for when to start and stop
     /// audio. CHIP-8 is a simple system and only supports a single tone which may only
-    /// be started or stopped.
+    /// be started or stopped. MegaChip8 programs may also play digitized samples.
     pub fn set_audio_handler(&mut self, audio: Box<dyn AudioHandler>) {
         self.audio = audio;
     }
 
+    /// Adds an observer that is told about instructions, memory accesses, drawing,
+    /// sound and timers as the program runs. Observers are called in the order they
+    /// were added. While no observers are attached, no events are recorded.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+        self.system.observing = true;
+    }
+
+    /// Sets where a trace of the instructions executed is written, or stops tracing.
+    /// Each instruction is written as a line before it runs, in the format described
+    /// by `TraceEntry`.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Adds an extension implementing instructions the emulator doesn't define.
+    /// Extensions are tried in the order they were added.
+    ///
+    /// Extensions are not used by the COSMAC VIP backend, which runs the original
+    /// interpreter.
+    pub fn add_opcode_extension(&mut self, extension: Box<dyn OpcodeExtension>) {
+        self.extensions.add_opcode_extension(extension);
+    }
+
+    /// Sets a native routine to run when the program calls machine code at `addr`
+    /// with `0NNN`, replacing any routine previously set for that address. Calls to
+    /// addresses without a routine are ignored.
+    ///
+    /// Native routines are not used by the COSMAC VIP backend, which runs the
+    /// machine code itself.
+    pub fn set_native_routine(&mut self, addr: u16, routine: Box<dyn NativeRoutine>) {
+        self.extensions.set_native_routine(addr, routine);
+    }
+
     /// Callback for keyboard input when a keypad key is pressed.
     ///
     /// # Panics
@@ -175,13 +974,6 @@ impl Emulator {
     }
 }
 
-struct NullAudio;
-
-impl AudioHandler for NullAudio {
-    fn start_sound(&mut self) {}
-    fn stop_sound(&mut self) {}
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,7 +985,7 @@ mod tests {
     }
 
     impl IOpcodeRunner for MockOpcodeRunner {
-        fn run(&self, _: &mut SystemState, opcode: u16) -> Result {
+        fn run(&self, _: &mut SystemState, _: &mut Extensions, opcode: u16) -> Result {
             *self.last_opcode.borrow_mut() = Some(opcode);
             Ok(())
         }
@@ -205,7 +997,7 @@ mod tests {
         let mock_runner = MockOpcodeRunner {
             last_opcode: Rc::clone(&last_opcode),
         };
-        let mut emulator = Emulator::new(Box::new(mock_runner));
+        let mut emulator = Emulator::new(MachineConfig::default(), Rc::new(mock_runner));
         emulator.load_program(&[0x01, 0x02, 0x03, 0x04]).unwrap();
         emulator.system.delay_timer = 10;
 
@@ -217,4 +1009,368 @@ mod tests {
         assert_eq!(*last_opcode.borrow(), Some(0x0304));
         assert_eq!(emulator.system.delay_timer, 8);
     }
+
+    fn run_program(program: &[u8]) -> Emulator {
+        let mut emulator = Emulator::default();
+        emulator.load_program(program).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn execution_status() {
+        // LD V0, 1; JP 0x202
+        let mut emulator = run_program(&[0x60, 0x01, 0x12, 0x02]);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Executed);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::IdleLoop);
+
+        let mut emulator = run_program(&[0x00, 0xFD, 0x60, 0x01]);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Halted);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(emulator.system.registers[0], 0);
+
+        let mut emulator = run_program(&[0xF0, 0x0A]);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::WaitingForKey);
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::WaitingForKey);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut emulator = run_program(&[0x60, 0x01, 0x60, 0x02, 0x60, 0x03]);
+        emulator.add_breakpoint(0x202);
+        let cycle = |emulator: &mut Emulator| emulator.execute_cycle(Duration::from_millis(0));
+        assert_eq!(cycle(&mut emulator).unwrap(), ExecutionStatus::Executed);
+        assert_eq!(
+            cycle(&mut emulator).unwrap(),
+            ExecutionStatus::Breakpoint(0x202)
+        );
+        assert_eq!(emulator.system.registers[0], 1);
+        assert_eq!(cycle(&mut emulator).unwrap(), ExecutionStatus::Executed);
+        assert_eq!(emulator.system.registers[0], 2);
+    }
+
+    #[test]
+    fn display_wait() {
+        // DRW V0, V0, 1; DRW V0, V0, 1
+        let mut emulator = run_program(&[0xD0, 0x01, 0xD0, 0x01]);
+        emulator.set_quirks(Quirks {
+            display_wait: true,
+            ..Default::default()
+        });
+        let status = emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::WaitingForVblank);
+        let status = emulator.execute_cycle(Duration::from_millis(10)).unwrap();
+        assert_eq!(status, ExecutionStatus::WaitingForVblank);
+        assert_eq!(emulator.system.program_counter, 0x202);
+        let status = emulator.execute_cycle(Duration::from_millis(10)).unwrap();
+        assert_eq!(status, ExecutionStatus::WaitingForVblank);
+        assert_eq!(emulator.system.program_counter, 0x204);
+    }
+
+    #[test]
+    fn run_until_conditions() {
+        // LD V0, 5; LD I, 0x300; LD [I], V0; LD F, V0; DRW V0, V0, 1; JP 0x20A
+        let program = [
+            0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x29, 0xD0, 0x01, 0x12, 0x0A,
+        ];
+        let mut emulator = run_program(&program);
+        let memory = StopCondition::MemoryEquals {
+            address: 0x300,
+            value: 5,
+        };
+        let pc = StopCondition::PcReached(0x202);
+        for &condition in &[pc, memory, StopCondition::DisplayChanged] {
+            let conditions = [condition, StopCondition::IdleLoop];
+            assert_eq!(emulator.run_until(&conditions).unwrap(), condition);
+        }
+        assert_eq!(emulator.system.program_counter, 0x20A);
+        let conditions = [StopCondition::IdleLoop, StopCondition::Cycles(3)];
+        assert_eq!(
+            emulator.run_until(&conditions).unwrap(),
+            StopCondition::IdleLoop
+        );
+    }
+
+    #[test]
+    fn run_until_budgets() {
+        // Blocked cycles count towards the budget, and the timers keep running.
+        let mut emulator = run_program(&[0xF0, 0x0A]);
+        let conditions = [StopCondition::Cycles(10), StopCondition::Frames(1)];
+        assert_eq!(
+            emulator.run_until(&conditions).unwrap(),
+            StopCondition::Cycles(10)
+        );
+        assert_eq!(emulator.cycles(), 1);
+        let conditions = [StopCondition::Cycles(100), StopCondition::Frames(1)];
+        assert_eq!(
+            emulator.run_until(&conditions).unwrap(),
+            StopCondition::Frames(1)
+        );
+
+        let mut emulator = run_program(&[0x12, 0x00]);
+        emulator.set_clock_speed(600);
+        assert_eq!(
+            emulator.run_until(&conditions).unwrap(),
+            StopCondition::Frames(1)
+        );
+        assert_eq!(emulator.frames(), 1);
+
+        let mut emulator = run_program(&[0x12, 0x00]);
+        let timeout = StopCondition::Timeout(Duration::from_millis(1));
+        assert_eq!(emulator.run_until(&[timeout]).unwrap(), timeout);
+
+        let mut emulator = run_program(&[0x00, 0xFD]);
+        assert_eq!(
+            emulator.run_until(&[StopCondition::Cycles(10)]).unwrap(),
+            StopCondition::Halted
+        );
+    }
+
+    #[test]
+    fn poke_state() {
+        // LD V1, V0; RET
+        let mut emulator = run_program(&[0x81, 0x00, 0x00, 0xEE]);
+        emulator.set_register(0, 0x42).unwrap();
+        assert!(emulator.set_register(16, 0).is_err());
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(emulator.view().registers()[1], 0x42);
+
+        emulator.push_stack(0x200).unwrap();
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(emulator.view().program_counter(), 0x200);
+        assert!(emulator.pop_stack().is_err());
+
+        emulator.write_memory(0x200, &[0x62, 0x07]).unwrap();
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(emulator.view().registers()[2], 0x07);
+
+        assert!(emulator.write_memory(0x100, &[0]).is_err());
+        assert!(emulator.write_memory(0xFFE, &[0, 0]).is_err());
+        assert!(emulator.set_program_counter(0x1FE).is_err());
+        assert!(emulator.set_address_register(0x1_0000).is_err());
+        emulator.set_program_counter(0x300).unwrap();
+        emulator.set_address_register(0xFFFF).unwrap();
+        emulator.set_delay_timer(10).unwrap();
+        emulator.set_sound_timer(20).unwrap();
+        let view = emulator.view();
+        assert_eq!(view.program_counter(), 0x300);
+        assert_eq!(view.address_register(), 0xFFFF);
+        assert_eq!((view.delay_timer(), view.sound_timer()), (10, 20));
+    }
+
+    #[test]
+    fn forks_are_deterministic() {
+        // RND V0, 0xFF; LD V1, K; RND V2, 0xFF
+        let mut emulator = run_program(&[0xC0, 0xFF, 0xF1, 0x0A, 0xC2, 0xFF]);
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+
+        let mut fork = emulator.fork();
+        let saved = emulator.save_state();
+        for emulator in [&mut emulator, &mut fork].iter_mut() {
+            emulator.on_key_down(5);
+            emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+        }
+        assert_eq!(emulator.view().registers(), fork.view().registers());
+        assert_eq!(fork.view().registers()[1], 5);
+
+        emulator.restore_state(saved);
+        emulator.on_key_down(7);
+        emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+        assert_eq!(emulator.view().registers()[1], 7);
+        assert_eq!(emulator.view().registers()[2], fork.view().registers()[2]);
+    }
+
+    #[test]
+    fn forks_keep_native_routines() {
+        // SYS 0x123; JP 0x200
+        let mut emulator = run_program(&[0x01, 0x23, 0x12, 0x00]);
+        emulator.set_native_routine(
+            0x123,
+            Box::new(|machine: &mut Machine| {
+                let v0 = machine.register(0);
+                machine.set_register(0, v0 + 1);
+                Ok(())
+            }),
+        );
+        emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+
+        let mut fork = emulator.fork();
+        for emulator in [&mut emulator, &mut fork].iter_mut() {
+            emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+            assert_eq!(emulator.view().registers()[0], 2);
+        }
+    }
+
+    #[test]
+    fn forks_share_extension_state() {
+        // SYS 0x123; JP 0x200
+        let mut emulator = run_program(&[0x01, 0x23, 0x12, 0x00]);
+        let mut calls = 0;
+        emulator.set_native_routine(
+            0x123,
+            Box::new(move |machine: &mut Machine| {
+                calls += 1;
+                machine.set_register(0, calls);
+                Ok(())
+            }),
+        );
+        emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+        assert_eq!(emulator.view().registers()[0], 1);
+
+        // The fork's call counts towards the routine the original calls next.
+        let mut fork = emulator.fork();
+        fork.run_until(&[StopCondition::Cycles(2)]).unwrap();
+        assert_eq!(fork.view().registers()[0], 2);
+        emulator.run_until(&[StopCondition::Cycles(2)]).unwrap();
+        assert_eq!(emulator.view().registers()[0], 3);
+    }
+
+    #[test]
+    fn font_must_fit_below_program() {
+        let mut emulator = Emulator::default();
+        assert!(emulator.set_font(Font::VIP, 0x1B0).is_ok());
+        assert_eq!(emulator.system.get_sprite_location(0).unwrap(), 0x1B0);
+        assert!(emulator.set_font(Font::VIP, 0x1B1).is_err());
+    }
+
+    #[derive(Default)]
+    struct EventLog(Vec<String>);
+
+    impl Observer for EventLog {
+        fn instruction_executed(&mut self, pc: u16, opcode: u16) {
+            self.0.push(format!("{:04X}: {:04X}", pc, opcode));
+        }
+
+        fn memory_read(&mut self, address: usize, value: u8) {
+            self.0.push(format!("read {:X}={:X}", address, value));
+        }
+
+        fn memory_written(&mut self, address: usize, value: u8) {
+            self.0.push(format!("write {:X}={:X}", address, value));
+        }
+
+        fn sprite_drawn(&mut self, x: u8, y: u8, rows: usize, collision: bool) {
+            self.0
+                .push(format!("sprite {},{} {} {}", x, y, rows, collision));
+        }
+
+        fn screen_cleared(&mut self) {
+            self.0.push("cls".to_string());
+        }
+
+        fn sound_started(&mut self) {
+            self.0.push("sound on".to_string());
+        }
+
+        fn sound_stopped(&mut self) {
+            self.0.push("sound off".to_string());
+        }
+
+        fn timers_ticked(&mut self, delay_timer: u8, sound_timer: u8) {
+            self.0.push(format!("tick {} {}", delay_timer, sound_timer));
+        }
+    }
+
+    #[test]
+    fn observers() {
+        // CLS; LD I, 0x20A; DRW V0, V1, 1; LD B, V0; JP 0x208; sprite data
+        let mut emulator = run_program(&[
+            0x00, 0xE0, 0xA2, 0x0A, 0xD0, 0x11, 0xF0, 0x33, 0x12, 0x08, 0x80,
+        ]);
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        emulator.add_observer(Box::new(Rc::clone(&log)));
+        emulator.run_until(&[StopCondition::Cycles(4)]).unwrap();
+        assert_eq!(
+            log.borrow().0,
+            [
+                "0200: 00E0",
+                "cls",
+                "0202: A20A",
+                "0204: D011",
+                "read 20A=80",
+                "sprite 0,0 1 false",
+                "0206: F033",
+                "write 20A=0",
+                "write 20B=0",
+                "write 20C=0",
+            ]
+        );
+
+        // Writes by the host aren't reported along with the next instruction.
+        log.borrow_mut().0.clear();
+        emulator.write_memory(0x300, &[1]).unwrap();
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        assert_eq!(log.borrow().0, ["0208: 1208"]);
+
+        log.borrow_mut().0.clear();
+        emulator.set_sound_timer(2).unwrap();
+        emulator.execute_cycle(Duration::from_millis(0)).unwrap();
+        emulator.execute_cycle(Duration::from_millis(40)).unwrap();
+        assert_eq!(
+            log.borrow().0,
+            [
+                "0208: 1208",
+                "sound on",
+                "tick 0 1",
+                "tick 0 0",
+                "0208: 1208",
+                "sound off",
+            ]
+        );
+    }
+
+    #[test]
+    fn emulated_timing() {
+        // LD V0, K; JP 0x202
+        let mut emulator = run_program(&[0xF0, 0x0A, 0x12, 0x02]);
+        emulator.set_timing(Timing::Emulated);
+        emulator.set_clock_speed(600);
+        emulator.set_delay_timer(10).unwrap();
+        for _ in 0..25 {
+            let status = emulator.execute_cycle(Duration::from_secs(1)).unwrap();
+            assert_eq!(status, ExecutionStatus::WaitingForKey);
+        }
+        // Timers keep running while waiting for a key.
+        assert_eq!(emulator.frames(), 2);
+        assert_eq!(emulator.view().delay_timer(), 8);
+
+        // Stopping at a breakpoint doesn't take an extra cycle.
+        emulator.add_breakpoint(0x202);
+        emulator.on_key_down(1);
+        emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+        let status = emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Breakpoint(0x202));
+        emulator.remove_breakpoint(0x202);
+        for _ in 0..4 {
+            emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+        }
+        assert_eq!(emulator.cycles(), 6);
+        assert_eq!(emulator.frames(), 3);
+
+        emulator.set_timer_frequency(100);
+        emulator.run_until(&[StopCondition::Cycles(60)]).unwrap();
+        assert_eq!(emulator.frames(), 13);
+    }
+
+    #[test]
+    fn vip_timing() {
+        // LD V0, 0x12; JP 0x202
+        let mut emulator = run_program(&[0x60, 0x12, 0x12, 0x02]);
+        emulator.set_timing(Timing::Vip);
+        emulator.run_until(&[StopCondition::Frames(1)]).unwrap();
+        assert_eq!(emulator.cycles(), 33);
+
+        // LD V0, K
+        let mut emulator = run_program(&[0xF0, 0x0A]);
+        emulator.set_timing(Timing::Vip);
+        emulator.run_until(&[StopCondition::Frames(1)]).unwrap();
+        assert_eq!(emulator.cycles(), 1);
+        assert_eq!(emulator.frames(), 1);
+    }
 }
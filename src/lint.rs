@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::platform::Platform;
+use crate::system::PROGRAM_START;
+
+/// A family of CHIP-8 instructions, named after the interpreter that introduced them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InstructionSet {
+    /// The original instructions of the COSMAC VIP interpreter.
+    Chip8,
+    /// The 64x64 hi-res variant of the VIP interpreter, which programs start by
+    /// jumping to 0x260.
+    HiresVip,
+    /// SUPER-CHIP 1.1, which adds a 128x64 mode, scrolling and large sprites.
+    SuperChip,
+    /// Octo's XO-CHIP, which adds a second bitplane, audio patterns and more memory.
+    XoChip,
+    /// MegaChip8, which adds a 256-color display and sampled sound.
+    MegaChip8,
+}
+
+impl InstructionSet {
+    pub fn name(self) -> &'static str {
+        match self {
+            InstructionSet::Chip8 => "CHIP-8",
+            InstructionSet::HiresVip => "hi-res CHIP-8",
+            InstructionSet::SuperChip => "SUPER-CHIP",
+            InstructionSet::XoChip => "XO-CHIP",
+            InstructionSet::MegaChip8 => "MegaChip8",
+        }
+    }
+
+    /// The platform this emulator runs the instruction set on, if it supports it.
+    pub fn platform(self) -> Option<Platform> {
+        match self {
+            InstructionSet::Chip8 => Some(Platform::Chip8),
+            InstructionSet::MegaChip8 => Some(Platform::MegaChip8),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The instruction behaves differently between interpreters, so the program may
+    /// need a particular one.
+    Quirk,
+    /// The program probably doesn't do what it means to.
+    Warning,
+}
+
+/// Something the linter found, at the address of the instruction responsible.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub address: u16,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Quirk => "quirk",
+            Severity::Warning => "warning",
+        };
+        write!(f, "0x{:03X}: {}: {}", self.address, severity, self.message)
+    }
+}
+
+/// What the linter found in a program.
+///
+/// The `Display` implementation formats a text report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintReport {
+    /// The instruction sets the program's instructions come from.
+    pub instruction_sets: BTreeSet<InstructionSet>,
+    /// Findings, in order of address.
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    /// The platform to run the program on. This is CHIP-8 unless the program needs
+    /// MegaChip8, including when it needs an instruction set that isn't supported.
+    pub fn platform(&self) -> Platform {
+        if self.instruction_sets.contains(&InstructionSet::MegaChip8) {
+            Platform::MegaChip8
+        } else {
+            Platform::Chip8
+        }
+    }
+
+    /// The instruction sets the program needs that this emulator can't run.
+    pub fn unsupported(&self) -> Vec<InstructionSet> {
+        self.instruction_sets
+            .iter()
+            .cloned()
+            .filter(|set| set.platform().is_none())
+            .collect()
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sets: Vec<&str> = self.instruction_sets.iter().map(|s| s.name()).collect();
+        writeln!(f, "instruction sets: {}", sets.join(", "))?;
+        for finding in self.findings.iter() {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// How an instruction passes control on.
+enum Flow {
+    Next,
+    Jump(u16),
+    Call(u16),
+    Skip,
+    Stop,
+}
+
+/// Decodes an instruction, returning the instruction set it belongs to, its length and
+/// where it goes next. Returns `None` if no instruction set has it.
+fn decode(opcode: u16, megachip: bool) -> Option<(InstructionSet, u16, Flow)> {
+    use self::InstructionSet::*;
+
+    let nnn = opcode & 0xFFF;
+    let decoded = match (
+        opcode >> 12,
+        (opcode >> 8) & 0xF,
+        (opcode >> 4) & 0xF,
+        opcode & 0xF,
+    ) {
+        (0x0, 0x0, 0xE, 0x0) => (Chip8, 2, Flow::Next),
+        (0x0, 0x0, 0xE, 0xE) => (Chip8, 2, Flow::Stop),
+        (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB) | (0x0, 0x0, 0xF, 0xC) => {
+            (SuperChip, 2, Flow::Next)
+        }
+        (0x0, 0x0, 0xF, 0xE) | (0x0, 0x0, 0xF, 0xF) => (SuperChip, 2, Flow::Next),
+        (0x0, 0x0, 0xF, 0xD) => (SuperChip, 2, Flow::Stop),
+        (0x0, 0x0, 0xD, _) => (XoChip, 2, Flow::Next),
+        (0x0, 0x0, 0x1, 0x0) | (0x0, 0x0, 0x1, 0x1) if megachip => (MegaChip8, 2, Flow::Next),
+        (0x0, 0x1, _, _) if megachip => (MegaChip8, 4, Flow::Next),
+        (0x0, 0x2, _, _) | (0x0, 0x3, _, _) | (0x0, 0x4, _, _) | (0x0, 0x9, _, _) if megachip => {
+            (MegaChip8, 2, Flow::Next)
+        }
+        (0x0, 0x6, 0x0, _) | (0x0, 0x7, 0x0, 0x0) | (0x0, 0x8, 0x0, _) if megachip => {
+            (MegaChip8, 2, Flow::Next)
+        }
+        // Machine code routines.
+        (0x0, _, _, _) => (Chip8, 2, Flow::Next),
+        (0x1, _, _, _) => (Chip8, 2, Flow::Jump(nnn)),
+        (0x2, _, _, _) => (Chip8, 2, Flow::Call(nnn)),
+        (0x3, _, _, _) | (0x4, _, _, _) => (Chip8, 2, Flow::Skip),
+        (0x5, _, _, 0x0) | (0x9, _, _, 0x0) => (Chip8, 2, Flow::Skip),
+        (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => (XoChip, 2, Flow::Next),
+        (0x6, _, _, _) | (0x7, _, _, _) => (Chip8, 2, Flow::Next),
+        (0x8, _, _, 0x0..=0x7) | (0x8, _, _, 0xE) => (Chip8, 2, Flow::Next),
+        (0xA, _, _, _) | (0xC, _, _, _) => (Chip8, 2, Flow::Next),
+        // The target of a computed jump isn't known.
+        (0xB, _, _, _) => (Chip8, 2, Flow::Stop),
+        (0xD, _, _, 0x0) => (SuperChip, 2, Flow::Next),
+        (0xD, _, _, _) => (Chip8, 2, Flow::Next),
+        (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => (Chip8, 2, Flow::Skip),
+        (0xF, 0x0, 0x0, 0x0) => (XoChip, 4, Flow::Next),
+        (0xF, _, 0x0, 0x1) | (0xF, 0x0, 0x0, 0x2) | (0xF, _, 0x3, 0xA) => (XoChip, 2, Flow::Next),
+        (0xF, _, 0x0, 0x7) | (0xF, _, 0x0, 0xA) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) => {
+            (Chip8, 2, Flow::Next)
+        }
+        (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x3) => (Chip8, 2, Flow::Next),
+        (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => (Chip8, 2, Flow::Next),
+        (0xF, _, 0x3, 0x0) | (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => (SuperChip, 2, Flow::Next),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/// Whether a `0NNN` instruction calls a machine code routine, rather than being one
+/// of the instructions later interpreters added in its place.
+fn is_machine_code(opcode: u16) -> bool {
+    opcode & 0xF000 == 0
+        && opcode != 0x00E0
+        && opcode != 0x00EE
+        && decode(opcode, false).map(|(set, _, _)| set) == Some(InstructionSet::Chip8)
+}
+
+/// A difference between interpreters that an instruction depends on.
+fn quirk(opcode: u16) -> Option<&'static str> {
+    match (opcode >> 12, opcode & 0xF) {
+        (0x8, 0x6) | (0x8, 0xE) => Some(
+            "8XY6 and 8XYE shift VY into VX on the COSMAC VIP, but shift VX in place on \
+             SUPER-CHIP",
+        ),
+        (0x8, 0x1) | (0x8, 0x2) | (0x8, 0x3) => {
+            Some("8XY1, 8XY2 and 8XY3 reset VF on the COSMAC VIP")
+        }
+        (0xB, _) => Some("BNNN jumps to NNN + V0 on the COSMAC VIP, but to XNN + VX on SUPER-CHIP"),
+        (0xD, _) => Some(
+            "DXYN waits for the next frame on the COSMAC VIP, and sprites wrap or are clipped \
+             at the edges depending on the interpreter",
+        ),
+        (0xF, 0x5) if opcode & 0xFF == 0x55 || opcode & 0xFF == 0x65 => {
+            Some("FX55 and FX65 advance I on the COSMAC VIP, but leave it unchanged on SUPER-CHIP")
+        }
+        _ => None,
+    }
+}
+
+/// Checks a program loaded at 0x200 without running it. Instructions are found by
+/// following the program's control flow from its start, so data isn't mistaken for
+/// code. This finds which instruction sets the program needs, which instructions
+/// depend on quirks of the interpreter, and likely mistakes such as writing over the
+/// font or accessing memory past the end.
+pub fn lint(program: &[u8]) -> LintReport {
+    let end = PROGRAM_START + program.len();
+    let opcode_at = |addr: usize| {
+        if addr >= PROGRAM_START && addr + 1 < end {
+            Some(
+                u16::from(program[addr - PROGRAM_START]) << 8
+                    | u16::from(program[addr + 1 - PROGRAM_START]),
+            )
+        } else {
+            None
+        }
+    };
+    // MegaChip8 instructions overlap machine code calls, so are only recognized in
+    // programs that switch into MegaChip mode.
+    let megachip = program.chunks(2).any(|word| word == [0x00, 0x11]);
+
+    let mut instruction_sets = BTreeSet::new();
+    let mut findings = Vec::new();
+    let mut instructions = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START as u16];
+    while let Some(addr) = pending.pop() {
+        if instructions.contains_key(&addr) {
+            continue;
+        }
+        let opcode = match opcode_at(usize::from(addr)) {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        let (set, len, flow) = match decode(opcode, megachip) {
+            Some(decoded) => decoded,
+            None => {
+                findings.push(Finding {
+                    address: addr,
+                    severity: Severity::Warning,
+                    message: format!("0x{:04X} isn't an instruction in any known set", opcode),
+                });
+                continue;
+            }
+        };
+        instruction_sets.insert(set);
+        instructions.insert(addr, opcode);
+        let next = addr.wrapping_add(len);
+        match flow {
+            Flow::Next => pending.push(next),
+            Flow::Jump(target) => {
+                targets.insert(target);
+                pending.push(target);
+            }
+            Flow::Call(target) => {
+                targets.insert(target);
+                targets.insert(next);
+                pending.extend(&[target, next]);
+            }
+            Flow::Skip => {
+                targets.insert(next.wrapping_add(2));
+                pending.extend(&[next, next.wrapping_add(2)]);
+            }
+            Flow::Stop => {}
+        }
+    }
+    if instruction_sets.is_empty() {
+        instruction_sets.insert(InstructionSet::Chip8);
+    }
+    if opcode_at(PROGRAM_START) == Some(0x1260) {
+        instruction_sets.insert(InstructionSet::HiresVip);
+    }
+
+    // Instructions that depend on a quirk are reported once, at the first use.
+    let mut quirks: BTreeMap<&str, (u16, usize)> = BTreeMap::new();
+    for (&addr, &opcode) in instructions.iter() {
+        if let Some(description) = quirk(opcode) {
+            quirks.entry(description).or_insert((addr, 0)).1 += 1;
+        }
+    }
+    for (description, (address, count)) in quirks {
+        findings.push(Finding {
+            address,
+            severity: Severity::Quirk,
+            message: format!(
+                "{} ({} use{})",
+                description,
+                count,
+                if count == 1 { "" } else { "s" }
+            ),
+        });
+    }
+
+    // Follow the value of I through straight-line code, to check the memory that
+    // instructions access through it.
+    let memory_size = if megachip {
+        Platform::MegaChip8.memory_size()
+    } else {
+        Platform::Chip8.memory_size()
+    };
+    let mut i: Option<usize> = None;
+    let mut last = None;
+    for (&addr, &opcode) in instructions.iter() {
+        if targets.contains(&addr) || last != Some(addr.wrapping_sub(2)) {
+            i = None;
+        }
+        last = Some(addr);
+        let x = usize::from((opcode >> 8) & 0xF);
+        let mut warn = |message: String| {
+            findings.push(Finding {
+                address: addr,
+                severity: Severity::Warning,
+                message,
+            })
+        };
+        match (opcode >> 12, opcode & 0xFF) {
+            (0x0, _) if !megachip && is_machine_code(opcode) => {
+                warn(format!(
+                    "calls machine code at 0x{:03X}, which needs the COSMAC VIP backend",
+                    opcode & 0xFFF
+                ));
+            }
+            (0xA, _) => i = Some(usize::from(opcode & 0xFFF)),
+            (0xD, _) | (0xF, 0x65) | (0xF, 0x33) | (0xF, 0x55) => {
+                if let Some(start) = i {
+                    let (len, writes) = match (opcode >> 12, opcode & 0xFF) {
+                        (0xD, _) if opcode & 0xF == 0 => (32, false),
+                        (0xD, _) => (usize::from(opcode & 0xF), false),
+                        (_, 0x65) => (x + 1, false),
+                        (_, 0x33) => (3, true),
+                        _ => (x + 1, true),
+                    };
+                    let access = if writes { "writes" } else { "reads" };
+                    if start + len > memory_size {
+                        warn(format!(
+                            "{} 0x{:03X}-0x{:03X}, past the end of memory",
+                            access,
+                            start,
+                            start + len - 1
+                        ));
+                    } else if writes && start < PROGRAM_START {
+                        warn(format!(
+                            "writes 0x{:03X}-0x{:03X}, below 0x200 where the font is",
+                            start,
+                            start + len - 1
+                        ));
+                    } else if writes
+                        && (start..start + len).any(|a| instructions.contains_key(&(a as u16)))
+                    {
+                        warn(format!(
+                            "writes 0x{:03X}-0x{:03X}, which holds code",
+                            start,
+                            start + len - 1
+                        ));
+                    }
+                }
+                if opcode >> 12 == 0xF && opcode & 0xFF != 0x33 {
+                    // Whether I advances is a quirk.
+                    i = None;
+                }
+            }
+            (0xF, 0x1E) | (0xF, 0x29) | (0xF, 0x00) | (0xF, 0x30) => i = None,
+            _ => {}
+        }
+    }
+    findings.sort_by_key(|finding| finding.address);
+
+    LintReport {
+        instruction_sets,
+        findings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instruction_sets() {
+        // CLS; LD V0, 1; JP 0x200
+        let report = lint(&[0x00, 0xE0, 0x60, 0x01, 0x12, 0x00]);
+        assert_eq!(
+            report.instruction_sets.iter().cloned().collect::<Vec<_>>(),
+            [InstructionSet::Chip8]
+        );
+        assert!(report.findings.is_empty());
+        assert_eq!(report.platform(), Platform::Chip8);
+
+        // HIGH; DRW V0, V0, 0; JP 0x204; data that looks like XO-CHIP
+        let report = lint(&[0x00, 0xFF, 0xD0, 0x00, 0x12, 0x04, 0xF0, 0x00]);
+        assert_eq!(report.unsupported(), [InstructionSet::SuperChip]);
+
+        // MEGAON; LDHI 0x000300; JP 0x206
+        let report = lint(&[0x00, 0x11, 0x01, 0x00, 0x03, 0x00, 0x12, 0x06]);
+        assert_eq!(report.platform(), Platform::MegaChip8);
+        assert!(report.unsupported().is_empty());
+    }
+
+    #[test]
+    fn findings() {
+        // LD I, 0x050; LD [I], V1; SHR V0, V1; SHR V2, V3; LD I, 0xFFE; LD B, V0;
+        // LD I, 0x202; LD [I], V0; EXIT
+        let report = lint(&[
+            0xA0, 0x50, 0xF1, 0x55, 0x80, 0x16, 0x82, 0x36, 0xAF, 0xFE, 0xF0, 0x33, 0xA2, 0x02,
+            0xF0, 0x55, 0x00, 0xFD,
+        ]);
+        let findings: Vec<String> = report.findings.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            [
+                "0x202: quirk: FX55 and FX65 advance I on the COSMAC VIP, but leave it \
+                 unchanged on SUPER-CHIP (2 uses)",
+                "0x202: warning: writes 0x050-0x051, below 0x200 where the font is",
+                "0x204: quirk: 8XY6 and 8XYE shift VY into VX on the COSMAC VIP, but shift VX \
+                 in place on SUPER-CHIP (2 uses)",
+                "0x20A: warning: writes 0xFFE-0x1000, past the end of memory",
+                "0x20E: warning: writes 0x202-0x202, which holds code",
+            ]
+        );
+        assert_eq!(
+            report.instruction_sets.iter().cloned().collect::<Vec<_>>(),
+            [InstructionSet::Chip8, InstructionSet::SuperChip]
+        );
+    }
+}
@@ -4,26 +4,39 @@ extern crate clap;
 extern crate ggez;
 extern crate rodio;
 
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
 use std::path::Path;
 use std::process;
+use std::rc::Rc;
 use std::time::Duration;
 
-use chip8::{AudioHandler, Emulator};
-use clap::{App, Arg};
+use chip8::{
+    assemble_file, compare_traces, decompile, disassemble_program, lint, lookup_rom, AudioHandler,
+    CallGraph, Cartridge, Colors, ControlFlowGraph, Coverage, Emulator, EmulatorBuilder,
+    ErrorPolicies, ErrorPolicy, ExecutionStatus, Font, MemoryLayout, OpcodeClass, Platform,
+    Profiler, Sample, SourceDebugger, StopCondition, Syntax, Timing, TraceFilter, Tracer,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use ggez::conf;
 use ggez::error::GameError;
 use ggez::event::{self, EventHandler, Keycode, Mod};
 use ggez::graphics::{self, Color, DrawMode, MeshBuilder, Point2};
 use ggez::timer;
 use ggez::{Context, ContextBuilder, GameResult};
+use rodio::buffer::SamplesBuffer;
 use rodio::source::SineWave;
-use rodio::{Device, Sink};
+use rodio::{Device, Sink, Source};
 
 #[derive(Default)]
 struct MainState {
     emulator: Emulator,
-    clock_speed: u32,
+    updates_per_second: u32,
+    colors: Option<Colors>,
+    /// Stops execution at the breakpoints set in the program's source.
+    debugger: SourceDebugger,
 }
 
 fn main() {
@@ -31,61 +44,819 @@ fn main() {
         .version(crate_version!())
         .author(crate_authors!())
         .about("A CHIP-8 emulator written in Rust.")
-        .arg(
-            Arg::with_name("clock_speed")
-                .short("c")
-                .long("clock-speed")
-                .value_name("hz")
-                .default_value("1200")
-                .help("Sets the clock speed (in hz) of the CPU.")
-                .validator(validate_clock_speed)
-                .takes_value(true),
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&machine_args())
+        .subcommand(SubCommand::with_name("run").about("Runs a program in a window.").args(&machine_args()))
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("Runs a program without a window, writing a line for each instruction.")
+                .args(&machine_args())
+                .arg(
+                    Arg::with_name("cycles")
+                        .long("cycles")
+                        .value_name("n")
+                        .default_value("10000")
+                        .validator(validate_count)
+                        .help("Sets the number of cycles to run for.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the trace to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("pc_range")
+                        .long("pc-range")
+                        .value_name("start-end")
+                        .validator(validate_range)
+                        .help(
+                            "Only traces instructions in this range of addresses, in hexadecimal.",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("class")
+                        .long("class")
+                        .value_name("class")
+                        .multiple(true)
+                        .require_delimiter(true)
+                        .validator(validate_class)
+                        .help("Only traces instructions of these classes, such as flow or display.")
+                        .takes_value(true),
+                ),
         )
-        .arg(
-            Arg::with_name("program")
-                .required(true)
-                .validator(validate_file_exists)
-                .help("The CHIP-8 ROM to load."),
+        .subcommand(
+            SubCommand::with_name("profile")
+                .about("Runs a program without a window, reporting where it spends its time.")
+                .args(&machine_args())
+                .arg(
+                    Arg::with_name("cycles")
+                        .long("cycles")
+                        .value_name("n")
+                        .default_value("10000")
+                        .validator(validate_count)
+                        .help("Sets the number of cycles to run for.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the report to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("heatmap")
+                        .long("heatmap")
+                        .value_name("file")
+                        .help("Writes a PPM image showing how often each byte of memory is used.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("chrome_trace")
+                        .long("chrome-trace")
+                        .value_name("file")
+                        .help("Writes each subroutine call as a span in the Chrome trace event format.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("folded_stacks")
+                        .long("folded-stacks")
+                        .value_name("file")
+                        .help("Writes the instructions run in each call stack, for flame graphs.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("coverage")
+                .about("Runs a program without a window, recording which bytes are code and data.")
+                .args(&machine_args())
+                .arg(
+                    Arg::with_name("cycles")
+                        .long("cycles")
+                        .value_name("n")
+                        .default_value("10000")
+                        .validator(validate_count)
+                        .help("Sets the number of cycles to run for.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the hex dump to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .value_name("file")
+                        .help("Writes the ranges of code, data and untouched bytes as JSON.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("disassembly")
+                        .long("disassembly")
+                        .value_name("file")
+                        .help("Writes a disassembly that lists bytes never executed as data.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("analyze")
+                .about("Finds the control-flow graph of a program without running it.")
+                .arg(
+                    Arg::with_name("platform")
+                        .short("p")
+                        .long("platform")
+                        .value_name("platform")
+                        .default_value("chip8")
+                        .possible_values(&["chip8", "megachip8"])
+                        .help("Sets the machine the program is for.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the graph to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("program")
+                        .required(true)
+                        .validator(validate_file_exists)
+                        .help("The program to analyze."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decompile")
+                .about("Turns a program into structured pseudo-code or Octo source.")
+                .arg(
+                    Arg::with_name("platform")
+                        .short("p")
+                        .long("platform")
+                        .value_name("platform")
+                        .default_value("chip8")
+                        .possible_values(&["chip8", "megachip8"])
+                        .help("Sets the machine the program is for.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("syntax")
+                        .long("syntax")
+                        .value_name("syntax")
+                        .default_value("pseudo")
+                        .possible_values(&["pseudo", "octo"])
+                        .help("Sets whether to write C-like pseudo-code or Octo source.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the source to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("program")
+                        .required(true)
+                        .validator(validate_file_exists)
+                        .help("The program to decompile."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Reports the instruction sets and quirks a program needs, and likely bugs.")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("file")
+                        .help("Writes the report to a file instead of standard output.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("program")
+                        .required(true)
+                        .validator(validate_file_exists)
+                        .help("The program to check."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("Finds the first point where two traces diverge.")
+                .arg(
+                    Arg::with_name("expected")
+                        .required(true)
+                        .validator(validate_file_exists)
+                        .help("The trace to compare against."),
+                )
+                .arg(
+                    Arg::with_name("actual")
+                        .required(true)
+                        .validator(validate_file_exists)
+                        .help("The trace to check."),
+                ),
         )
         .get_matches();
 
-    let filename = matches.value_of("program").unwrap();
-    let rom = match fs::read(&filename) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
+    match matches.subcommand() {
+        ("run", Some(matches)) => run(matches),
+        ("trace", Some(matches)) => trace(matches),
+        ("profile", Some(matches)) => profile(matches),
+        ("coverage", Some(matches)) => coverage(matches),
+        ("analyze", Some(matches)) => analyze(matches),
+        ("decompile", Some(matches)) => decompile_program(matches),
+        ("lint", Some(matches)) => lint_program(matches),
+        ("compare", Some(matches)) => compare(matches),
+        _ => run(&matches),
+    }
+}
+
+/// Arguments that configure the emulated machine and the program it runs.
+fn machine_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("clock_speed")
+            .short("c")
+            .long("clock-speed")
+            .value_name("hz")
+            .default_value("1200")
+            .help("Sets the clock speed (in hz) of the CPU. Overrides the ROM database.")
+            .validator(validate_clock_speed)
+            .takes_value(true),
+        Arg::with_name("vip_timing").long("vip-timing").help(
+            "Makes each instruction take as long as on the COSMAC VIP, instead of \
+                 running at a fixed clock speed.",
+        ),
+        Arg::with_name("timer_frequency")
+            .long("timer-frequency")
+            .value_name("hz")
+            .default_value("60")
+            .help("Sets how often (in hz) the delay and sound timers count down.")
+            .validator(validate_timer_frequency)
+            .takes_value(true),
+        Arg::with_name("seed")
+            .long("seed")
+            .value_name("seed")
+            .help("Seeds the random number generator, so that runs can be repeated.")
+            .validator(validate_seed)
+            .takes_value(true),
+        Arg::with_name("platform")
+            .short("p")
+            .long("platform")
+            .value_name("platform")
+            .default_value("chip8")
+            .possible_values(&["chip8", "megachip8"])
+            .help(
+                "Sets the machine to emulate. Defaults to the ROM database's, or the one the program needs.",
+            )
+            .takes_value(true),
+        Arg::with_name("memory_layout")
+            .long("memory-layout")
+            .value_name("layout")
+            .default_value("separate")
+            .possible_values(&["separate", "vip"])
+            .help("Sets whether the stack and display are stored in memory, as on the COSMAC VIP.")
+            .takes_value(true),
+        Arg::with_name("font")
+            .long("font")
+            .value_name("font")
+            .possible_values(&["vip", "dream6800", "eti660", "schip", "octo"])
+            .conflicts_with("font_file")
+            .help("Sets the font used for hexadecimal digit sprites. Defaults to octo.")
+            .takes_value(true),
+        Arg::with_name("font_file")
+            .long("font-file")
+            .value_name("file")
+            .validator(validate_file_exists)
+            .help("Loads the hexadecimal digit sprites from an 80 byte file.")
+            .takes_value(true),
+        Arg::with_name("font_address")
+            .long("font-address")
+            .value_name("address")
+            .default_value("0")
+            .validator(validate_address)
+            .help("Sets the address the font is stored at, in hexadecimal.")
+            .takes_value(true),
+        Arg::with_name("display_wait")
+            .long("display-wait")
+            .help("Makes drawing wait for the next frame, as on the COSMAC VIP."),
+        Arg::with_name("shift_quirk")
+            .long("shift-quirk")
+            .help("Makes 8XY6 and 8XYE shift VX in place, as on SUPER-CHIP."),
+        Arg::with_name("load_store_quirk")
+            .long("load-store-quirk")
+            .help("Makes FX55 and FX65 leave I unchanged, as on SUPER-CHIP."),
+        Arg::with_name("jump_quirk")
+            .long("jump-quirk")
+            .help("Makes BXNN jump to XNN + VX, as on SUPER-CHIP."),
+        Arg::with_name("logic_quirk")
+            .long("logic-quirk")
+            .help("Makes 8XY1, 8XY2 and 8XY3 reset VF, as on the COSMAC VIP."),
+        Arg::with_name("clip_quirk")
+            .long("clip-quirk")
+            .help("Clips sprites at the edges of the display instead of wrapping them."),
+        Arg::with_name("no_rom_database")
+            .long("no-rom-database")
+            .help("Doesn't look the program up in the ROM database for its settings."),
+        Arg::with_name("on_error")
+            .long("on-error")
+            .value_name("policy")
+            .default_value("strict")
+            .possible_values(POLICIES)
+            .help("Sets whether invalid programs stop, warn and continue, or silently continue.")
+            .takes_value(true),
+        Arg::with_name("on_unknown_opcode")
+            .long("on-unknown-opcode")
+            .value_name("policy")
+            .possible_values(POLICIES)
+            .help("Overrides --on-error for instructions that don't exist.")
+            .takes_value(true),
+        Arg::with_name("on_invalid_address")
+            .long("on-invalid-address")
+            .value_name("policy")
+            .possible_values(POLICIES)
+            .help("Overrides --on-error for accesses outside of program memory.")
+            .takes_value(true),
+        Arg::with_name("on_sprite_overflow")
+            .long("on-sprite-overflow")
+            .value_name("policy")
+            .possible_values(POLICIES)
+            .help("Overrides --on-error for sprites extending past the end of memory.")
+            .takes_value(true),
+        Arg::with_name("on_invalid_operand")
+            .long("on-invalid-operand")
+            .value_name("policy")
+            .possible_values(POLICIES)
+            .help("Overrides --on-error for key values, digits and blend modes out of range.")
+            .takes_value(true),
+        Arg::with_name("on_stack")
+            .long("on-stack")
+            .value_name("policy")
+            .possible_values(POLICIES)
+            .help("Overrides --on-error for calls with a full stack and returns with an empty one.")
+            .takes_value(true),
+        Arg::with_name("vip_monitor")
+            .long("vip-monitor")
+            .value_name("file")
+            .requires("vip_interpreter")
+            .validator(validate_file_exists)
+            .help("Runs the program on an emulated COSMAC VIP, using this monitor ROM.")
+            .takes_value(true),
+        Arg::with_name("vip_interpreter")
+            .long("vip-interpreter")
+            .value_name("file")
+            .requires("vip_monitor")
+            .validator(validate_file_exists)
+            .help("The CHIP-8 interpreter to run on the emulated COSMAC VIP.")
+            .takes_value(true),
+        Arg::with_name("program")
+            .required(true)
+            .validator(validate_file_exists)
+            .help("The CHIP-8 ROM, Octo cartridge or Octo source file to load."),
+    ]
+}
+
+/// Configures an emulator from the arguments given by `machine_args`.
+fn emulator_builder(matches: &ArgMatches, program: &Program) -> EmulatorBuilder {
+    let report = lint(&program.rom);
+    let use_database = !matches.is_present("no_rom_database");
+    let known = use_database && lookup_rom(&program.rom).is_some();
+    // Settings given on the command line override a cartridge's options and the ROM
+    // database, which override the platform guessed from the program's instructions.
+    let mut builder = EmulatorBuilder::new()
+        .rom_database(use_database)
+        .timer_frequency(value_t!(matches, "timer_frequency", u32).unwrap())
+        .timing(if matches.is_present("vip_timing") {
+            Timing::Vip
+        } else {
+            Timing::Emulated
+        });
+    if matches.occurrences_of("platform") > 0 {
+        builder = builder.platform(match matches.value_of("platform").unwrap() {
+            "megachip8" => Platform::MegaChip8,
+            _ => Platform::Chip8,
+        });
+    } else if !known {
+        builder = builder.platform(report.platform());
+    }
+    if let Some(cartridge) = &program.cartridge {
+        if let Some(instructions) = cartridge.instructions_per_frame {
+            builder = builder.instructions_per_frame(instructions);
+        }
+        builder = builder.quirks(cartridge.quirks);
+        for option in cartridge.unsupported.iter() {
+            eprintln!("note: the cartridge's {} option isn't supported", option);
         }
+    }
+    if matches.occurrences_of("clock_speed") > 0 {
+        builder = builder.clock_speed(value_t!(matches, "clock_speed", u32).unwrap());
+    }
+    if matches.is_present("display_wait") {
+        builder = builder.display_wait(true);
+    }
+    if matches.is_present("shift_quirk") {
+        builder = builder.shift_quirk(true);
+    }
+    if matches.is_present("load_store_quirk") {
+        builder = builder.load_store_quirk(true);
+    }
+    if matches.is_present("jump_quirk") {
+        builder = builder.jump_quirk(true);
+    }
+    if matches.is_present("logic_quirk") {
+        builder = builder.logic_quirk(true);
+    }
+    if matches.is_present("clip_quirk") {
+        builder = builder.clip_quirk(true);
+    }
+    if let (Some(monitor), Some(interpreter)) = (
+        matches.value_of("vip_monitor"),
+        matches.value_of("vip_interpreter"),
+    ) {
+        builder = builder.cosmac_vip(&read_file(monitor), &read_file(interpreter));
+    }
+    if matches.value_of("memory_layout") == Some("vip") {
+        builder = builder.memory_layout(MemoryLayout::Vip);
+    }
+    let font = match matches.value_of("font_file") {
+        Some(file) => match Font::from_bytes(&read_file(file)) {
+            Ok(font) => font,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                process::exit(2);
+            }
+        },
+        None => match matches.value_of("font") {
+            Some("vip") => Font::VIP,
+            Some("dream6800") => Font::DREAM_6800,
+            Some("eti660") => Font::ETI_660,
+            Some("schip") => Font::SCHIP,
+            _ => Font::OCTO,
+        },
     };
+    let font_address = parse_address(matches.value_of("font_address").unwrap()).unwrap();
+    builder = builder.font(font, font_address);
+    let policy = |name| matches.value_of(name).map(parse_policy);
+    let default_policy = policy("on_error").unwrap();
+    let mut unknown_opcode = policy("on_unknown_opcode").unwrap_or(default_policy);
+    let unsupported = report.unsupported();
+    if !unsupported.is_empty()
+        && matches.occurrences_of("on_error") == 0
+        && matches.occurrences_of("on_unknown_opcode") == 0
+    {
+        // Skipping the instructions lets many such programs run, if not correctly.
+        let names: Vec<&str> = unsupported.iter().map(|set| set.name()).collect();
+        eprintln!(
+            "note: the program uses {} instructions, which aren't supported; unknown \
+             instructions will be skipped",
+            names.join(" and ")
+        );
+        unknown_opcode = ErrorPolicy::Warn;
+    }
+    builder = builder.error_policies(ErrorPolicies {
+        unknown_opcode,
+        invalid_address: policy("on_invalid_address").unwrap_or(default_policy),
+        sprite_overflow: policy("on_sprite_overflow").unwrap_or(default_policy),
+        invalid_operand: policy("on_invalid_operand").unwrap_or(default_policy),
+        stack: policy("on_stack").unwrap_or(default_policy),
+    });
+    if let Some(seed) = matches.value_of("seed") {
+        builder = builder.rng_seed(seed.parse().unwrap());
+    }
+    builder
+}
 
-    let mut main_state = MainState {
-        clock_speed: value_t!(matches, "clock_speed", u32).unwrap(),
-        ..Default::default()
+/// Creates the emulator and loads the program, exiting if either fails.
+fn load_emulator(builder: EmulatorBuilder, program: &Program) -> Emulator {
+    let mut emulator = match builder.build() {
+        Ok(emulator) => emulator,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(2);
+        }
     };
-    match main_state.emulator.load_program(rom.as_slice()) {
+    match emulator.load_program(&program.rom) {
         Ok(_) => {}
         Err(e) => {
             eprintln!("{:?}", e);
             process::exit(2);
         }
     }
+    emulator
+}
 
+/// Runs the program in a window.
+fn run(matches: &ArgMatches) {
+    let program = read_program(matches.value_of("program").unwrap());
+    let mut builder = emulator_builder(matches, &program);
     if let Some(device) = rodio::default_output_device() {
-        main_state
-            .emulator
-            .set_audio_handler(Box::new(SimpleAudio::with_device(device)));
+        builder = builder.audio_handler(Box::new(SimpleAudio::with_device(device)));
     } else {
         eprintln!("Could not open audio output device.");
     }
+    let mut emulator = load_emulator(builder, &program);
+    let debugger = SourceDebugger::new(&mut emulator, program.breakpoints);
+    let colors = match &program.cartridge {
+        Some(cartridge) => cartridge.colors,
+        None => emulator.rom_info().and_then(|info| info.colors),
+    };
+    if let Some(info) = emulator.rom_info() {
+        if info.author.is_empty() {
+            eprintln!("{}", info.title);
+        } else {
+            eprintln!("{} by {}", info.title, info.author);
+        }
+        for (key, action) in info.keys {
+            eprintln!("  {}: {}", keyboard_key(*key), action);
+        }
+    }
 
+    let (width, height) = match emulator.view().platform() {
+        Platform::Chip8 => (640, 320),
+        Platform::MegaChip8 => (768, 576),
+    };
+    let mut main_state = MainState {
+        updates_per_second: match emulator.timing() {
+            Timing::Vip => 60,
+            _ => emulator.clock_speed(),
+        },
+        emulator,
+        colors,
+        debugger,
+    };
     let cb = ContextBuilder::new("chip8", "kevin")
         .window_setup(conf::WindowSetup::default().title("CHIP-8"))
-        .window_mode(conf::WindowMode::default().dimensions(640, 320));
+        .window_mode(conf::WindowMode::default().dimensions(width, height));
     let ctx = &mut cb.build().unwrap();
+    if let Some(colors) = colors {
+        graphics::set_background_color(ctx, argb_color(colors.background));
+    }
 
     event::run(ctx, &mut main_state).unwrap();
 }
 
+/// Runs the program without a window or input, writing a trace of the instructions
+/// executed.
+fn trace(matches: &ArgMatches) {
+    let program = read_program(matches.value_of("program").unwrap());
+    let mut builder = emulator_builder(matches, &program);
+    if !matches.is_present("seed") {
+        // Traces can only be compared if random numbers are repeated.
+        builder = builder.rng_seed(0);
+    }
+    let mut emulator = load_emulator(builder, &program);
+    let output = open_output(matches.value_of("output"));
+    let filter = TraceFilter {
+        pc_range: matches.value_of("pc_range").and_then(parse_range),
+        classes: match matches.values_of("class") {
+            Some(classes) => classes.map(|class| class.parse().unwrap()).collect(),
+            None => Vec::new(),
+        },
+    };
+    emulator.set_tracer(Some(Tracer::new(output, filter)));
+
+    let cycles = value_t!(matches, "cycles", u64).unwrap();
+    let result = emulator.run_until(&[StopCondition::Cycles(cycles)]);
+    for warning in emulator.take_warnings() {
+        eprintln!("warning: {}", warning.0);
+    }
+    // Flush the trace, which isn't done when exiting the process.
+    emulator.set_tracer(None);
+    if let Err(e) = result {
+        eprintln!("error: {}", e.0);
+        eprintln!("{}", emulator.view());
+        process::exit(1);
+    }
+}
+
+/// Runs the program without a window or input, and reports which instructions and
+/// subroutines it spends its time in.
+fn profile(matches: &ArgMatches) {
+    let program = read_program(matches.value_of("program").unwrap());
+    let mut builder = emulator_builder(matches, &program);
+    if !matches.is_present("seed") {
+        builder = builder.rng_seed(0);
+    }
+    let mut emulator = load_emulator(builder, &program);
+    let profiler = Rc::new(RefCell::new(Profiler::new(emulator.view().platform())));
+    emulator.add_observer(Box::new(profiler.clone()));
+    let call_graph = Rc::new(RefCell::new(CallGraph::new(emulator.clock_speed())));
+    emulator.add_observer(Box::new(call_graph.clone()));
+
+    let cycles = value_t!(matches, "cycles", u64).unwrap();
+    let result = emulator.run_until(&[StopCondition::Cycles(cycles)]);
+    for warning in emulator.take_warnings() {
+        eprintln!("warning: {}", warning.0);
+    }
+    if let Err(e) = &result {
+        eprintln!("error: {}", e.0);
+        eprintln!("{}", emulator.view());
+    }
+
+    let profiler = profiler.borrow();
+    write_output(matches.value_of("output"), |out| {
+        write!(out, "{}", profiler)
+    });
+    if let Some(file) = matches.value_of("heatmap") {
+        write_output(Some(file), |out| profiler.write_heatmap(out, 4));
+    }
+    let call_graph = call_graph.borrow();
+    if let Some(file) = matches.value_of("chrome_trace") {
+        write_output(Some(file), |out| call_graph.write_trace_events(out));
+    }
+    if let Some(file) = matches.value_of("folded_stacks") {
+        write_output(Some(file), |out| call_graph.write_folded_stacks(out));
+    }
+    if result.is_err() {
+        process::exit(1);
+    }
+}
+
+/// Runs the program without a window or input, and reports how each byte of the
+/// program was used.
+fn coverage(matches: &ArgMatches) {
+    let program = read_program(matches.value_of("program").unwrap());
+    let mut builder = emulator_builder(matches, &program);
+    if !matches.is_present("seed") {
+        builder = builder.rng_seed(0);
+    }
+    let mut emulator = load_emulator(builder, &program);
+    let coverage = Rc::new(RefCell::new(Coverage::new()));
+    emulator.add_observer(Box::new(coverage.clone()));
+
+    let cycles = value_t!(matches, "cycles", u64).unwrap();
+    let result = emulator.run_until(&[StopCondition::Cycles(cycles)]);
+    for warning in emulator.take_warnings() {
+        eprintln!("warning: {}", warning.0);
+    }
+    if let Err(e) = &result {
+        eprintln!("error: {}", e.0);
+        eprintln!("{}", emulator.view());
+    }
+
+    let coverage = coverage.borrow();
+    let rom = &program.rom;
+    write_output(matches.value_of("output"), |out| {
+        coverage.write_hex_dump(out, rom)
+    });
+    if let Some(file) = matches.value_of("json") {
+        write_output(Some(file), |out| coverage.write_json(out, rom.len()));
+    }
+    if let Some(file) = matches.value_of("disassembly") {
+        let megachip = emulator.view().platform() == Platform::MegaChip8;
+        let listing = disassemble_program(rom, Some(&coverage), megachip);
+        write_output(Some(file), |out| out.write_all(listing.as_bytes()));
+    }
+    if result.is_err() {
+        process::exit(1);
+    }
+}
+
+/// Writes to a file, or standard output if there isn't one, exiting if that fails.
+fn write_output<F>(file: Option<&str>, write: F)
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let mut output = open_output(file);
+    if let Err(e) = write(&mut output).and_then(|_| output.flush()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Opens a file to write to, or standard output if there isn't one.
+fn open_output(file: Option<&str>) -> Box<dyn Write> {
+    match file {
+        Some(file) => match fs::File::create(file) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    }
+}
+
+/// Writes the control-flow graph of a program as Graphviz DOT, and reports the
+/// problems found to standard error.
+fn analyze(matches: &ArgMatches) {
+    let rom = read_program(matches.value_of("program").unwrap()).rom;
+    let megachip = matches.value_of("platform") == Some("megachip8");
+    let graph = ControlFlowGraph::analyze(&rom, megachip);
+    for issue in graph.issues() {
+        eprintln!("warning: {}", issue);
+    }
+    write_output(matches.value_of("output"), |out| graph.write_dot(out));
+}
+
+/// Writes a program as pseudo-code or Octo source.
+fn decompile_program(matches: &ArgMatches) {
+    let rom = read_program(matches.value_of("program").unwrap()).rom;
+    let megachip = matches.value_of("platform") == Some("megachip8");
+    let syntax = match matches.value_of("syntax").unwrap() {
+        "octo" => Syntax::Octo,
+        _ => Syntax::PseudoCode,
+    };
+    let source = decompile(&rom, megachip, syntax);
+    write_output(matches.value_of("output"), |out| {
+        out.write_all(source.as_bytes())
+    });
+}
+
+/// Writes what the linter finds in a program.
+fn lint_program(matches: &ArgMatches) {
+    let report = lint(&read_program(matches.value_of("program").unwrap()).rom);
+    write_output(matches.value_of("output"), |out| write!(out, "{}", report));
+}
+
+/// Compares two traces, exiting with status 1 if they diverge.
+fn compare(matches: &ArgMatches) {
+    let open = |name| match fs::File::open(matches.value_of(name).unwrap()) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    match compare_traces(open("expected"), open("actual")) {
+        Ok(None) => println!("traces match"),
+        Ok(Some(divergence)) => {
+            println!("{}", divergence);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e.0);
+            process::exit(2);
+        }
+    }
+}
+
+/// A program to run, the cartridge it was read from, if any, and the breakpoints set
+/// in its source.
+struct Program {
+    rom: Vec<u8>,
+    cartridge: Option<Cartridge>,
+    breakpoints: BTreeMap<u16, String>,
+}
+
+/// Reads a program from a ROM, an Octo cartridge or Octo source, exiting if that
+/// fails.
+fn read_program(filename: &str) -> Program {
+    let data = read_file(filename);
+    if filename.ends_with(".8o") {
+        return match assemble_file(filename, &data) {
+            Ok(assembly) => Program {
+                rom: assembly.rom,
+                cartridge: None,
+                breakpoints: assembly.breakpoints,
+            },
+            Err(e) => {
+                eprintln!("{}", e.0);
+                process::exit(2);
+            }
+        };
+    }
+    if !Cartridge::is_cartridge(&data) {
+        return Program {
+            rom: data,
+            cartridge: None,
+            breakpoints: BTreeMap::new(),
+        };
+    }
+    match Cartridge::from_gif(&data) {
+        Ok(cartridge) => Program {
+            rom: cartridge.rom.clone(),
+            cartridge: Some(cartridge),
+            breakpoints: BTreeMap::new(),
+        },
+        Err(e) => {
+            eprintln!("{}: {}", filename, e.0);
+            process::exit(2);
+        }
+    }
+}
+
+fn read_file(filename: &str) -> Vec<u8> {
+    match fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
 fn validate_clock_speed(v: String) -> Result<(), String> {
     match v.parse::<u32>() {
         Ok(n) if n < 60 => Err(format!("clock speed must be at least 60 Hz, got {} Hz", n)),
@@ -98,6 +869,21 @@ fn validate_clock_speed(v: String) -> Result<(), String> {
     }
 }
 
+fn validate_timer_frequency(v: String) -> Result<(), String> {
+    match v.parse::<u32>() {
+        Ok(0) => Err("timer frequency must be at least 1 Hz".to_owned()),
+        Ok(_) => Ok(()),
+        _ => Err(format!("expecting a number, got '{}'", v)),
+    }
+}
+
+fn validate_seed(v: String) -> Result<(), String> {
+    match v.parse::<u64>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("expecting a number, got '{}'", v)),
+    }
+}
+
 fn format_hz(hz: u32) -> String {
     match hz {
         n if n >= 1024 * 1024 * 1024 => format!("{:.1} GHz", n as f32 / 1024.0 / 1024.0 / 1024.0),
@@ -107,6 +893,70 @@ fn format_hz(hz: u32) -> String {
     }
 }
 
+const POLICIES: &[&str] = &["strict", "warn", "lenient"];
+
+fn parse_policy(v: &str) -> ErrorPolicy {
+    match v {
+        "warn" => ErrorPolicy::Warn,
+        "lenient" => ErrorPolicy::Lenient,
+        _ => ErrorPolicy::Strict,
+    }
+}
+
+fn parse_address(v: &str) -> Option<u16> {
+    let digits = v.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn validate_address(v: String) -> Result<(), String> {
+    match parse_address(&v) {
+        Some(_) => Ok(()),
+        None => Err(format!("expecting a hexadecimal address, got '{}'", v)),
+    }
+}
+
+fn parse_range(v: &str) -> Option<(u16, u16)> {
+    let mut parts = v.splitn(2, '-');
+    let start = parse_address(parts.next()?)?;
+    let end = parse_address(parts.next()?)?;
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn validate_range(v: String) -> Result<(), String> {
+    match parse_range(&v) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "expecting a range of hexadecimal addresses such as 200-2FF, got '{}'",
+            v
+        )),
+    }
+}
+
+fn validate_count(v: String) -> Result<(), String> {
+    match v.parse::<u64>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("expecting a number, got '{}'", v)),
+    }
+}
+
+fn validate_class(v: String) -> Result<(), String> {
+    match v.parse::<OpcodeClass>() {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            let names: Vec<&str> = OpcodeClass::ALL.iter().map(|class| class.name()).collect();
+            Err(format!(
+                "expecting one of {}, got '{}'",
+                names.join(", "),
+                v
+            ))
+        }
+    }
+}
+
 fn validate_file_exists(f: String) -> Result<(), String> {
     if Path::new(&f).exists() {
         Ok(())
@@ -117,41 +967,85 @@ fn validate_file_exists(f: String) -> Result<(), String> {
 
 impl EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let mut delta = timer::get_delta(ctx);
-        while timer::check_update_time(ctx, self.clock_speed) {
-            match self.emulator.execute_cycle(delta) {
-                Ok(_) => delta = Duration::from_secs(0),
-                Err(e) => return Err(GameError::from(e.0)),
+        // Cycles are run at the clock speed, and the timers tick after an exact number
+        // of cycles, so the time between updates doesn't matter. With VIP timing, each
+        // update runs a frame's worth of instructions instead.
+        while timer::check_update_time(ctx, self.updates_per_second) {
+            if self.debugger.is_paused() {
+                continue;
             }
+            let result = match self.emulator.timing() {
+                Timing::Vip => self
+                    .emulator
+                    .run_until(&[StopCondition::Frames(1), StopCondition::Breakpoint])
+                    .map(|stop| stop == StopCondition::Breakpoint),
+                _ => self
+                    .emulator
+                    .execute_cycle(Duration::from_secs(0))
+                    .map(|status| matches!(status, ExecutionStatus::Breakpoint(_))),
+            };
+            match result {
+                Ok(true) => eprintln!("{}", self.debugger.pause(&self.emulator)),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("{}", self.emulator.view());
+                    return Err(GameError::from(e.0));
+                }
+            }
+        }
+        for warning in self.emulator.take_warnings() {
+            eprintln!("warning: {}", warning.0);
         }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx);
-        graphics::set_color(ctx, Color::from_rgb(246, 234, 190))?;
-
-        let mut mesh = MeshBuilder::new();
-        for (y, row) in self.emulator.get_display().iter().enumerate() {
-            for (x, cell) in row.iter().enumerate() {
-                if *cell {
-                    let (x0, y0) = (10.0 * x as f32, 10.0 * y as f32);
-                    let (x1, y1) = (x0 + 10.0, y0 + 10.0);
-                    mesh.polygon(
-                        DrawMode::Fill,
-                        &[
-                            Point2::new(x0, y0),
-                            Point2::new(x0, y1),
-                            Point2::new(x1, y1),
-                            Point2::new(x1, y0),
-                            Point2::new(x0, y0),
-                        ],
-                    );
+
+        // Scale the display to fit the window, since MegaChip programs can switch
+        // between resolutions with different aspect ratios.
+        let display = self.emulator.get_display();
+        let (width, height) = graphics::get_size(ctx);
+        let scale =
+            (width as f32 / display.width() as f32).min(height as f32 / display.height() as f32);
+        let x_offset = (width as f32 - scale * display.width() as f32) / 2.0;
+        let y_offset = (height as f32 - scale * display.height() as f32) / 2.0;
+
+        // A mesh is drawn in a single color, so pixels are batched by color. The ROM
+        // database's colors only replace the plain CHIP-8 ones.
+        let foreground = match self.colors {
+            Some(colors) if self.emulator.view().platform() == Platform::Chip8 => {
+                Some(colors.foreground)
+            }
+            _ => None,
+        };
+        let mut meshes: HashMap<u32, MeshBuilder> = HashMap::new();
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                if display.is_lit(x, y) {
+                    let (x0, y0) = (x_offset + scale * x as f32, y_offset + scale * y as f32);
+                    let (x1, y1) = (x0 + scale, y0 + scale);
+                    meshes
+                        .entry(foreground.unwrap_or_else(|| display.color(x, y)))
+                        .or_insert_with(MeshBuilder::new)
+                        .polygon(
+                            DrawMode::Fill,
+                            &[
+                                Point2::new(x0, y0),
+                                Point2::new(x0, y1),
+                                Point2::new(x1, y1),
+                                Point2::new(x1, y0),
+                                Point2::new(x0, y0),
+                            ],
+                        );
                 }
             }
         }
-        let mesh = mesh.build(ctx)?;
-        graphics::draw(ctx, &mesh, Point2::new(0.0, 0.0), 0.0)?;
+        for (argb, mesh) in meshes {
+            graphics::set_color(ctx, argb_color(argb))?;
+            let mesh = mesh.build(ctx)?;
+            graphics::draw(ctx, &mesh, Point2::new(0.0, 0.0), 0.0)?;
+        }
 
         graphics::present(ctx);
         timer::yield_now();
@@ -161,6 +1055,7 @@ impl EventHandler for MainState {
     fn key_down_event(&mut self, ctx: &mut Context, key: Keycode, _keymod: Mod, _repeat: bool) {
         match key {
             Keycode::Escape => ctx.quit().unwrap(),
+            Keycode::Return if self.debugger.is_paused() => self.debugger.resume(),
             k => {
                 if let Some(k) = keypad_key_from_keycode(k) {
                     self.emulator.on_key_down(k);
@@ -176,6 +1071,16 @@ impl EventHandler for MainState {
     }
 }
 
+fn argb_color(argb: u32) -> Color {
+    let [a, r, g, b] = argb.to_be_bytes();
+    Color::from_rgba(r, g, b, a)
+}
+
+/// The key on the keyboard that is mapped to a keypad key.
+fn keyboard_key(key: u8) -> char {
+    b"x123qweasdzc4rfv"[usize::from(key)] as char
+}
+
 fn keypad_key_from_keycode(key: Keycode) -> Option<u8> {
     match key {
         Keycode::Num1 => Some(1),
@@ -201,11 +1106,16 @@ fn keypad_key_from_keycode(key: Keycode) -> Option<u8> {
 struct SimpleAudio {
     device: Device,
     sink: Option<Sink>,
+    sample_sink: Option<Sink>,
 }
 
 impl SimpleAudio {
     fn with_device(device: Device) -> SimpleAudio {
-        SimpleAudio { device, sink: None }
+        SimpleAudio {
+            device,
+            sink: None,
+            sample_sink: None,
+        }
     }
 }
 
@@ -221,4 +1131,28 @@ impl AudioHandler for SimpleAudio {
     fn stop_sound(&mut self) {
         self.sink.take();
     }
+
+    fn play_sample(&mut self, sample: &Sample) {
+        if sample.rate == 0 || sample.data.is_empty() {
+            self.stop_sample();
+            return;
+        }
+        let data: Vec<f32> = sample
+            .data
+            .iter()
+            .map(|s| (f32::from(*s) - 128.0) / 128.0)
+            .collect();
+        let source = SamplesBuffer::new(1, u32::from(sample.rate), data);
+        let sink = Sink::new(&self.device);
+        if sample.looping {
+            sink.append(source.repeat_infinite());
+        } else {
+            sink.append(source);
+        }
+        self.sample_sink = Some(sink);
+    }
+
+    fn stop_sample(&mut self) {
+        self.sample_sink.take();
+    }
 }
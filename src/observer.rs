@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Receives callbacks as a program runs, for instrumentation such as profilers,
+/// tracers and test assertions. Every callback does nothing by default.
+///
+/// Observers are not told about memory accessed by the COSMAC VIP backend, or
+/// about its drawing.
+pub trait Observer {
+    /// An instruction was executed. This is followed by callbacks for the memory
+    /// accesses and drawing it caused.
+    fn instruction_executed(&mut self, _pc: u16, _opcode: u16) {}
+
+    /// A program instruction read a byte of memory.
+    fn memory_read(&mut self, _address: usize, _value: u8) {}
+
+    /// A program instruction wrote a byte of memory.
+    fn memory_written(&mut self, _address: usize, _value: u8) {}
+
+    /// A sprite was drawn at the given position, with the given number of rows.
+    fn sprite_drawn(&mut self, _x: u8, _y: u8, _rows: usize, _collision: bool) {}
+
+    /// The screen was cleared.
+    fn screen_cleared(&mut self) {}
+
+    /// A subroutine was called with `2NNN`, and will return to `return_address`.
+    fn subroutine_called(&mut self, _address: u16, _return_address: u16) {}
+
+    /// A subroutine returned with `00EE` to `return_address`.
+    fn subroutine_returned(&mut self, _return_address: u16) {}
+
+    /// MegaChip mode was switched on with `0011` or off with `0010`.
+    fn megachip_mode_changed(&mut self, _enabled: bool) {}
+
+    /// The sound timer started running.
+    fn sound_started(&mut self) {}
+
+    /// The sound timer reached zero.
+    fn sound_stopped(&mut self) {}
+
+    /// The timers counted down, which happens once per frame.
+    fn timers_ticked(&mut self, _delay_timer: u8, _sound_timer: u8) {}
+}
+
+/// Lets an observer be shared with the host, which can then read what it collected.
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn instruction_executed(&mut self, pc: u16, opcode: u16) {
+        self.borrow_mut().instruction_executed(pc, opcode);
+    }
+
+    fn memory_read(&mut self, address: usize, value: u8) {
+        self.borrow_mut().memory_read(address, value);
+    }
+
+    fn memory_written(&mut self, address: usize, value: u8) {
+        self.borrow_mut().memory_written(address, value);
+    }
+
+    fn sprite_drawn(&mut self, x: u8, y: u8, rows: usize, collision: bool) {
+        self.borrow_mut().sprite_drawn(x, y, rows, collision);
+    }
+
+    fn screen_cleared(&mut self) {
+        self.borrow_mut().screen_cleared();
+    }
+
+    fn subroutine_called(&mut self, address: u16, return_address: u16) {
+        self.borrow_mut().subroutine_called(address, return_address);
+    }
+
+    fn subroutine_returned(&mut self, return_address: u16) {
+        self.borrow_mut().subroutine_returned(return_address);
+    }
+
+    fn megachip_mode_changed(&mut self, enabled: bool) {
+        self.borrow_mut().megachip_mode_changed(enabled);
+    }
+
+    fn sound_started(&mut self) {
+        self.borrow_mut().sound_started();
+    }
+
+    fn sound_stopped(&mut self) {
+        self.borrow_mut().sound_stopped();
+    }
+
+    fn timers_ticked(&mut self, delay_timer: u8, sound_timer: u8) {
+        self.borrow_mut().timers_ticked(delay_timer, sound_timer);
+    }
+}
+
+/// Something an instruction did, recorded while observers are attached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Event {
+    MemoryRead {
+        address: usize,
+        value: u8,
+    },
+    MemoryWritten {
+        address: usize,
+        value: u8,
+    },
+    SpriteDrawn {
+        x: u8,
+        y: u8,
+        rows: usize,
+        collision: bool,
+    },
+    ScreenCleared,
+    SubroutineCalled {
+        address: u16,
+        return_address: u16,
+    },
+    SubroutineReturned {
+        return_address: u16,
+    },
+    MegachipModeChanged {
+        enabled: bool,
+    },
+}
+
+impl Event {
+    pub fn dispatch(&self, observer: &mut dyn Observer) {
+        match *self {
+            Event::MemoryRead { address, value } => observer.memory_read(address, value),
+            Event::MemoryWritten { address, value } => observer.memory_written(address, value),
+            Event::SpriteDrawn {
+                x,
+                y,
+                rows,
+                collision,
+            } => observer.sprite_drawn(x, y, rows, collision),
+            Event::ScreenCleared => observer.screen_cleared(),
+            Event::SubroutineCalled {
+                address,
+                return_address,
+            } => observer.subroutine_called(address, return_address),
+            Event::SubroutineReturned { return_address } => {
+                observer.subroutine_returned(return_address)
+            }
+            Event::MegachipModeChanged { enabled } => observer.megachip_mode_changed(enabled),
+        }
+    }
+}
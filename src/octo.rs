@@ -0,0 +1,993 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use crate::errors::*;
+use crate::system::PROGRAM_START;
+
+/// A program assembled from Octo source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assembly {
+    /// The program, to be loaded at 0x200.
+    pub rom: Vec<u8>,
+    /// The address of each label.
+    pub labels: BTreeMap<String, u16>,
+    /// The names of the breakpoints set with `:breakpoint`, by address.
+    pub breakpoints: BTreeMap<u16, String>,
+}
+
+/// A problem in Octo source, at a line numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Assembles [Octo](https://github.com/JohnEarnest/Octo) source into a program.
+///
+/// All of Octo's instructions, control structures and directives are supported except
+/// `:stringmode` and `:assert`, and `:monitor` is ignored. `:breakpoint name` marks the
+/// instruction that follows it, to stop there when debugging. Expressions in braces, as
+/// used by `:calc` and `:byte`, are evaluated with integer arithmetic. If the program
+/// defines `main` after some other code or data, a jump to `main` is placed at 0x200.
+///
+/// # Errors
+///
+/// Returns the first problem in the source, with its line.
+pub fn assemble(source: &str) -> std::result::Result<Assembly, SourceError> {
+    let mut assembler = Assembler::new(source);
+    match assembler.run() {
+        Ok(()) => Ok(Assembly {
+            rom: assembler.rom,
+            labels: assembler.labels,
+            breakpoints: assembler.breakpoints,
+        }),
+        Err(e) => Err(SourceError {
+            line: assembler.line,
+            message: e.0,
+        }),
+    }
+}
+
+/// Assembles an Octo source file, as read from disk.
+///
+/// # Errors
+///
+/// Returns an error starting with the file's name and the line of the first problem in
+/// the source, such as ``game.8o:12: `draw` is never defined``.
+pub fn assemble_file(filename: &str, data: &[u8]) -> Result<Assembly> {
+    let source = match std::str::from_utf8(data) {
+        Ok(source) => source,
+        Err(_) => return Err(Error(format!("{}: the source isn't valid UTF-8", filename))),
+    };
+    assemble(source).map_err(|e| Error(format!("{}:{}: {}", filename, e.line, e.message)))
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for text in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: text.to_string(),
+                line: number + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// How to fill in a reference to a label that isn't defined yet.
+#[derive(Clone, Copy)]
+enum Patch {
+    /// The low 12 bits of the instruction at the address.
+    Nnn,
+    /// The 16 bits at the address, as used by `i := long`.
+    Long,
+    /// The low nibble of the byte at the address, from the high nibble of a 12-bit
+    /// address, as used by `:unpack`.
+    HighNibble,
+    /// The byte at the address, from the low byte of the address.
+    LowByte,
+}
+
+struct Fixup {
+    address: usize,
+    patch: Patch,
+    label: String,
+    line: usize,
+}
+
+/// An open control structure.
+enum Block {
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+#[derive(Clone)]
+enum Operand {
+    Register(u8),
+    Value(i64),
+    /// A label that isn't defined yet.
+    Forward(String),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    started: bool,
+    defines_main: bool,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    breakpoints: BTreeMap<u16, String>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        let tokens = tokenize(source);
+        let defines_main = tokens
+            .iter()
+            .zip(tokens.iter().skip(1))
+            .any(|(a, b)| a.text == ":" && b.text == "main");
+        Assembler {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            here: PROGRAM_START,
+            started: false,
+            defines_main,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.last() {
+            return err(match block {
+                Block::If { .. } | Block::Else { .. } => "`begin` without a matching `end`",
+                Block::Loop { .. } => "`loop` without a matching `again`",
+            });
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let address = match self.labels.get(&fixup.label) {
+                Some(&address) => usize::from(address),
+                None => return err(&format!("`{}` is never defined", fixup.label)),
+            };
+            self.patch(fixup.address, fixup.patch, address)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => err("unexpected end of source"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            err(&format!("expected `{}`, found `{}`", expected, token))
+        }
+    }
+
+    /// Places the jump to `main` at 0x200 before the first code or label, unless
+    /// that is `main` itself.
+    fn start(&mut self, label: Option<&str>) -> Result {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if self.defines_main && label != Some("main") {
+            self.reference(0x1000, "main", Patch::Nnn)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result {
+        self.start(None)?;
+        let offset = self.here - PROGRAM_START;
+        if self.here > 0xFFFF {
+            return err("the program doesn't fit in memory");
+        }
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_op(&mut self, opcode: u16) -> Result {
+        self.emit((opcode >> 8) as u8)?;
+        self.emit(opcode as u8)
+    }
+
+    /// Emits an instruction that refers to a label, which is filled in once the label
+    /// is defined.
+    fn reference(&mut self, opcode: u16, label: &str, patch: Patch) -> Result {
+        self.start(None)?;
+        let address = self.here;
+        self.emit_op(opcode)?;
+        if let Patch::Long = patch {
+            self.emit_op(0)?;
+        }
+        self.fixups.push(Fixup {
+            address: match patch {
+                Patch::Long => address + 2,
+                _ => address,
+            },
+            patch,
+            label: label.to_string(),
+            line: self.line,
+        });
+        Ok(())
+    }
+
+    fn patch(&mut self, address: usize, patch: Patch, value: usize) -> Result {
+        let offset = address - PROGRAM_START;
+        match patch {
+            Patch::Nnn => {
+                if value > 0xFFF {
+                    return err(&format!("address 0x{:X} is out of range", value));
+                }
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            Patch::Long => {
+                self.rom[offset] = (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            Patch::HighNibble => {
+                self.rom[offset + 1] |= ((value >> 8) & 0xF) as u8;
+            }
+            Patch::LowByte => self.rom[offset + 1] = value as u8,
+        }
+        Ok(())
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+                digit.to_digit(16).map(|digit| digit as u8)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(register) => Ok(register),
+            None => err(&format!("expected a register, found `{}`", token)),
+        }
+    }
+
+    /// A register, number, constant or label.
+    fn operand(&mut self) -> Result<Operand> {
+        let token = self.next()?;
+        if let Some(register) = self.register(&token) {
+            return Ok(Operand::Register(register));
+        }
+        if token == "{" {
+            return self.expression().map(Operand::Value);
+        }
+        match self.lookup(&token) {
+            Some(value) => Ok(Operand::Value(value)),
+            None if is_name(&token) => Ok(Operand::Forward(token)),
+            None => err(&format!("expected a value, found `{}`", token)),
+        }
+    }
+
+    fn lookup(&self, token: &str) -> Option<i64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).cloned())
+            .or_else(|| self.labels.get(token).map(|&address| i64::from(address)))
+    }
+
+    /// A value that must be known now, in the given range.
+    fn value(&mut self, min: i64, max: i64) -> Result<i64> {
+        match self.operand()? {
+            Operand::Value(value) if value >= min && value <= max => Ok(value),
+            Operand::Value(value) => err(&format!("{} is out of range", value)),
+            Operand::Register(_) => err("expected a value, found a register"),
+            Operand::Forward(name) => err(&format!("`{}` isn't defined yet", name)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16> {
+        self.value(-128, 255).map(|value| value as u16 & 0xFF)
+    }
+
+    fn nibble(&mut self) -> Result<u16> {
+        self.value(0, 15).map(|value| value as u16)
+    }
+
+    /// Emits an instruction with a 12-bit address, which may refer to a label defined
+    /// later.
+    fn address_op(&mut self, opcode: u16) -> Result {
+        match self.operand()? {
+            Operand::Value(value) if (0..=0xFFF).contains(&value) => {
+                self.emit_op(opcode | value as u16)
+            }
+            Operand::Value(value) => err(&format!("address 0x{:X} is out of range", value)),
+            Operand::Register(_) => err("expected an address, found a register"),
+            Operand::Forward(name) => self.reference(opcode, &name, Patch::Nnn),
+        }
+    }
+
+    /// Evaluates an expression up to the closing brace, with the usual precedence.
+    fn expression(&mut self) -> Result<i64> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "}" => break,
+                token => tokens.push(token.to_string()),
+            }
+        }
+        let mut position = 0;
+        let value = self.binary(&tokens, &mut position, 0)?;
+        if position < tokens.len() {
+            return err(&format!("unexpected `{}` in expression", tokens[position]));
+        }
+        Ok(value)
+    }
+
+    fn binary(&self, tokens: &[String], position: &mut usize, min_precedence: u8) -> Result<i64> {
+        let mut left = self.unary(tokens, position)?;
+        while let Some(operator) = tokens.get(*position) {
+            let precedence = match operator.as_str() {
+                "|" => 1,
+                "^" => 2,
+                "&" => 3,
+                "<<" | ">>" => 4,
+                "+" | "-" => 5,
+                "*" | "/" | "%" => 6,
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            *position += 1;
+            let right = self.binary(tokens, position, precedence + 1)?;
+            left = match operator.as_str() {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left << (right & 63),
+                ">>" => left >> (right & 63),
+                "+" => left + right,
+                "-" => left - right,
+                "*" => left * right,
+                _ if right == 0 => return err("division by zero"),
+                "/" => left / right,
+                _ => left % right,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&self, tokens: &[String], position: &mut usize) -> Result<i64> {
+        let token = match tokens.get(*position) {
+            Some(token) => token.as_str(),
+            None => return err("incomplete expression"),
+        };
+        *position += 1;
+        match token {
+            "-" => self.unary(tokens, position).map(|value| -value),
+            "~" => self.unary(tokens, position).map(|value| !value),
+            "(" => {
+                let value = self.binary(tokens, position, 0)?;
+                match tokens.get(*position) {
+                    Some(token) if token == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => err("missing `)` in expression"),
+                }
+            }
+            _ => match self.lookup(token) {
+                Some(value) => Ok(value),
+                None => err(&format!("`{}` isn't defined", token)),
+            },
+        }
+    }
+
+    fn statement(&mut self) -> Result {
+        let token = self.next()?;
+        if let Some(register) = self.register(&token) {
+            return self.register_statement(register);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value(i64::MIN, i64::MAX)?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.expression()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.expect_register()?;
+                self.aliases.insert(name, register);
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit(value as u8)?;
+            }
+            ":org" => {
+                let address = self.value(PROGRAM_START as i64, 0xFFFF)?;
+                self.started = true;
+                self.here = address as usize;
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.start(None)?;
+                self.here += 1;
+                let defined = self.define_label(&name);
+                self.here -= 1;
+                defined?;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                match self.operand()? {
+                    Operand::Value(address) if (0..=0xFFF).contains(&address) => {
+                        self.emit_op(0x6000 | nibble << 4 | (address as u16 >> 8))?;
+                        self.emit_op(0x6100 | (address as u16 & 0xFF))?;
+                    }
+                    Operand::Forward(name) => {
+                        self.reference(0x6000 | nibble << 4, &name, Patch::HighNibble)?;
+                        self.reference(0x6100, &name, Patch::LowByte)?;
+                    }
+                    _ => return err("`:unpack` needs an address"),
+                }
+            }
+            ":call" => self.address_op(0x2000)?,
+            ":macro" => self.define_macro()?,
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.start(None)?;
+                self.breakpoints.insert(self.here as u16, name);
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.emit_op(0x00EE)?,
+            "clear" => self.emit_op(0x00E0)?,
+            "exit" => self.emit_op(0x00FD)?,
+            "hires" => self.emit_op(0x00FF)?,
+            "lores" => self.emit_op(0x00FE)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit_op(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit_op(0x00D0 | n)?;
+            }
+            "scroll-right" => self.emit_op(0x00FB)?,
+            "scroll-left" => self.emit_op(0x00FC)?,
+            "audio" => self.emit_op(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit_op(0xF001 | n << 8)?;
+            }
+            "jump" => self.address_op(0x1000)?,
+            "jump0" => self.address_op(0xB000)?,
+            "native" => self.address_op(0x0000)?,
+            "sprite" => {
+                let x = u16::from(self.expect_register()?);
+                let y = u16::from(self.expect_register()?);
+                let n = self.nibble()?;
+                self.emit_op(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "save" | "load" => {
+                let x = u16::from(self.expect_register()?);
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = u16::from(self.expect_register()?);
+                    let opcode = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit_op(opcode | x << 8 | y << 4)?;
+                } else {
+                    let opcode = if token == "save" { 0xF055 } else { 0xF065 };
+                    self.emit_op(opcode | x << 8)?;
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_op(opcode)?;
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let end = self.here;
+                    self.emit_op(0x1000)?;
+                    self.patch(jump, Patch::Nnn, self.here)?;
+                    self.blocks.push(Block::Else { jump: end });
+                }
+                _ => return err("`else` without a matching `begin`"),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump }) | Some(Block::Else { jump }) => {
+                    self.patch(jump, Patch::Nnn, self.here)?;
+                }
+                _ => return err("`end` without a matching `begin`"),
+            },
+            "loop" => {
+                self.start(None)?;
+                self.blocks.push(Block::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                });
+            }
+            "while" => {
+                self.condition(true)?;
+                let jump = self.here;
+                self.emit_op(0x1000)?;
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return err("`while` outside of a loop"),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    let jump = self.here;
+                    self.emit_op(0x1000)?;
+                    self.patch(jump, Patch::Nnn, start)?;
+                    for exit in exits {
+                        self.patch(exit, Patch::Nnn, self.here)?;
+                    }
+                }
+                _ => return err("`again` without a matching `loop`"),
+            },
+            ":stringmode" | ":assert" => {
+                return err(&format!("`{}` isn't supported", token));
+            }
+            _ if token.starts_with(':') && token.len() > 1 => {
+                return err(&format!("unknown directive `{}`", token));
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ => {
+                // Numbers are data, and anything else is a subroutine call.
+                if let Some(value) = parse_number(&token) {
+                    if !(-128..=255).contains(&value) {
+                        return err(&format!("{} is out of range", value));
+                    }
+                    self.emit(value as u8)?;
+                } else if let Some(address) = self.lookup(&token) {
+                    self.emit_op(0x2000 | (address as u16 & 0xFFF))?;
+                } else if is_name(&token) {
+                    self.reference(0x2000, &token, Patch::Nnn)?;
+                } else {
+                    return err(&format!("unexpected `{}`", token));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str) -> Result {
+        if !is_name(name) {
+            return err(&format!("`{}` can't be used as a label", name));
+        }
+        if self.labels.contains_key(name) {
+            return err(&format!("`{}` is defined more than once", name));
+        }
+        self.start(Some(name))?;
+        self.labels.insert(name.to_string(), self.here as u16);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result {
+        let name = self.next()?;
+        let mut params = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "{" => break,
+                param => params.push(param.to_string()),
+            }
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return err(&format!("macro `{}` is missing its `}}`", name)),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result {
+        let count = self.macros[name].params.len();
+        let mut args = Vec::new();
+        for _ in 0..count {
+            args.push(self.next()?);
+        }
+        let line = self.line;
+        let definition = &self.macros[name];
+        let expansion: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| {
+                let text = match definition.params.iter().position(|p| *p == token.text) {
+                    Some(index) => args[index].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn register_op(&mut self, opcode: u16) -> Result {
+        let x = u16::from(self.expect_register()?);
+        self.emit_op(opcode | x << 8)
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result {
+        let x = u16::from(x);
+        let operator = self.next()?;
+        let operand = match (operator.as_str(), self.peek()) {
+            (":=", Some("random")) => {
+                self.next()?;
+                let mask = self.byte()?;
+                return self.emit_op(0xC000 | x << 8 | mask);
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                return self.emit_op(0xF00A | x << 8);
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                return self.emit_op(0xF007 | x << 8);
+            }
+            _ => self.operand()?,
+        };
+        let y = match operand {
+            Operand::Register(y) => u16::from(y),
+            Operand::Value(value) => {
+                if !(-128..=255).contains(&value) {
+                    return err(&format!("{} is out of range", value));
+                }
+                let nn = value as u16 & 0xFF;
+                return match operator.as_str() {
+                    ":=" => self.emit_op(0x6000 | x << 8 | nn),
+                    "+=" => self.emit_op(0x7000 | x << 8 | nn),
+                    "-=" => self.emit_op(0x7000 | x << 8 | (nn.wrapping_neg() & 0xFF)),
+                    _ => err(&format!("`{}` needs a register", operator)),
+                };
+            }
+            Operand::Forward(name) => return err(&format!("`{}` isn't defined yet", name)),
+        };
+        let op = match operator.as_str() {
+            ":=" => 0x0,
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xE,
+            _ => return err(&format!("unknown operator `{}`", operator)),
+        };
+        self.emit_op(0x8000 | x << 8 | y << 4 | op)
+    }
+
+    fn i_statement(&mut self) -> Result {
+        match self.next()?.as_str() {
+            ":=" => {}
+            "+=" => return self.register_op(0xF01E),
+            operator => return err(&format!("unknown operator `{}`", operator)),
+        }
+        match self.peek() {
+            Some("hex") => {
+                self.next()?;
+                self.register_op(0xF029)
+            }
+            Some("bighex") => {
+                self.next()?;
+                self.register_op(0xF030)
+            }
+            Some("long") => {
+                self.next()?;
+                match self.operand()? {
+                    Operand::Value(value) if (0..=0xFFFF).contains(&value) => {
+                        self.emit_op(0xF000)?;
+                        self.emit_op(value as u16)
+                    }
+                    Operand::Forward(name) => self.reference(0xF000, &name, Patch::Long),
+                    _ => err("`i := long` needs a 16-bit address"),
+                }
+            }
+            _ => self.address_op(0xA000),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result {
+        // The form of the condition depends on whether `then` or `begin` follows it.
+        let block = match self
+            .tokens
+            .iter()
+            .find(|token| token.text == "then" || token.text == "begin")
+        {
+            Some(token) => token.text == "begin",
+            None => return err("`if` without `then` or `begin`"),
+        };
+        self.condition(block)?;
+        let keyword = self.next()?;
+        if keyword != "then" && keyword != "begin" {
+            return err(&format!("expected `then` or `begin`, found `{}`", keyword));
+        }
+        if block {
+            let jump = self.here;
+            self.emit_op(0x1000)?;
+            self.blocks.push(Block::If { jump });
+        }
+        Ok(())
+    }
+
+    /// Emits instructions that skip the next one unless the condition holds, or with
+    /// `inverted`, skip it if the condition holds.
+    fn condition(&mut self, inverted: bool) -> Result {
+        let x = u16::from(self.expect_register()?);
+        let operator = self.next()?;
+        let (skip_unless, skip_if) = match operator.as_str() {
+            "key" => return self.emit_op(if inverted { 0xE09E } else { 0xE0A1 } | x << 8),
+            "-key" => return self.emit_op(if inverted { 0xE0A1 } else { 0xE09E } | x << 8),
+            "==" | "!=" => {
+                let (equal, not_equal) = match self.operand()? {
+                    Operand::Register(y) => {
+                        let y = u16::from(y) << 4;
+                        (0x5000 | x << 8 | y, 0x9000 | x << 8 | y)
+                    }
+                    Operand::Value(value) if (-128..=255).contains(&value) => {
+                        let nn = value as u16 & 0xFF;
+                        (0x3000 | x << 8 | nn, 0x4000 | x << 8 | nn)
+                    }
+                    _ => return err("expected a register or byte to compare with"),
+                };
+                if operator == "==" {
+                    (not_equal, equal)
+                } else {
+                    (equal, not_equal)
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // VF is set to the other operand, then compared with VX by subtracting,
+                // which leaves the flag in VF.
+                match self.operand()? {
+                    Operand::Register(y) => self.emit_op(0x8F00 | u16::from(y) << 4)?,
+                    Operand::Value(value) if (-128..=255).contains(&value) => {
+                        self.emit_op(0x6F00 | (value as u16 & 0xFF))?
+                    }
+                    _ => return err("expected a register or byte to compare with"),
+                }
+                let (subtract, holds_when_flag) = match operator.as_str() {
+                    // VF = VX - VF, with the flag set if VX >= the operand.
+                    "<" => (0x8F07, 0),
+                    ">=" => (0x8F07, 1),
+                    // VF = VF - VX, with the flag set if the operand >= VX.
+                    ">" => (0x8F05, 0),
+                    _ => (0x8F05, 1),
+                };
+                self.emit_op(subtract | x << 4)?;
+                (0x4F00 | holds_when_flag, 0x3F00 | holds_when_flag)
+            }
+            _ => return err(&format!("unknown comparison `{}`", operator)),
+        };
+        self.emit_op(if inverted { skip_if } else { skip_unless })
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    !token.is_empty()
+        && !token.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == ':')
+        && !matches!(
+            token,
+            "{" | "}" | ";" | ":=" | "+=" | "-=" | "=-" | "==" | "!="
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompile::{decompile, Syntax};
+
+    #[test]
+    fn instructions() {
+        let assembly = assemble(
+            "
+            :const SPEED 3
+            :alias x v4
+            : main
+                i := sprite
+                x := SPEED
+                x += -1
+                v5 := random 0xF0
+                sprite x v5 8
+                if x != 2 then x := { SPEED * 2 + 1 }
+                if v1 > 10 begin
+                    bcd v1
+                else
+                    save v3
+                end
+                loop
+                    v0 += 1
+                    while v0 key
+                again
+                :breakpoint drawing
+                draw
+            : draw  # Forward references are filled in later.
+                ;
+            : sprite
+                0xFF 0x81 -1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            assembly.rom,
+            [
+                0xA2, 0x28, 0x64, 0x03, 0x74, 0xFF, 0xC5, 0xF0, 0xD4, 0x58, 0x34, 0x02, 0x64, 0x07,
+                0x6F, 0x0A, 0x8F, 0x15, 0x3F, 0x00, 0x12, 0x1A, 0xF1, 0x33, 0x12, 0x1C, 0xF3, 0x55,
+                0x70, 0x01, 0xE0, 0x9E, 0x12, 0x24, 0x12, 0x1C, 0x22, 0x26, 0x00, 0xEE, 0xFF, 0x81,
+                0xFF,
+            ][..]
+        );
+        assert_eq!(assembly.labels["draw"], 0x226);
+        assert_eq!(assembly.breakpoints[&0x224], "drawing");
+    }
+
+    #[test]
+    fn jump_to_main() {
+        let assembly = assemble(": data 1 2 : main jump main").unwrap();
+        assert_eq!(assembly.rom, [0x12, 0x04, 0x01, 0x02, 0x12, 0x04]);
+        let assembly = assemble("sub : main ; : sub ;").unwrap();
+        assert_eq!(assembly.rom, [0x12, 0x04, 0x22, 0x06, 0x00, 0xEE, 0x00, 0xEE]);
+        let assembly = assemble(":macro twice op { op op } : main twice clear").unwrap();
+        assert_eq!(assembly.rom, [0x00, 0xE0, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| assemble(source).unwrap_err().to_string();
+        assert_eq!(error(": main\n  v0 := 300"), "line 2: 300 is out of range");
+        assert_eq!(error("jump nowhere"), "line 1: `nowhere` is never defined");
+        assert_eq!(
+            error("loop\nv0 := 1"),
+            "line 2: `loop` without a matching `again`"
+        );
+        assert_eq!(error(": a\n: a"), "line 2: `a` is defined more than once");
+        assert_eq!(
+            error(":org 0x1000\nloop\nagain"),
+            "line 3: address 0x1000 is out of range"
+        );
+        assert_eq!(
+            assemble("clear\n\n  v0 +=").unwrap_err(),
+            SourceError {
+                line: 3,
+                message: "unexpected end of source".to_string(),
+            }
+        );
+
+        let error = |data: &[u8]| assemble_file("game.8o", data).unwrap_err().0;
+        assert_eq!(
+            error(b"clear\njump draw"),
+            "game.8o:2: `draw` is never defined"
+        );
+        assert_eq!(error(b"\xFF"), "game.8o: the source isn't valid UTF-8");
+        let assembly = assemble_file("game.8o", b":breakpoint start clear").unwrap();
+        assert_eq!(assembly.breakpoints[&0x200], "start");
+    }
+
+    #[test]
+    fn decompiled_programs_reassemble() {
+        // LD I, 0x214; LD V0, 0; loop: SNE V0, 10; JP 0x20E; CALL 0x210; ADD V0, 1;
+        // JP 0x204; JP 0x20E; DRW V0, V1, 1; RET; sprite
+        let program = [
+            0xA2, 0x14, 0x60, 0x00, 0x40, 0x0A, 0x12, 0x0E, 0x22, 0x10, 0x70, 0x01, 0x12, 0x04,
+            0x12, 0x0E, 0xD0, 0x11, 0x00, 0xEE, 0x80, 0xC0,
+        ];
+        let source = decompile(&program, false, Syntax::Octo);
+        assert_eq!(assemble(&source).unwrap().rom, program);
+    }
+}
@@ -1,22 +1,49 @@
 extern crate rand;
 
-use rand::prelude::*;
+use std::borrow::Cow;
 
+use rand::Rng;
+
+use crate::audio::{Sample, SampleCommand};
+use crate::display::BlendMode;
 use crate::errors::*;
+use crate::extension::Extensions;
+use crate::observer::Event;
+use crate::platform::Platform;
 use crate::system::SystemState;
 
 pub trait IOpcodeRunner {
-    fn run(&self, system: &mut SystemState, opcode: u16) -> Result;
+    /// Prepares a freshly created or reset system for running programs.
+    fn init(&self, _system: &mut SystemState) {}
+
+    /// Executes the next instruction of the program.
+    fn step(&self, system: &mut SystemState, extensions: &mut Extensions) -> Result {
+        let opcode = system.next_opcode();
+        self.run(system, extensions, opcode)
+    }
+
+    fn run(&self, system: &mut SystemState, extensions: &mut Extensions, opcode: u16) -> Result;
 }
 
 pub struct OpcodeRunner;
 
 impl IOpcodeRunner for OpcodeRunner {
-    fn run(&self, system: &mut SystemState, opcode: u16) -> Result {
+    fn run(&self, system: &mut SystemState, extensions: &mut Extensions, opcode: u16) -> Result {
         match nibbles(opcode) {
             (0x0, 0x0, 0xE, 0x0) => op_cls(system),
             (0x0, 0x0, 0xE, 0xE) => op_ret(system),
-            (0x0, _, _, _) => noop(), // machine call disabled
+            (0x0, 0x0, 0xF, 0xD) => op_exit(system),
+            (0x0, 0x0, 0x1, 0x0) if system.platform == Platform::MegaChip8 => op_megaoff(system),
+            (0x0, 0x0, 0x1, 0x1) if system.platform == Platform::MegaChip8 => op_megaon(system),
+            (0x0, 0x1, a, b) if system.megachip => op_ldhi(system, nn(a, b)),
+            (0x0, 0x2, a, b) if system.megachip => op_ldpal(system, nn(a, b)),
+            (0x0, 0x3, a, b) if system.megachip => op_sprw(system, nn(a, b)),
+            (0x0, 0x4, a, b) if system.megachip => op_sprh(system, nn(a, b)),
+            (0x0, 0x6, 0x0, n) if system.megachip => op_digisnd(system, n),
+            (0x0, 0x7, 0x0, 0x0) if system.megachip => op_stopsnd(system),
+            (0x0, 0x8, 0x0, n) if system.megachip => op_bmode(system, n),
+            (0x0, 0x9, a, b) if system.megachip => op_ccol(system, nn(a, b)),
+            (0x0, _, _, _) => op_sys(system, extensions, opcode),
             (0x1, a, b, c) => op_jp(system, nnn(a, b, c)),
             (0x2, a, b, c) => op_call(system, nnn(a, b, c)),
             (0x3, a, b, c) => op_se_reg_byte(system, a, nn(b, c)),
@@ -37,6 +64,7 @@ impl IOpcodeRunner for OpcodeRunner {
             (0xA, a, b, c) => op_ld_i(system, nnn(a, b, c)),
             (0xB, a, b, c) => op_jp_v0_addr(system, nnn(a, b, c)),
             (0xC, a, b, c) => op_rnd_reg_byte(system, a, nn(b, c)),
+            (0xD, a, b, _) if system.megachip => op_drw_megachip(system, a, b),
             (0xD, a, b, c) => op_drw(system, a, b, c),
             (0xE, a, 0x9, 0xE) => op_skp(system, a),
             (0xE, a, 0xA, 0x1) => op_sknp(system, a),
@@ -49,10 +77,7 @@ impl IOpcodeRunner for OpcodeRunner {
             (0xF, a, 0x3, 0x3) => op_ld_b(system, a),
             (0xF, a, 0x5, 0x5) => op_store_regs(system, a),
             (0xF, a, 0x6, 0x5) => op_load_regs(system, a),
-            _ => err(&format!(
-                "unknown opcode: 0x{:X}; pc=0x{:04X}, registers={:?}",
-                opcode, system.program_counter, system.registers
-            )),
+            _ => op_unknown(system, extensions, opcode),
         }
     }
 }
@@ -78,33 +103,110 @@ fn bcd(val: u8) -> (u8, u8, u8) {
     ((val / 100) % 10, (val / 10) % 10, val % 10)
 }
 
-fn sanitize_addr(system: &SystemState, addr: u16, len: usize) -> std::result::Result<usize, Error> {
-    let i = usize::from(addr);
-    if i < 0x200 || i + len >= system.memory.len() {
-        return err("invalid address");
+fn sanitize_addr(
+    system: &mut SystemState,
+    addr: u32,
+    len: usize,
+) -> std::result::Result<usize, Error> {
+    match system.check_address(addr as usize, len) {
+        Ok(i) => Ok(i),
+        Err(e) => {
+            let policy = system.error_policies.invalid_address;
+            system.report(policy, e.0)?;
+            Ok(addr as usize % system.memory.len())
+        }
+    }
+}
+
+/// Checks that a sprite of `len` bytes at `I` lies within memory, returning its
+/// address wrapped around the end of memory.
+fn sanitize_sprite_addr(system: &mut SystemState, len: usize) -> std::result::Result<usize, Error> {
+    let i = system.address_register as usize;
+    if i + len > system.memory.len() {
+        let policy = system.error_policies.sprite_overflow;
+        system.report(
+            policy,
+            format!("sprite at 0x{:X} extends past end of memory", i),
+        )?;
+        return Ok(i % system.memory.len());
     }
     Ok(i)
 }
 
-fn noop() -> Result {
+/// Reads VX as a key or digit, which must be at most 0xF. Only its low nibble is
+/// used when continuing.
+fn nibble_operand(system: &mut SystemState, x: u8, what: &str) -> std::result::Result<u8, Error> {
+    let vx = system.registers[usize::from(x)];
+    if vx > 0xF {
+        let policy = system.error_policies.invalid_operand;
+        system.report(policy, format!("invalid {}: {}", what, vx))?;
+    }
+    Ok(vx & 0xF)
+}
+
+/// Reads bytes from memory, wrapping around to address 0 at the end of memory.
+fn read_memory(memory: &[u8], addr: usize, len: usize) -> Cow<'_, [u8]> {
+    if addr + len <= memory.len() {
+        Cow::Borrowed(&memory[addr..addr + len])
+    } else {
+        Cow::Owned(
+            (addr..addr + len)
+                .map(|a| memory[a % memory.len()])
+                .collect(),
+        )
+    }
+}
+
+fn op_sys(system: &mut SystemState, extensions: &mut Extensions, opcode: u16) -> Result {
+    // Machine code can't be run directly, but the host may provide a native
+    // implementation of the routine. Otherwise, the call is ignored.
+    if let Some(result) = extensions.call_routine(system, opcode & 0xFFF) {
+        return result;
+    }
+    extensions.execute(system, opcode)?;
     Ok(())
 }
 
+fn op_unknown(system: &mut SystemState, extensions: &mut Extensions, opcode: u16) -> Result {
+    if extensions.execute(system, opcode)? {
+        return Ok(());
+    }
+    let policy = system.error_policies.unknown_opcode;
+    let message = format!(
+        "unknown opcode: 0x{:X}; pc=0x{:04X}, registers={:?}",
+        opcode, system.program_counter, system.registers
+    );
+    system.report(policy, message)
+}
+
 fn op_cls(system: &mut SystemState) -> Result {
-    for row in system.display.iter_mut() {
-        for val in row.iter_mut() {
-            *val = false;
-        }
+    if system.megachip {
+        // In MegaChip mode, 00E0 presents the frame that has been drawn so far.
+        std::mem::swap(&mut system.display, &mut system.presented);
     }
+    system.display.clear();
+    system.display_updated();
+    system.record(Event::ScreenCleared);
     Ok(())
 }
 
 fn op_ret(system: &mut SystemState) -> Result {
-    if system.stack_pointer == 0 {
-        return err("can't return from empty call stack");
-    }
-    system.stack_pointer -= 1;
-    system.program_counter = system.stack[usize::from(system.stack_pointer)];
+    system.program_counter = match system.pop_stack() {
+        Ok(addr) => addr,
+        Err(e) => {
+            // A return with nothing to return to is skipped.
+            let policy = system.error_policies.stack;
+            return system.report(policy, e.0);
+        }
+    };
+    system.record(Event::SubroutineReturned {
+        return_address: system.program_counter,
+    });
+    Ok(())
+}
+
+fn op_exit(system: &mut SystemState) -> Result {
+    system.halted = true;
     Ok(())
 }
 
@@ -114,12 +216,18 @@ fn op_jp(system: &mut SystemState, address: u16) -> Result {
 }
 
 fn op_call(system: &mut SystemState, address: u16) -> Result {
-    if usize::from(system.stack_pointer + 1) > system.stack.len() {
-        return err("stack overflow");
+    let address = sanitize_addr(system, u32::from(address), 1)?;
+    if let Err(e) = system.push_stack(system.program_counter) {
+        // The stack wraps around, overwriting the oldest return addresses.
+        let policy = system.error_policies.stack;
+        system.report(policy, e.0)?;
+        system.stack_pointer = 0;
+        system.push_stack(system.program_counter)?;
     }
-    let address = sanitize_addr(system, address, 1)?;
-    system.stack[usize::from(system.stack_pointer)] = system.program_counter;
-    system.stack_pointer += 1;
+    system.record(Event::SubroutineCalled {
+        address: address as u16,
+        return_address: system.program_counter,
+    });
     system.program_counter = address as u16;
     Ok(())
 }
@@ -172,19 +280,28 @@ fn op_ld_reg_reg(system: &mut SystemState, x: u8, y: u8) -> Result {
 
 fn op_or(system: &mut SystemState, x: u8, y: u8) -> Result {
     system.registers[usize::from(x)] |= system.registers[usize::from(y)];
+    logic_quirk(system);
     Ok(())
 }
 
 fn op_and(system: &mut SystemState, x: u8, y: u8) -> Result {
     system.registers[usize::from(x)] &= system.registers[usize::from(y)];
+    logic_quirk(system);
     Ok(())
 }
 
 fn op_xor(system: &mut SystemState, x: u8, y: u8) -> Result {
     system.registers[usize::from(x)] ^= system.registers[usize::from(y)];
+    logic_quirk(system);
     Ok(())
 }
 
+fn logic_quirk(system: &mut SystemState) {
+    if system.quirks.logic {
+        system.registers[0xF] = 0;
+    }
+}
+
 fn op_add_reg_reg(system: &mut SystemState, x: u8, y: u8) -> Result {
     let (ix, iy) = (usize::from(x), usize::from(y));
     let (result, overflow) = system.registers[ix].overflowing_add(system.registers[iy]);
@@ -203,7 +320,7 @@ fn op_sub_reg_reg(system: &mut SystemState, x: u8, y: u8) -> Result {
 
 fn op_shr(system: &mut SystemState, x: u8, y: u8) -> Result {
     let idx = usize::from(x);
-    let vy = system.registers[usize::from(y)];
+    let vy = system.registers[usize::from(shift_source(system, x, y))];
     system.registers[idx] = vy >> 1;
     system.registers[0xF] = vy & 0x01;
     Ok(())
@@ -219,29 +336,40 @@ fn op_subn(system: &mut SystemState, x: u8, y: u8) -> Result {
 
 fn op_shl(system: &mut SystemState, x: u8, y: u8) -> Result {
     let idx = usize::from(x);
-    let vy = system.registers[usize::from(y)];
+    let vy = system.registers[usize::from(shift_source(system, x, y))];
     system.registers[idx] = vy << 1;
     system.registers[0xF] = (vy >> 7) & 0x1;
     Ok(())
 }
 
+/// The register shifted by `8XY6` and `8XYE`.
+fn shift_source(system: &SystemState, x: u8, y: u8) -> u8 {
+    if system.quirks.shift {
+        x
+    } else {
+        y
+    }
+}
+
 fn op_ld_i(system: &mut SystemState, addr: u16) -> Result {
-    system.address_register = addr & 0xFFF;
+    system.address_register = u32::from(addr & 0xFFF);
     Ok(())
 }
 
 fn op_jp_v0_addr(system: &mut SystemState, addr: u16) -> Result {
-    let v0 = system.registers[0];
-    let (addr, overflow) = addr.overflowing_add(u16::from(v0));
+    let reg = if system.quirks.jump { addr >> 8 } else { 0 };
+    let offset = system.registers[usize::from(reg)];
+    let (addr, overflow) = addr.overflowing_add(u16::from(offset));
     if overflow {
-        return err("invalid address");
+        let policy = system.error_policies.invalid_address;
+        system.report(policy, "invalid address: jump past 0xFFFF".to_owned())?;
     }
-    system.program_counter = sanitize_addr(system, addr, 2)? as u16;
+    system.program_counter = sanitize_addr(system, u32::from(addr), 2)? as u16;
     Ok(())
 }
 
 fn op_rnd_reg_byte(system: &mut SystemState, reg: u8, byte: u8) -> Result {
-    system.registers[usize::from(reg)] = random::<u8>() & byte;
+    system.registers[usize::from(reg)] = system.rng.gen::<u8>() & byte;
     Ok(())
 }
 
@@ -249,29 +377,92 @@ fn op_drw(system: &mut SystemState, x: u8, y: u8, n: u8) -> Result {
     let vx = system.registers[usize::from(x)];
     let vy = system.registers[usize::from(y)];
 
-    let i = usize::from(system.address_register);
-    if i + usize::from(n) > system.memory.len() {
-        return err("invalid address");
-    }
+    let i = sanitize_sprite_addr(system, usize::from(n))?;
+    let (width, height) = (system.display.width(), system.display.height());
+    let (x0, y0) = (usize::from(vx) % width, usize::from(vy) % height);
+    let clip = system.quirks.clip;
     let mut collide = false;
-    for (idx, byte) in system.memory[i..i + usize::from(n)].iter().enumerate() {
+    for (idx, byte) in read_memory(&system.memory, i, usize::from(n))
+        .iter()
+        .enumerate()
+    {
         for bit in 0..8 {
-            let iy = (usize::from(vy) + idx) % system.display.len();
-            let ix = (usize::from(vx + bit)) % system.display[0].len();
-            let on = (byte & (1 << (7 - bit))) != 0;
-            collide = collide || (on && system.display[iy][ix]);
-            system.display[iy][ix] ^= on;
+            if clip && (x0 + bit >= width || y0 + idx >= height) {
+                continue;
+            }
+            let iy = (y0 + idx) % height;
+            let ix = (x0 + bit) % width;
+            if (byte & (1 << (7 - bit))) != 0 {
+                collide |= system.display.toggle(ix, iy);
+            }
         }
     }
+    system.display_updated();
     system.registers[0xF] = if collide { 1 } else { 0 };
+    system.record_reads(i, usize::from(n));
+    system.record(Event::SpriteDrawn {
+        x: vx,
+        y: vy,
+        rows: usize::from(n),
+        collision: collide,
+    });
+    system.waiting_for_vblank = system.quirks.display_wait;
     Ok(())
 }
 
-fn op_skp(system: &mut SystemState, x: u8) -> Result {
-    let vx = system.registers[usize::from(x)];
-    if vx > 0xF {
-        return err("invalid key value");
+fn op_drw_megachip(system: &mut SystemState, x: u8, y: u8) -> Result {
+    let (x, y) = (
+        system.registers[usize::from(x)],
+        system.registers[usize::from(y)],
+    );
+    let (vx, vy) = (usize::from(x), usize::from(y));
+    let width = megachip_sprite_dimension(system.sprite_width);
+    let height = megachip_sprite_dimension(system.sprite_height);
+
+    let i = sanitize_sprite_addr(system, width * height)?;
+    let sprite = read_memory(&system.memory, i, width * height);
+    // Sprites are one palette index per byte. Index 0 is transparent, and pixels
+    // falling outside of the screen are clipped rather than wrapped.
+    let mut collide = false;
+    for row in 0..height {
+        let iy = vy + row;
+        if iy >= system.display.height() {
+            break;
+        }
+        for col in 0..width {
+            let ix = vx + col;
+            if ix >= system.display.width() {
+                break;
+            }
+            let index = sprite[row * width + col];
+            if index == 0 {
+                continue;
+            }
+            collide = collide || system.display.pixel(ix, iy) == system.collision_color;
+            system.display.blend(ix, iy, index, system.blend_mode);
+        }
     }
+    system.registers[0xF] = if collide { 1 } else { 0 };
+    system.record_reads(i, width * height);
+    system.record(Event::SpriteDrawn {
+        x,
+        y,
+        rows: height,
+        collision: collide,
+    });
+    Ok(())
+}
+
+fn megachip_sprite_dimension(n: u8) -> usize {
+    if n == 0 {
+        256
+    } else {
+        usize::from(n)
+    }
+}
+
+fn op_skp(system: &mut SystemState, x: u8) -> Result {
+    let vx = nibble_operand(system, x, "key value")?;
     if system.keys[usize::from(vx)] {
         system.program_counter += 2;
     }
@@ -279,10 +470,7 @@ fn op_skp(system: &mut SystemState, x: u8) -> Result {
 }
 
 fn op_sknp(system: &mut SystemState, x: u8) -> Result {
-    let vx = system.registers[usize::from(x)];
-    if vx > 0xF {
-        return err("invalid key value");
-    }
+    let vx = nibble_operand(system, x, "key value")?;
     if !system.keys[usize::from(vx)] {
         system.program_counter += 2;
     }
@@ -321,16 +509,14 @@ fn op_ld_st(system: &mut SystemState, x: u8) -> Result {
 }
 
 fn op_add_i(system: &mut SystemState, x: u8) -> Result {
-    let (addr, _) = system
-        .address_register
-        .overflowing_add(u16::from(system.registers[usize::from(x)]));
-    system.address_register = addr;
+    let vx = u32::from(system.registers[usize::from(x)]);
+    system.address_register = (system.address_register + vx) & system.address_mask();
     Ok(())
 }
 
 fn op_ld_f(system: &mut SystemState, x: u8) -> Result {
-    let vx = system.registers[usize::from(x)];
-    system.address_register = system.get_sprite_location(vx)?;
+    let vx = nibble_operand(system, x, "sprite digit")?;
+    system.address_register = u32::from(system.get_sprite_location(vx)?);
     Ok(())
 }
 
@@ -338,29 +524,124 @@ fn op_ld_b(system: &mut SystemState, x: u8) -> Result {
     let vx = system.registers[usize::from(x)];
     let i = sanitize_addr(system, system.address_register, 3)?;
     let (hundreds, tens, ones) = bcd(vx);
-    system.memory[i..(i + 3)].copy_from_slice(&[hundreds, tens, ones]);
+    system.write_memory(i, &[hundreds, tens, ones]);
     Ok(())
 }
 
 fn op_store_regs(system: &mut SystemState, x: u8) -> Result {
     let x = usize::from(x);
     let i = sanitize_addr(system, system.address_register, x + 1)?;
-    system.memory[i..=i + x].copy_from_slice(&system.registers[0..=x]);
-    system.address_register = (i + x + 1) as u16;
+    let registers = system.registers;
+    system.write_memory(i, &registers[0..=x]);
+    if !system.quirks.load_store {
+        system.address_register = (i + x + 1) as u32;
+    }
     Ok(())
 }
 
 fn op_load_regs(system: &mut SystemState, x: u8) -> Result {
     let x = usize::from(x);
     let i = sanitize_addr(system, system.address_register, x)?;
-    system.registers[0..=x].copy_from_slice(&system.memory[i..=i + x]);
-    system.address_register = (i + x + 1) as u16;
+    system.registers[0..=x].copy_from_slice(&read_memory(&system.memory, i, x + 1));
+    system.record_reads(i, x + 1);
+    if !system.quirks.load_store {
+        system.address_register = (i + x + 1) as u32;
+    }
+    Ok(())
+}
+
+fn op_megaoff(system: &mut SystemState) -> Result {
+    system.set_megachip(false);
+    system.record(Event::MegachipModeChanged { enabled: false });
+    Ok(())
+}
+
+fn op_megaon(system: &mut SystemState) -> Result {
+    system.set_megachip(true);
+    system.record(Event::MegachipModeChanged { enabled: true });
+    Ok(())
+}
+
+fn op_ldhi(system: &mut SystemState, hi: u8) -> Result {
+    // The low 16 bits of the address are stored in the word following the opcode.
+    let lo = system.next_opcode();
+    system.address_register = (u32::from(hi) << 16) | u32::from(lo);
+    Ok(())
+}
+
+fn op_ldpal(system: &mut SystemState, count: u8) -> Result {
+    let n = usize::from(count);
+    let i = sanitize_addr(system, system.address_register, n * 4)?;
+    let colors = read_memory(&system.memory, i, n * 4).into_owned();
+    system.record_reads(i, n * 4);
+    // Colors are stored as ARGB, and are loaded starting at index 1 since index 0
+    // is always transparent.
+    for (c, color) in colors.chunks(4).enumerate() {
+        let argb = color
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u32::from(*byte));
+        system.display.set_palette_color((c + 1) as u8, argb);
+        system.presented.set_palette_color((c + 1) as u8, argb);
+    }
+    Ok(())
+}
+
+fn op_sprw(system: &mut SystemState, width: u8) -> Result {
+    system.sprite_width = width;
+    Ok(())
+}
+
+fn op_sprh(system: &mut SystemState, height: u8) -> Result {
+    system.sprite_height = height;
+    Ok(())
+}
+
+fn op_digisnd(system: &mut SystemState, n: u8) -> Result {
+    // Samples have a six byte header: a 16-bit sample rate, a 24-bit length,
+    // and a reserved byte. The 8-bit samples follow immediately after.
+    let i = sanitize_addr(system, system.address_register, 6)?;
+    let header = read_memory(&system.memory, i, 6);
+    let rate = (u16::from(header[0]) << 8) | u16::from(header[1]);
+    let len =
+        (usize::from(header[2]) << 16) | (usize::from(header[3]) << 8) | usize::from(header[4]);
+    let start = sanitize_addr(system, (i + 6) as u32, len)?;
+    system.record_reads(i, 6);
+    system.record_reads(start, len);
+    system.sample_command = Some(SampleCommand::Play(Sample {
+        rate,
+        data: read_memory(&system.memory, start, len).into_owned(),
+        looping: n == 0,
+    }));
+    Ok(())
+}
+
+fn op_stopsnd(system: &mut SystemState) -> Result {
+    system.sample_command = Some(SampleCommand::Stop);
+    Ok(())
+}
+
+fn op_bmode(system: &mut SystemState, n: u8) -> Result {
+    system.blend_mode = match BlendMode::from_nibble(n) {
+        Some(mode) => mode,
+        None => {
+            let policy = system.error_policies.invalid_operand;
+            system.report(policy, format!("invalid blend mode: {}", n))?;
+            BlendMode::Normal
+        }
+    };
+    Ok(())
+}
+
+fn op_ccol(system: &mut SystemState, index: u8) -> Result {
+    system.collision_color = index;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::platform::{MemoryLayout, Quirks};
+    use crate::system::MachineConfig;
 
     #[test]
     fn test_nibbles() {
@@ -386,8 +667,10 @@ mod tests {
 
     #[test]
     fn test_op_ld_b() {
-        let mut system = SystemState::default();
-        system.address_register = 0x800;
+        let mut system = SystemState {
+            address_register: 0x800,
+            ..Default::default()
+        };
         system.registers[8] = 123;
         op_ld_b(&mut system, 8).unwrap();
         assert_eq!(system.memory[0x800..0x803], [1, 2, 3]);
@@ -395,23 +678,178 @@ mod tests {
 
     #[test]
     fn test_sanitize_addr() {
-        let system = SystemState::default();
-        assert!(sanitize_addr(&system, 0x100, 1).is_err());
-        assert!(sanitize_addr(&system, 4100, 1).is_err());
-        assert!(sanitize_addr(&system, 4000, 100).is_err());
-        assert_eq!(sanitize_addr(&system, 4000, 95).unwrap(), 4000);
+        let mut system = SystemState::default();
+        assert!(sanitize_addr(&mut system, 0x100, 1).is_err());
+        assert!(sanitize_addr(&mut system, 4100, 1).is_err());
+        assert!(sanitize_addr(&mut system, 4000, 100).is_err());
+        assert_eq!(sanitize_addr(&mut system, 4000, 95).unwrap(), 4000);
+    }
+
+    #[test]
+    fn test_sanitize_addr_lenient() {
+        let mut system = SystemState::default();
+        system.error_policies.invalid_address = ErrorPolicy::Lenient;
+        assert_eq!(sanitize_addr(&mut system, 0x100, 1).unwrap(), 0x100);
+        assert_eq!(sanitize_addr(&mut system, 0x1004, 1).unwrap(), 4);
+        assert!(system.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_store_regs_wraps_when_lenient() {
+        let mut system = SystemState {
+            address_register: 0xFFE,
+            ..Default::default()
+        };
+        system.error_policies.invalid_address = ErrorPolicy::Lenient;
+        system.registers[0..3].copy_from_slice(&[1, 2, 3]);
+        op_store_regs(&mut system, 2).unwrap();
+        assert_eq!(&system.memory[0xFFE..], &[1, 2]);
+        assert_eq!(system.memory[0], 3);
+
+        system.address_register = 0xFFE;
+        system.registers = [0; 16];
+        op_load_regs(&mut system, 2).unwrap();
+        assert_eq!(&system.registers[0..3], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_unknown_opcode_policies() {
+        let mut system = SystemState::default();
+        assert!(OpcodeRunner
+            .run(&mut system, &mut Extensions::default(), 0xE0FF)
+            .is_err());
+        system.error_policies.unknown_opcode = ErrorPolicy::Warn;
+        OpcodeRunner
+            .run(&mut system, &mut Extensions::default(), 0xE0FF)
+            .unwrap();
+        assert_eq!(system.warnings.len(), 1);
+        system.error_policies.unknown_opcode = ErrorPolicy::Lenient;
+        OpcodeRunner
+            .run(&mut system, &mut Extensions::default(), 0xE0FF)
+            .unwrap();
+        assert_eq!(system.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_drw_sprite_overflow() {
+        let mut system = SystemState {
+            address_register: 0xFFF,
+            ..Default::default()
+        };
+        system.memory[0xFFF] = 0x80;
+        system.memory[0] = 0x40;
+        assert!(op_drw(&mut system, 0, 0, 2).is_err());
+
+        system.error_policies.sprite_overflow = ErrorPolicy::Lenient;
+        op_drw(&mut system, 0, 0, 2).unwrap();
+        assert_eq!(&system.display[0][0..2], &[1, 0]);
+        assert_eq!(&system.display[1][0..2], &[0, 1]);
+    }
+
+    #[test]
+    fn test_invalid_operands() {
+        let mut system = SystemState::default();
+        system.registers[1] = 0x13;
+        system.keys[3] = true;
+        system.blend_mode = BlendMode::Add;
+        assert!(op_skp(&mut system, 1).is_err());
+        assert!(op_sknp(&mut system, 1).is_err());
+        assert!(op_ld_f(&mut system, 1).is_err());
+        assert!(op_bmode(&mut system, 9).is_err());
+        assert_eq!(system.blend_mode, BlendMode::Add);
+
+        // Keys and digits use the low nibble, and unknown blend modes are normal.
+        system.error_policies.invalid_operand = ErrorPolicy::Lenient;
+        system.program_counter = 0x200;
+        op_skp(&mut system, 1).unwrap();
+        assert_eq!(system.program_counter, 0x202);
+        op_sknp(&mut system, 1).unwrap();
+        assert_eq!(system.program_counter, 0x202);
+        op_ld_f(&mut system, 1).unwrap();
+        assert_eq!(system.address_register, 15);
+        op_bmode(&mut system, 9).unwrap();
+        assert_eq!(system.blend_mode, BlendMode::Normal);
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut system = SystemState::default();
+        assert!(op_ret(&mut system).is_err());
+        for _ in 0..16 {
+            op_call(&mut system, 0x300).unwrap();
+        }
+        assert!(op_call(&mut system, 0x300).is_err());
+
+        // The stack wraps around when full, and returns from an empty one are skipped.
+        system.error_policies.stack = ErrorPolicy::Lenient;
+        system.program_counter = 0x240;
+        op_call(&mut system, 0x300).unwrap();
+        assert_eq!(system.stack_pointer, 1);
+        assert_eq!(system.program_counter, 0x300);
+        op_ret(&mut system).unwrap();
+        assert_eq!(system.program_counter, 0x240);
+        op_ret(&mut system).unwrap();
+        assert_eq!(system.program_counter, 0x240);
+    }
+
+    #[test]
+    fn test_sanitize_addr_vip_layout() {
+        let mut system = SystemState::with_config(MachineConfig {
+            layout: MemoryLayout::Vip,
+            ..Default::default()
+        });
+        assert_eq!(sanitize_addr(&mut system, 0x100, 1).unwrap(), 0x100);
+        assert!(sanitize_addr(&mut system, 4100, 1).is_err());
+    }
+
+    #[test]
+    fn test_vip_layout_stack_matches_vip() {
+        let mut system = SystemState::with_config(MachineConfig {
+            layout: MemoryLayout::Vip,
+            ..Default::default()
+        });
+        system.program_counter = 0x202;
+        op_call(&mut system, 0x340).unwrap();
+        system.program_counter = 0x342;
+        op_call(&mut system, 0x456).unwrap();
+        // Pushed downward from 0xECF, most significant byte first.
+        assert_eq!(
+            &system.memory[0xECA..0xED0],
+            &[0x00, 0x00, 0x03, 0x42, 0x02, 0x02][..]
+        );
+        assert_eq!(&system.memory[0xEA0..0xEA4], &[0; 4]);
+    }
+
+    #[test]
+    fn test_vip_layout_programs_see_display() {
+        let mut system = SystemState::with_config(MachineConfig {
+            layout: MemoryLayout::Vip,
+            ..Default::default()
+        });
+        system.memory[0x300] = 0xFF;
+        system.address_register = 0x300;
+        op_drw(&mut system, 0, 0, 1).unwrap();
+        assert_eq!(system.memory[0xF00], 0xFF);
+
+        // Reading the display back into registers.
+        system.address_register = 0xF00;
+        op_load_regs(&mut system, 1).unwrap();
+        assert_eq!(&system.registers[0..2], &[0xFF, 0x00]);
+
+        op_cls(&mut system).unwrap();
+        assert_eq!(system.memory[0xF00], 0);
     }
 
     #[test]
     fn test_cls() {
         let mut system = SystemState::default();
-        system.display[0][0..8].copy_from_slice(&random::<[bool; 8]>());
-        system.display[1][0..8].copy_from_slice(&random::<[bool; 8]>());
-        system.display[2][0..8].copy_from_slice(&random::<[bool; 8]>());
+        for (x, y) in (0..8).map(|_| rand::random::<(usize, usize)>()) {
+            system.display.toggle(x % 64, y % 32);
+        }
         op_cls(&mut system).unwrap();
-        for row in system.display.iter() {
-            for val in row.iter() {
-                assert_eq!(false, *val);
+        for y in 0..32 {
+            for x in 0..64 {
+                assert!(!system.display.is_lit(x, y));
             }
         }
     }
@@ -423,31 +861,241 @@ mod tests {
         system.address_register = 0x200;
         system.registers[5..8].copy_from_slice(&[30, 20, 10]);
         op_drw(&mut system, 6, 5, 1).unwrap();
-        assert_eq!(
-            &system.display[30][20..28],
-            &[true, false, false, true, false, true, false, true]
-        );
+        assert_eq!(&system.display[30][20..28], &[1, 0, 0, 1, 0, 1, 0, 1]);
         assert_eq!(system.registers[0xF], 0);
 
         system.memory[0x201] = 0b1110_0000;
         system.address_register = 0x201;
         op_drw(&mut system, 6, 5, 1).unwrap();
-        assert_eq!(
-            &system.display[30][20..28],
-            &[false, true, true, true, false, true, false, true]
-        );
+        assert_eq!(&system.display[30][20..28], &[0, 1, 1, 1, 0, 1, 0, 1]);
         assert_eq!(system.registers[0xF], 1);
 
         system.address_register = 0x200;
         op_drw(&mut system, 6, 7, 2).unwrap();
-        assert_eq!(
-            &system.display[10][20..28],
-            &[true, false, false, true, false, true, false, true]
+        assert_eq!(&system.display[10][20..28], &[1, 0, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(&system.display[11][20..28], &[1, 1, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(system.registers[0xF], 0);
+    }
+
+    fn quirks_system(quirks: Quirks) -> SystemState {
+        SystemState::with_config(MachineConfig {
+            quirks,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut system = SystemState::default();
+        system.registers[0..2].copy_from_slice(&[0x81, 0x06]);
+        op_shr(&mut system, 0, 1).unwrap();
+        assert_eq!(system.registers[0], 0x03);
+
+        let mut system = quirks_system(Quirks {
+            shift: true,
+            ..Default::default()
+        });
+        system.registers[0..2].copy_from_slice(&[0x81, 0x06]);
+        op_shr(&mut system, 0, 1).unwrap();
+        assert_eq!((system.registers[0], system.registers[0xF]), (0x40, 1));
+        op_shl(&mut system, 0, 1).unwrap();
+        assert_eq!((system.registers[0], system.registers[0xF]), (0x80, 0));
+    }
+
+    #[test]
+    fn test_load_store_quirk() {
+        let mut system = quirks_system(Quirks {
+            load_store: true,
+            ..Default::default()
+        });
+        system.address_register = 0x300;
+        system.registers[0..3].copy_from_slice(&[1, 2, 3]);
+        op_store_regs(&mut system, 2).unwrap();
+        assert_eq!(&system.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(system.address_register, 0x300);
+        op_load_regs(&mut system, 2).unwrap();
+        assert_eq!(system.address_register, 0x300);
+
+        system.quirks.load_store = false;
+        op_load_regs(&mut system, 2).unwrap();
+        assert_eq!(system.address_register, 0x303);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        let mut system = SystemState::default();
+        system.registers[0] = 0x10;
+        system.registers[3] = 0x20;
+        op_jp_v0_addr(&mut system, 0x345).unwrap();
+        assert_eq!(system.program_counter, 0x355);
+
+        system.quirks.jump = true;
+        op_jp_v0_addr(&mut system, 0x345).unwrap();
+        assert_eq!(system.program_counter, 0x365);
+    }
+
+    #[test]
+    fn test_logic_quirk() {
+        let mut system = SystemState::default();
+        system.registers[0xF] = 1;
+        op_or(&mut system, 0, 1).unwrap();
+        assert_eq!(system.registers[0xF], 1);
+
+        system.quirks.logic = true;
+        for op in [op_or, op_and, op_xor].iter() {
+            system.registers[0xF] = 1;
+            op(&mut system, 0, 1).unwrap();
+            assert_eq!(system.registers[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        let mut system = SystemState::default();
+        system.memory[0x200..0x202].copy_from_slice(&[0xFF, 0xFF]);
+        system.address_register = 0x200;
+        system.registers[0..2].copy_from_slice(&[60, 31]);
+        op_drw(&mut system, 0, 1, 2).unwrap();
+        assert_eq!(&system.display[0][0..4], &[1, 1, 1, 1]);
+
+        let mut system = quirks_system(Quirks {
+            clip: true,
+            ..Default::default()
+        });
+        system.memory[0x200..0x202].copy_from_slice(&[0xFF, 0xFF]);
+        system.address_register = 0x200;
+        system.registers[0..2].copy_from_slice(&[124, 31]);
+        op_drw(&mut system, 0, 1, 2).unwrap();
+        // The position wraps to (60, 31), and the rest of the sprite is clipped.
+        assert_eq!(&system.display[31][60..64], &[1, 1, 1, 1]);
+        assert_eq!(&system.display[31][0..4], &[0, 0, 0, 0]);
+        assert_eq!(&system.display[0][60..64], &[0, 0, 0, 0]);
+    }
+
+    fn megachip_system() -> SystemState {
+        let mut system = SystemState::with_platform(Platform::MegaChip8);
+        system.set_megachip(true);
+        system
+    }
+
+    #[test]
+    fn test_megachip_mode_switch() {
+        let runner = OpcodeRunner;
+        let mut system = SystemState::default();
+        runner
+            .run(&mut system, &mut Extensions::default(), 0x0011)
+            .unwrap();
+        assert!(
+            !system.megachip,
+            "MegaChip mode requires the MegaChip8 platform"
         );
+
+        let mut system = SystemState::with_platform(Platform::MegaChip8);
+        runner
+            .run(&mut system, &mut Extensions::default(), 0x0011)
+            .unwrap();
+        assert!(system.megachip);
+        assert_eq!(system.display.width(), 256);
+        runner
+            .run(&mut system, &mut Extensions::default(), 0x0010)
+            .unwrap();
+        assert!(!system.megachip);
+        assert_eq!(system.display.width(), 64);
+    }
+
+    #[test]
+    fn test_ldhi() {
+        let mut system = megachip_system();
+        system.program_counter = 0x202;
+        system.memory[0x202..0x204].copy_from_slice(&[0x34, 0x56]);
+        OpcodeRunner
+            .run(&mut system, &mut Extensions::default(), 0x0112)
+            .unwrap();
+        assert_eq!(system.address_register, 0x12_3456);
+        assert_eq!(system.program_counter, 0x204);
+    }
+
+    #[test]
+    fn test_ldpal() {
+        let mut system = megachip_system();
+        system.address_register = 0x1000;
+        system.memory[0x1000..0x1008]
+            .copy_from_slice(&[0xFF, 0x11, 0x22, 0x33, 0x80, 0x44, 0x55, 0x66]);
+        op_ldpal(&mut system, 2).unwrap();
+        assert_eq!(&system.display.palette()[1..3], &[0xFF11_2233, 0x8044_5566]);
         assert_eq!(
-            &system.display[11][20..28],
-            &[true, true, true, false, false, false, false, false]
+            &system.presented.palette()[1..3],
+            &[0xFF11_2233, 0x8044_5566]
         );
+    }
+
+    #[test]
+    fn test_drw_megachip() {
+        let mut system = megachip_system();
+        system.display.set_palette_color(1, 0xFFFF_0000);
+        system.display.set_palette_color(2, 0xFF00_FF00);
+        system.sprite_width = 2;
+        system.sprite_height = 2;
+        system.collision_color = 2;
+        system.address_register = 0x1000;
+        system.memory[0x1000..0x1004].copy_from_slice(&[1, 0, 2, 1]);
+        system.registers[0..2].copy_from_slice(&[10, 20]);
+
+        op_drw_megachip(&mut system, 0, 1).unwrap();
+        assert_eq!(&system.display[20][10..12], &[1, 0]);
+        assert_eq!(&system.display[21][10..12], &[2, 1]);
+        assert_eq!(system.display.color(10, 21), 0xFF00_FF00);
         assert_eq!(system.registers[0xF], 0);
+
+        // Drawing over a pixel with the collision color sets VF.
+        system.memory[0x1000..0x1004].copy_from_slice(&[0, 0, 1, 0]);
+        op_drw_megachip(&mut system, 0, 1).unwrap();
+        assert_eq!(&system.display[21][10..12], &[1, 1]);
+        assert_eq!(system.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_drw_megachip_clips() {
+        let mut system = megachip_system();
+        system.sprite_width = 4;
+        system.sprite_height = 1;
+        system.address_register = 0x1000;
+        system.memory[0x1000..0x1004].copy_from_slice(&[1, 1, 1, 1]);
+        system.registers[0..2].copy_from_slice(&[254, 0]);
+        op_drw_megachip(&mut system, 0, 1).unwrap();
+        assert_eq!(&system.display[0][252..256], &[0, 0, 1, 1]);
+        assert_eq!(system.display[0][0], 0);
+    }
+
+    #[test]
+    fn test_megachip_cls_presents_frame() {
+        let mut system = megachip_system();
+        system.display.blend(5, 5, 1, BlendMode::Normal);
+        op_cls(&mut system).unwrap();
+        assert!(system.presented.is_lit(5, 5));
+        assert!(!system.display.is_lit(5, 5));
+        assert!(system.visible_display().is_lit(5, 5));
+    }
+
+    #[test]
+    fn test_digisnd() {
+        let mut system = megachip_system();
+        system.address_register = 0x1000;
+        system.memory[0x1000..0x1009]
+            .copy_from_slice(&[0x1F, 0x40, 0x00, 0x00, 0x03, 0x00, 0x80, 0xFF, 0x00]);
+        op_digisnd(&mut system, 1).unwrap();
+        match system.sample_command.take() {
+            Some(SampleCommand::Play(sample)) => assert_eq!(
+                sample,
+                Sample {
+                    rate: 8000,
+                    data: vec![0x80, 0xFF, 0x00],
+                    looping: false,
+                }
+            ),
+            _ => panic!("expected a sample to be played"),
+        }
+        op_stopsnd(&mut system).unwrap();
+        assert!(matches!(system.sample_command, Some(SampleCommand::Stop)));
     }
 }
@@ -0,0 +1,56 @@
+/// The machine being emulated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8, with 4 KiB of memory and a 64x32 monochrome display.
+    #[default]
+    Chip8,
+    /// MegaChip8, which extends CHIP-8 with 16 MiB of memory and a 256x192 display
+    /// with a 256-color palette. Programs switch into MegaChip mode with `0011`.
+    MegaChip8,
+}
+
+impl Platform {
+    /// Amount of addressable memory, in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 => 0x1000,
+            Platform::MegaChip8 => 0x100_0000,
+        }
+    }
+}
+
+/// Where the call stack and display live.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryLayout {
+    /// The call stack and display are kept apart from main memory, and programs may
+    /// not access memory below 0x200.
+    #[default]
+    Separate,
+    /// The call stack and display are stored in main memory as on the COSMAC VIP,
+    /// with the stack growing down from 0xECF and the display at 0xF00, and all of
+    /// memory may be accessed. Programs that read or modify those regions directly
+    /// see the same values they would have on the VIP.
+    Vip,
+}
+
+/// Behaviors that differ between interpreters, which some programs depend on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `DXYN` waits for the next vertical blank before the program continues, as the
+    /// COSMAC VIP interpreter did. This limits programs to drawing one sprite per
+    /// frame.
+    pub display_wait: bool,
+    /// `8XY6` and `8XYE` shift VX in place and ignore VY, as on SUPER-CHIP, rather
+    /// than shifting VY into VX.
+    pub shift: bool,
+    /// `FX55` and `FX65` leave `I` unchanged, as on SUPER-CHIP, rather than advancing
+    /// it past the registers.
+    pub load_store: bool,
+    /// `BNNN` jumps to XNN + VX, as on SUPER-CHIP, rather than to NNN + V0.
+    pub jump: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0, as on the COSMAC VIP.
+    pub logic: bool,
+    /// `DXYN` clips sprites at the edges of the display rather than wrapping them
+    /// around. Sprites drawn at a position off the display still wrap to it.
+    pub clip: bool,
+}
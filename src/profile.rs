@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disasm::{self, OpcodeClass};
+use crate::observer::Observer;
+use crate::platform::Platform;
+
+/// Number of the most executed instructions listed in the report.
+const HOT_SPOTS: usize = 20;
+/// Number of bytes of memory on each row of the heatmap.
+const HEATMAP_WIDTH: usize = 64;
+
+/// Time spent in a subroutine, measured in instructions executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubroutineProfile {
+    pub address: u16,
+    pub calls: u64,
+    /// Instructions executed by the subroutine, including the subroutines it called.
+    pub total: u64,
+    /// Instructions executed by the subroutine itself.
+    pub own: u64,
+}
+
+/// A subroutine that was called and hasn't returned yet.
+struct Frame {
+    address: u16,
+    start: u64,
+    children: u64,
+}
+
+/// An observer that finds where a program spends its time. It counts how often each
+/// instruction is executed, how many instructions each subroutine takes (from the
+/// calls and returns the emulator reports), which kinds of instructions are run, and how often each byte
+/// of memory is accessed.
+///
+/// The `Display` implementation formats a text report.
+pub struct Profiler {
+    platform: Platform,
+    megachip: bool,
+    instructions: u64,
+    executions: HashMap<u16, u64>,
+    opcodes: HashMap<u16, (u16, bool)>,
+    classes: BTreeMap<OpcodeClass, u64>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    subroutines: HashMap<u16, SubroutineProfile>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn new(platform: Platform) -> Profiler {
+        Profiler {
+            platform,
+            megachip: false,
+            instructions: 0,
+            executions: HashMap::new(),
+            opcodes: HashMap::new(),
+            classes: BTreeMap::new(),
+            reads: HashMap::new(),
+            writes: HashMap::new(),
+            subroutines: HashMap::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// The number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// How many times the instruction at an address was executed.
+    pub fn executions(&self, pc: u16) -> u64 {
+        self.executions.get(&pc).cloned().unwrap_or(0)
+    }
+
+    /// How many instructions of each class were executed.
+    pub fn class_counts(&self) -> &BTreeMap<OpcodeClass, u64> {
+        &self.classes
+    }
+
+    /// The subroutines called, taking the most time first. Subroutines that haven't
+    /// returned yet are included, up to the current instruction.
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut subroutines = self.subroutines.clone();
+        let mut children = 0;
+        for frame in self.frames.iter().rev() {
+            let total = self.instructions - frame.start;
+            let subroutine = subroutines.get_mut(&frame.address).unwrap();
+            subroutine.total += total;
+            subroutine.own += total - frame.children - children;
+            children = total;
+        }
+        let mut subroutines: Vec<SubroutineProfile> = subroutines.values().cloned().collect();
+        subroutines.sort_by_key(|s| (std::cmp::Reverse(s.total), s.address));
+        subroutines
+    }
+
+    /// Writes an image of memory as a binary PPM, with one pixel per byte, scaled up by
+    /// `scale`. Each row holds 64 bytes. Red shows how often a byte was executed,
+    /// green how often it was read, and blue how often it was written.
+    pub fn write_heatmap(&self, out: &mut dyn Write, scale: usize) -> io::Result<()> {
+        let mut executed: HashMap<usize, u64> = HashMap::new();
+        for (&pc, &count) in self.executions.iter() {
+            for offset in 0..2 {
+                *executed.entry(usize::from(pc) + offset).or_insert(0) += count;
+            }
+        }
+        let highest = executed
+            .keys()
+            .chain(self.reads.keys())
+            .chain(self.writes.keys())
+            .max()
+            .map_or(0, |addr| addr + 1);
+        let size = highest.max(Platform::Chip8.memory_size());
+        // `usize::div_ceil` needs a newer compiler than the dependencies build with.
+        #[allow(clippy::manual_div_ceil)]
+        let rows = (size + HEATMAP_WIDTH - 1) / HEATMAP_WIDTH;
+
+        // Counts are shown on a log scale, so that rarely used bytes are still visible.
+        let scaled = |counts: &HashMap<usize, u64>| {
+            let max = ((counts.values().max().cloned().unwrap_or(0) + 1) as f64).ln();
+            let mut channel = vec![0; rows * HEATMAP_WIDTH];
+            for (&addr, &count) in counts.iter() {
+                channel[addr] = (((count + 1) as f64).ln() / max * 255.0) as u8;
+            }
+            channel
+        };
+        let (red, green, blue) = (scaled(&executed), scaled(&self.reads), scaled(&self.writes));
+
+        write!(out, "P6\n{} {}\n255\n", HEATMAP_WIDTH * scale, rows * scale)?;
+        let mut line = Vec::with_capacity(HEATMAP_WIDTH * scale * 3);
+        for row in 0..rows {
+            line.clear();
+            for addr in row * HEATMAP_WIDTH..(row + 1) * HEATMAP_WIDTH {
+                for _ in 0..scale {
+                    line.extend_from_slice(&[red[addr], green[addr], blue[addr]]);
+                }
+            }
+            for _ in 0..scale {
+                out.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction_executed(&mut self, pc: u16, opcode: u16) {
+        self.instructions += 1;
+        *self.executions.entry(pc).or_insert(0) += 1;
+
+        // MegaChip8 opcodes are only recognized once MegaChip mode is on, except for
+        // the instructions that turn it on and off.
+        let is_mode_switch = self.platform == Platform::MegaChip8 && opcode & 0xFFFE == 0x0010;
+        let megachip = self.megachip || is_mode_switch;
+        self.opcodes.insert(pc, (opcode, megachip));
+        *self
+            .classes
+            .entry(OpcodeClass::of(opcode, megachip))
+            .or_insert(0) += 1;
+    }
+
+    fn subroutine_called(&mut self, address: u16, _return_address: u16) {
+        self.subroutines
+            .entry(address)
+            .or_insert(SubroutineProfile {
+                address,
+                calls: 0,
+                total: 0,
+                own: 0,
+            })
+            .calls += 1;
+        self.frames.push(Frame {
+            address,
+            start: self.instructions,
+            children: 0,
+        });
+    }
+
+    fn subroutine_returned(&mut self, _return_address: u16) {
+        if let Some(frame) = self.frames.pop() {
+            let total = self.instructions - frame.start;
+            let subroutine = self.subroutines.get_mut(&frame.address).unwrap();
+            subroutine.total += total;
+            subroutine.own += total - frame.children;
+            if let Some(parent) = self.frames.last_mut() {
+                parent.children += total;
+            }
+        }
+    }
+
+    fn megachip_mode_changed(&mut self, enabled: bool) {
+        self.megachip = enabled;
+    }
+
+    fn memory_read(&mut self, address: usize, _value: u8) {
+        *self.reads.entry(address).or_insert(0) += 1;
+    }
+
+    fn memory_written(&mut self, address: usize, _value: u8) {
+        *self.writes.entry(address).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        writeln!(f, "instructions executed: {}", self.instructions)?;
+
+        writeln!(f, "\nhot spots:")?;
+        writeln!(f, "{:>10} {:>7}  {:<6}  instruction", "count", "%", "addr")?;
+        let mut hot_spots: Vec<(&u16, &u64)> = self.executions.iter().collect();
+        hot_spots.sort_by_key(|&(pc, count)| (std::cmp::Reverse(*count), *pc));
+        for (&pc, &count) in hot_spots.into_iter().take(HOT_SPOTS) {
+            let (opcode, megachip) = self.opcodes[&pc];
+            writeln!(
+                f,
+                "{:>10} {:>6.2}%  0x{:04X}  {}",
+                count,
+                percent(count),
+                pc,
+                disasm::disassemble(opcode, megachip)
+            )?;
+        }
+
+        let subroutines = self.subroutines();
+        if !subroutines.is_empty() {
+            writeln!(f, "\nsubroutines:")?;
+            writeln!(
+                f,
+                "{:>10} {:>10} {:>7} {:>10} {:>7}  addr",
+                "calls", "total", "%", "own", "%"
+            )?;
+            for s in subroutines {
+                writeln!(
+                    f,
+                    "{:>10} {:>10} {:>6.2}% {:>10} {:>6.2}%  0x{:04X}",
+                    s.calls,
+                    s.total,
+                    percent(s.total),
+                    s.own,
+                    percent(s.own),
+                    s.address
+                )?;
+            }
+        }
+
+        writeln!(f, "\ninstruction classes:")?;
+        for (class, &count) in self.classes.iter() {
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>6.2}%",
+                class.name(),
+                count,
+                percent(count)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EmulatorBuilder, ErrorPolicies, ErrorPolicy, StopCondition};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Passes instructions to the profiler, with the calls and returns the emulator
+    /// would report for them.
+    fn run(profiler: &mut Profiler, instructions: &[(u16, u16)]) {
+        for &(pc, opcode) in instructions {
+            profiler.instruction_executed(pc, opcode);
+            if opcode & 0xF000 == 0x2000 {
+                profiler.subroutine_called(opcode & 0xFFF, pc + 2);
+            } else if opcode == 0x00EE {
+                profiler.subroutine_returned(0);
+            }
+        }
+    }
+
+    #[test]
+    fn subroutine_time() {
+        let mut profiler = Profiler::new(Platform::Chip8);
+        // 0x200: CALL 0x300; JP 0x200
+        // 0x300: CALL 0x400; RET
+        // 0x400: LD V0, 1; RET
+        let call = [
+            (0x200, 0x2300),
+            (0x300, 0x2400),
+            (0x400, 0x6001),
+            (0x402, 0x00EE),
+            (0x302, 0x00EE),
+            (0x202, 0x1200),
+        ];
+        run(&mut profiler, &call);
+        run(&mut profiler, &call[..3]);
+
+        assert_eq!(profiler.instructions(), 9);
+        assert_eq!(profiler.executions(0x200), 2);
+        assert_eq!(profiler.class_counts()[&OpcodeClass::Flow], 7);
+        let subroutines = profiler.subroutines();
+        assert_eq!(
+            subroutines[0],
+            SubroutineProfile {
+                address: 0x300,
+                calls: 2,
+                total: 6,
+                own: 3,
+            }
+        );
+        assert_eq!(
+            subroutines[1],
+            SubroutineProfile {
+                address: 0x400,
+                calls: 2,
+                total: 3,
+                own: 3,
+            }
+        );
+        assert!(profiler
+            .to_string()
+            .contains("         2  22.22%  0x0200  CALL 0x300"));
+    }
+
+    #[test]
+    fn follows_the_emulator() {
+        // 0x200: RET, which is skipped, since nothing was called
+        // 0x202: CALL 0x206; JP 0x204
+        // 0x206: MEGAON; STOPSND; RET
+        let program = [
+            0x00, 0xEE, 0x22, 0x06, 0x12, 0x04, 0x00, 0x11, 0x07, 0x00, 0x00, 0xEE,
+        ];
+        let mut emulator = EmulatorBuilder::new()
+            .platform(Platform::MegaChip8)
+            .error_policies(ErrorPolicies::all(ErrorPolicy::Lenient))
+            .build()
+            .unwrap();
+        emulator.load_program(&program).unwrap();
+        let profiler = Rc::new(RefCell::new(Profiler::new(Platform::MegaChip8)));
+        emulator.add_observer(Box::new(profiler.clone()));
+        emulator.run_until(&[StopCondition::Cycles(6)]).unwrap();
+
+        let profiler = profiler.borrow();
+        assert_eq!(
+            profiler.subroutines(),
+            [SubroutineProfile {
+                address: 0x206,
+                calls: 1,
+                total: 3,
+                own: 3,
+            }]
+        );
+        assert_eq!(profiler.class_counts()[&OpcodeClass::Sound], 1);
+    }
+
+    #[test]
+    fn heatmap() {
+        let mut profiler = Profiler::new(Platform::Chip8);
+        run(&mut profiler, &[(0x200, 0x1200)]);
+        profiler.memory_written(0x241, 1);
+        let mut image = Vec::new();
+        profiler.write_heatmap(&mut image, 2).unwrap();
+        let header = b"P6\n128 128\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 128 * 128 * 3);
+        let pixel = |x: usize, y: usize| &pixels[(y * 128 + x) * 3..(y * 128 + x) * 3 + 3];
+        // 0x200 is at row 8, column 0, and 0x241 is at row 9, column 1.
+        assert_eq!(pixel(0, 16), &[255, 0, 0]);
+        assert_eq!(pixel(3, 19), &[0, 0, 255]);
+        assert_eq!(pixel(4, 19), &[0, 0, 0]);
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt::Write;
+
+use crate::platform::{Platform, Quirks};
+
+/// The colors a program was meant to be shown in, as ARGB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Colors {
+    pub foreground: u32,
+    pub background: u32,
+}
+
+/// What is known about a program from the ROM database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: &'static str,
+    pub author: &'static str,
+    /// The machine the program was written for.
+    pub platform: Platform,
+    /// The interpreter behaviors the program depends on.
+    pub quirks: Quirks,
+    /// The number of instructions per frame the program runs best at, if it matters.
+    pub instructions_per_frame: Option<u32>,
+    /// What the keys used by the program do, by keypad key.
+    pub keys: &'static [(u8, &'static str)],
+    pub colors: Option<Colors>,
+}
+
+/// Which settings were chosen by the host, and so aren't taken from the ROM database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExplicitSettings {
+    pub platform: bool,
+    /// Which quirks were chosen, as a quirk set with each chosen quirk turned on.
+    pub quirks: Quirks,
+    pub clock_speed: bool,
+}
+
+impl ExplicitSettings {
+    /// Every quirk, for when the whole quirk set is chosen.
+    pub const ALL_QUIRKS: Quirks = Quirks {
+        display_wait: true,
+        shift: true,
+        load_store: true,
+        jump: true,
+        logic: true,
+        clip: true,
+    };
+
+    /// Takes the quirks that weren't chosen from a program's quirks, and keeps the
+    /// chosen ones from `quirks`.
+    pub fn merge_quirks(&self, quirks: Quirks, program: Quirks) -> Quirks {
+        let pick = |chosen, ours, theirs| if chosen { ours } else { theirs };
+        let explicit = self.quirks;
+        Quirks {
+            display_wait: pick(
+                explicit.display_wait,
+                quirks.display_wait,
+                program.display_wait,
+            ),
+            shift: pick(explicit.shift, quirks.shift, program.shift),
+            load_store: pick(explicit.load_store, quirks.load_store, program.load_store),
+            jump: pick(explicit.jump, quirks.jump, program.jump),
+            logic: pick(explicit.logic, quirks.logic, program.logic),
+            clip: pick(explicit.clip, quirks.clip, program.clip),
+        }
+    }
+}
+
+/// Known programs, by the SHA-1 hash of the ROM in lowercase hexadecimal, sorted by
+/// hash. The table is generated from the community database by the build script.
+static DATABASE: &[(&str, RomInfo)] = include!(concat!(env!("OUT_DIR"), "/database.rs"));
+
+/// Looks a program up in the ROM database.
+pub fn lookup_rom(program: &[u8]) -> Option<&'static RomInfo> {
+    let mut hash = String::new();
+    for byte in sha1(program).iter() {
+        write!(hash, "{:02x}", byte).unwrap();
+    }
+    DATABASE
+        .binary_search_by(|(key, _)| (*key).cmp(&hash))
+        .ok()
+        .map(|i| &DATABASE[i].1)
+}
+
+/// Computes the SHA-1 hash of some data.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    // The message is padded with a 1 bit, zeros, and its length in bits, to a
+    // multiple of 64 bytes.
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5A82_7999),
+                1 => (b ^ c ^ d, 0x6ED9_EBA1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip(&[a, b, c, d, e]) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    let mut hash = [0; 20];
+    for (bytes, word) in hash.chunks_mut(4).zip(h.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emulator, EmulatorBuilder};
+
+    const CHIP8: RomInfo = RomInfo {
+        title: "",
+        author: "",
+        platform: Platform::Chip8,
+        quirks: Quirks {
+            display_wait: false,
+            shift: false,
+            load_store: false,
+            jump: false,
+            logic: false,
+            clip: false,
+        },
+        instructions_per_frame: None,
+        keys: &[],
+        colors: None,
+    };
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            sha1(b"abc"),
+            [
+                0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E, 0x25, 0x71, 0x78, 0x50,
+                0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D
+            ]
+        );
+        // Long enough that the padding needs a second block.
+        assert_eq!(sha1(&[0x61; 60])[..4], [0x13, 0xD9, 0x56, 0x03][..]);
+    }
+
+    #[test]
+    fn known_roms() {
+        let maze = [
+            0xA2, 0x1E, 0xC2, 0x01, 0x32, 0x01, 0xA2, 0x1A, 0xD0, 0x14, 0x70, 0x04, 0x30, 0x40,
+            0x12, 0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40,
+            0x20, 0x10, 0x20, 0x40, 0x80, 0x10,
+        ];
+        assert_eq!(lookup_rom(&maze).map(|info| info.title), Some("Maze"));
+        assert_eq!(lookup_rom(&maze[1..]), None);
+        // Maze is listed for the original CHIP-8, whose quirks it gets.
+        let info = lookup_rom(&maze).unwrap();
+        assert_eq!(info.platform, Platform::Chip8);
+        assert_eq!(
+            info.quirks,
+            Quirks {
+                display_wait: true,
+                logic: true,
+                clip: true,
+                ..Default::default()
+            }
+        );
+
+        let mut emulator = Emulator::default();
+        emulator.load_program(&maze).unwrap();
+        assert_eq!(
+            emulator.rom_info().map(|info| info.author),
+            Some("David Winter")
+        );
+        emulator.reset();
+        assert_eq!(emulator.rom_info(), None);
+
+        let ibm_logo = [
+            0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39,
+            0xD0, 0x1F, 0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F,
+            0x70, 0x08, 0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28,
+            0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00,
+            0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF,
+            0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00,
+            0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39,
+            0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+            0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+            0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+        ];
+        let info = lookup_rom(&ibm_logo).unwrap();
+        assert_eq!((info.title, info.author), ("IBM Logo", ""));
+        assert_eq!(info.platform, Platform::Chip8);
+    }
+
+    #[test]
+    fn database_is_sorted() {
+        // Lookups search the table, so it must be sorted and hold each hash once.
+        assert!(DATABASE.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(DATABASE
+            .iter()
+            .all(|(hash, _)| hash.len() == 40 && *hash == hash.to_lowercase()));
+    }
+
+    #[test]
+    fn too_long_known_roms() {
+        static CHIP8_INFO: RomInfo = RomInfo {
+            quirks: Quirks {
+                display_wait: true,
+                ..CHIP8.quirks
+            },
+            instructions_per_frame: Some(30),
+            ..CHIP8
+        };
+        static MEGACHIP_INFO: RomInfo = RomInfo {
+            platform: Platform::MegaChip8,
+            ..CHIP8_INFO
+        };
+        let program = vec![0; 0xE01];
+
+        // A failed load changes none of the program's settings.
+        let mut emulator = Emulator::default();
+        assert!(emulator
+            .load_known_program(&program, Some(&CHIP8_INFO))
+            .is_err());
+        assert_eq!(emulator.system.quirks, Quirks::default());
+        assert_eq!(emulator.clock_speed(), 1200);
+        assert_eq!(emulator.rom_info(), None);
+        emulator.load_program(&[0x12, 0x00]).unwrap();
+
+        // The program fits on the platform it is listed for.
+        let mut emulator = Emulator::default();
+        emulator
+            .load_known_program(&program, Some(&MEGACHIP_INFO))
+            .unwrap();
+        assert_eq!(emulator.view().platform(), Platform::MegaChip8);
+        assert_eq!(emulator.clock_speed(), 1800);
+    }
+
+    #[test]
+    fn explicit_settings_win() {
+        let info = RomInfo {
+            platform: Platform::MegaChip8,
+            quirks: Quirks {
+                display_wait: true,
+                logic: true,
+                ..Default::default()
+            },
+            instructions_per_frame: Some(30),
+            ..CHIP8
+        };
+        let mut emulator = Emulator::default();
+        emulator.apply_rom_info(&info);
+        assert_eq!(emulator.view().platform(), Platform::MegaChip8);
+        assert_eq!(emulator.clock_speed(), 1800);
+
+        let mut emulator = EmulatorBuilder::new()
+            .platform(Platform::Chip8)
+            .clock_speed(500)
+            .build()
+            .unwrap();
+        emulator.apply_rom_info(&info);
+        assert_eq!(emulator.view().platform(), Platform::Chip8);
+        assert_eq!(emulator.clock_speed(), 500);
+
+        let mut emulator = Emulator::with_platform(Platform::Chip8);
+        emulator.apply_rom_info(&info);
+        assert_eq!(emulator.view().platform(), Platform::Chip8);
+        assert_eq!(emulator.clock_speed(), 1800);
+
+        // Choosing one quirk keeps the program's other quirks.
+        let mut emulator = EmulatorBuilder::new()
+            .display_wait(false)
+            .clip_quirk(true)
+            .build()
+            .unwrap();
+        emulator.apply_rom_info(&info);
+        assert_eq!(
+            emulator.system.quirks,
+            Quirks {
+                logic: true,
+                clip: true,
+                ..Default::default()
+            }
+        );
+        let mut emulator = EmulatorBuilder::new()
+            .quirks(Quirks::default())
+            .build()
+            .unwrap();
+        emulator.apply_rom_info(&info);
+        assert_eq!(emulator.system.quirks, Quirks::default());
+    }
+}
@@ -1,3 +1,8 @@
+use crate::errors::*;
+
+/// Size of a font, in bytes: 16 hexadecimal digits of 5 bytes each.
+pub const FONT_SIZE: usize = 80;
+
 pub const HEX_DIGITS: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
     0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
@@ -5,3 +10,125 @@ pub const HEX_DIGITS: [u8; 80] = [
     0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80,
     0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
+
+const VIP_DIGITS: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x60, 0x20, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
+    0x10, 0xF0, 0x10, 0xF0, 0xA0, 0xA0, 0xF0, 0x20, 0x20, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
+    0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x10, 0x10, 0x10, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0,
+    0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x50, 0x70, 0x50, 0xF0, 0xF0, 0x80, 0x80, 0x80,
+    0xF0, 0xF0, 0x50, 0x50, 0x50, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+const DREAM_6800_DIGITS: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x40, 0x40, 0x40, 0x40, 0x40, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0,
+    0x20, 0xE0, 0x20, 0xE0, 0x80, 0xA0, 0xA0, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, 0xE0, 0x80,
+    0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0,
+    0x20, 0xE0, 0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xE0, 0xA0, 0xC0, 0xE0, 0x80, 0x80, 0x80,
+    0xE0, 0xC0, 0xA0, 0xA0, 0xA0, 0xC0, 0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+const ETI_660_DIGITS: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0x20, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0,
+    0x20, 0xE0, 0x20, 0xE0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, 0xE0, 0x80,
+    0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0,
+    0x20, 0xE0, 0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0x80, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x80, 0x80, 0x80,
+    0xE0, 0x20, 0x20, 0xE0, 0xA0, 0xE0, 0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+const SCHIP_DIGITS: [u8; 80] = [
+    0x60, 0x90, 0x90, 0x90, 0x60, 0x20, 0x60, 0x20, 0x20, 0x70, 0xE0, 0x10, 0x60, 0x80, 0xF0, 0xE0,
+    0x10, 0x60, 0x10, 0xE0, 0x20, 0x60, 0xA0, 0xF0, 0x20, 0xF0, 0x80, 0xE0, 0x10, 0xE0, 0x60, 0x80,
+    0xE0, 0x90, 0x60, 0xF0, 0x10, 0x20, 0x40, 0x40, 0x60, 0x90, 0x60, 0x90, 0x60, 0x60, 0x90, 0x70,
+    0x10, 0x60, 0x60, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0x60, 0x90, 0x80, 0x90,
+    0x60, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xE0, 0x80, 0xF0, 0xF0, 0x80, 0xE0, 0x80, 0x80,
+];
+
+/// Checks that a font stored at `address` lies below the program start at 0x200.
+pub fn check_font_address(address: u16) -> Result {
+    if usize::from(address) + FONT_SIZE > 0x200 {
+        return err(&format!(
+            "font at 0x{:03X} would overlap the program at 0x200",
+            address
+        ));
+    }
+    Ok(())
+}
+
+/// Sprites for the hexadecimal digits 0-F, which programs locate with `FX29`.
+///
+/// Several fonts used by historical interpreters are provided. Some programs compare
+/// against the exact bytes of the font they were written for, so they only look
+/// correct when run with that font.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Font([u8; FONT_SIZE]);
+
+impl Font {
+    /// The font of the COSMAC VIP monitor ROM.
+    pub const VIP: Font = Font(VIP_DIGITS);
+    /// The font of the DREAM 6800, which is three pixels wide.
+    pub const DREAM_6800: Font = Font(DREAM_6800_DIGITS);
+    /// The font of the ETI-660, which is three pixels wide.
+    pub const ETI_660: Font = Font(ETI_660_DIGITS);
+    /// The small font of SUPER-CHIP 1.1.
+    pub const SCHIP: Font = Font(SCHIP_DIGITS);
+    /// The font used by Octo, and by this emulator by default.
+    pub const OCTO: Font = Font(HEX_DIGITS);
+
+    /// Creates a custom font from 80 bytes of sprite data, with 5 bytes per digit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not exactly 80 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Font, Error> {
+        if bytes.len() != FONT_SIZE {
+            return err(&format!(
+                "font must be {} bytes, got {} bytes",
+                FONT_SIZE,
+                bytes.len()
+            ));
+        }
+        let mut data = [0; FONT_SIZE];
+        data.copy_from_slice(bytes);
+        Ok(Font(data))
+    }
+
+    /// The sprite data, with 5 bytes per digit.
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font::OCTO
+    }
+}
+
+impl std::fmt::Debug for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("Font").field(&&self.0[..]).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_font_size() {
+        assert!(Font::from_bytes(&[0xFF; 79]).is_err());
+        assert!(Font::from_bytes(&[0xFF; 81]).is_err());
+        assert_eq!(
+            Font::from_bytes(&[0xFF; 80]).unwrap().bytes(),
+            &[0xFF; 80][..]
+        );
+    }
+
+    #[test]
+    fn schip_font_is_its_own() {
+        assert!(Font::SCHIP != Font::OCTO);
+        // SUPER-CHIP's zero is rounded, where Octo's is square.
+        assert_eq!(&Font::SCHIP.bytes()[0..5], &[0x60, 0x90, 0x90, 0x90, 0x60]);
+        assert_eq!(&Font::OCTO.bytes()[0..5], &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    }
+}
@@ -1,19 +1,97 @@
+use rand::prng::XorShiftRng;
+use rand::{FromEntropy, SeedableRng};
+
+use crate::audio::SampleCommand;
+use crate::display::{self, BlendMode, Display};
 use crate::errors::*;
-use crate::sprites;
+use crate::observer::Event;
+use crate::platform::{MemoryLayout, Platform, Quirks};
+use crate::sprites::{self, Font};
+use crate::vip::Vip;
+
+/// Where programs are loaded.
+pub(crate) const PROGRAM_START: usize = 0x200;
+/// Start of the memory the COSMAC VIP interpreter reserves for its call stack, which
+/// programs must end before with the VIP memory layout.
+pub const VIP_STACK_ADDRESS: usize = 0xEA0;
+/// The first byte of the call stack with the VIP memory layout. As on the VIP, the
+/// stack grows downward from here, and each entry is stored as two bytes, most
+/// significant first.
+pub const VIP_STACK_TOP: usize = 0xECF;
+/// The number of return addresses the call stack holds with the VIP memory layout.
+/// The interpreter's 48 bytes of stack are shared with its interrupt routine, which
+/// leaves room for 12 levels of nesting.
+pub const VIP_STACK_DEPTH: usize = 12;
+/// Location of the display in memory with the VIP memory layout. Each row is 8 bytes,
+/// with the most significant bit of each byte being the leftmost pixel.
+pub const VIP_DISPLAY_ADDRESS: usize = 0xF00;
 
+/// Maximum number of warnings kept until they are taken by the emulator's owner.
+const MAX_WARNINGS: usize = 64;
+
+/// Settings that determine how a system is set up when it is created or reset.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MachineConfig {
+    pub platform: Platform,
+    pub layout: MemoryLayout,
+    pub font: Font,
+    pub font_address: u16,
+    pub error_policies: ErrorPolicies,
+    pub quirks: Quirks,
+    pub rng_seed: Option<u64>,
+}
+
+impl MachineConfig {
+    /// The size of the largest program that can be loaded.
+    pub fn max_program_size(&self) -> usize {
+        let end = match self.layout {
+            MemoryLayout::Separate => self.platform.memory_size(),
+            MemoryLayout::Vip => VIP_STACK_ADDRESS,
+        };
+        end - PROGRAM_START
+    }
+}
+
+#[derive(Clone)]
 pub struct SystemState {
-    pub memory: [u8; 4096],
+    pub platform: Platform,
+    pub layout: MemoryLayout,
+    pub memory: Vec<u8>,
+    pub font_address: u16,
     pub registers: [u8; 16],
-    pub address_register: u16,
+    pub address_register: u32,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub program_counter: u16,
     pub stack_pointer: u8,
     pub stack: [u16; 16],
-    pub display: [[bool; 64]; 32],
+    pub display: Display,
     pub keys: [bool; 16],
     pub waiting_for_key: bool,
     pub pending_keypress: Option<u8>,
+    pub waiting_for_vblank: bool,
+    pub halted: bool,
+    pub error_policies: ErrorPolicies,
+    pub quirks: Quirks,
+    pub warnings: Vec<Error>,
+    pub rng: XorShiftRng,
+    // Events for observers, recorded only while `observing` is set.
+    pub observing: bool,
+    pub events: Vec<Event>,
+
+    // MegaChip8 state. Drawing happens on `display`, which is copied to
+    // `presented` and cleared by 00E0 while in MegaChip mode.
+    pub megachip: bool,
+    pub presented: Display,
+    pub sprite_width: u8,
+    pub sprite_height: u8,
+    pub blend_mode: BlendMode,
+    pub collision_color: u8,
+    pub sample_command: Option<SampleCommand>,
+
+    // State of the emulated COSMAC VIP hardware, when running the original
+    // interpreter instead of interpreting instructions directly.
+    pub vip: Option<Box<Vip>>,
 }
 
 impl Default for SystemState {
@@ -24,8 +102,33 @@ impl Default for SystemState {
 
 impl SystemState {
     pub fn new() -> SystemState {
+        SystemState::with_config(MachineConfig::default())
+    }
+
+    #[cfg(test)]
+    pub fn with_platform(platform: Platform) -> SystemState {
+        SystemState::with_config(MachineConfig {
+            platform,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: MachineConfig) -> SystemState {
+        let MachineConfig {
+            platform,
+            layout,
+            font,
+            font_address,
+            error_policies,
+            quirks,
+            rng_seed,
+        } = config;
+        let lores = || Display::new(display::LORES_WIDTH, display::LORES_HEIGHT);
         let mut system = SystemState {
-            memory: [0; 4096],
+            platform,
+            layout,
+            memory: vec![0; platform.memory_size()],
+            font_address,
             registers: [0; 16],
             address_register: 0,
             delay_timer: 0,
@@ -33,31 +136,194 @@ impl SystemState {
             program_counter: 0,
             stack_pointer: 0,
             stack: [0; 16],
-            display: [[false; 64]; 32],
+            display: lores(),
             keys: [false; 16],
             waiting_for_key: false,
             pending_keypress: None,
+            waiting_for_vblank: false,
+            halted: false,
+            error_policies,
+            quirks,
+            warnings: Vec::new(),
+            rng: match rng_seed {
+                Some(seed) => {
+                    let mut bytes = [0; 16];
+                    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+                    XorShiftRng::from_seed(bytes)
+                }
+                None => XorShiftRng::from_entropy(),
+            },
+            observing: false,
+            events: Vec::new(),
+            megachip: false,
+            presented: lores(),
+            sprite_width: 0,
+            sprite_height: 0,
+            blend_mode: BlendMode::Normal,
+            collision_color: 0,
+            sample_command: None,
+            vip: None,
         };
-        system.load_sprites();
+        system.load_sprites(&font);
         system
     }
 
     pub fn next_opcode(&mut self) -> u16 {
-        let addr = self.program_counter as usize;
-        let opcode = (u16::from(self.memory[addr]) << 8) | u16::from(self.memory[addr + 1]);
+        let len = self.memory.len();
+        let addr = usize::from(self.program_counter) % len;
+        let opcode = (u16::from(self.memory[addr]) << 8) | u16::from(self.memory[(addr + 1) % len]);
         self.program_counter += 2;
         opcode
     }
 
     pub fn load_program(&mut self, program: &[u8]) -> Result {
-        if program.len() > self.memory.len() - 0x200 {
+        let end = match self.layout {
+            MemoryLayout::Separate => self.memory.len(),
+            MemoryLayout::Vip => VIP_STACK_ADDRESS,
+        };
+        if program.len() > end - PROGRAM_START {
             return err("program too long");
         }
-        self.memory[0x200..0x200 + program.len()].copy_from_slice(program);
-        self.program_counter = 0x200;
+        self.memory[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+        self.program_counter = PROGRAM_START as u16;
         Ok(())
     }
 
+    /// The lowest address programs are allowed to access.
+    pub fn lowest_address(&self) -> usize {
+        match self.layout {
+            MemoryLayout::Separate => PROGRAM_START,
+            MemoryLayout::Vip => 0,
+        }
+    }
+
+    /// Checks that `len` bytes starting at `addr` may be accessed by programs.
+    pub fn check_address(&self, addr: usize, len: usize) -> Result<usize> {
+        if addr < self.lowest_address() || addr + len >= self.memory.len() {
+            return err(&format!("invalid address: 0x{:X}", addr));
+        }
+        Ok(addr)
+    }
+
+    /// Records an event for observers, if any are attached.
+    pub fn record(&mut self, event: Event) {
+        if self.observing {
+            self.events.push(event);
+        }
+    }
+
+    /// Records that the program read `len` bytes starting at `addr`, wrapping around
+    /// to address 0 at the end of memory, if any observers are attached.
+    pub fn record_reads(&mut self, addr: usize, len: usize) {
+        if !self.observing {
+            return;
+        }
+        let size = self.memory.len();
+        for address in (addr..addr + len).map(|a| a % size) {
+            let value = self.memory[address];
+            self.events.push(Event::MemoryRead { address, value });
+        }
+    }
+
+    /// Writes bytes to memory, wrapping around to address 0 at the end of memory.
+    /// With the VIP memory layout, writes to the stack or display regions also update
+    /// the stack or display.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) {
+        if addr + bytes.len() > self.memory.len() {
+            let (head, tail) = bytes.split_at(self.memory.len() - addr);
+            self.write_memory(addr, head);
+            self.write_memory(0, tail);
+            return;
+        }
+        self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+        if self.observing {
+            for (i, &value) in bytes.iter().enumerate() {
+                let address = addr + i;
+                self.events.push(Event::MemoryWritten { address, value });
+            }
+        }
+        if self.layout != MemoryLayout::Vip {
+            return;
+        }
+        let end = addr + bytes.len();
+        if addr <= VIP_STACK_TOP && end > vip_stack_entry(VIP_STACK_DEPTH - 1) {
+            for (i, entry) in self.stack[..VIP_STACK_DEPTH].iter_mut().enumerate() {
+                let a = vip_stack_entry(i);
+                *entry = (u16::from(self.memory[a]) << 8) | u16::from(self.memory[a + 1]);
+            }
+        }
+        let display_end = VIP_DISPLAY_ADDRESS + display::LORES_WIDTH * display::LORES_HEIGHT / 8;
+        if addr < display_end && end > VIP_DISPLAY_ADDRESS {
+            for y in 0..display::LORES_HEIGHT {
+                for x in 0..display::LORES_WIDTH {
+                    let byte = self.memory[VIP_DISPLAY_ADDRESS + y * 8 + x / 8];
+                    self.display.set(x, y, byte & (0x80 >> (x % 8)) != 0);
+                }
+            }
+        }
+    }
+
+    /// Copies the display into memory if the VIP memory layout is in use. Must be called
+    /// after the display is modified.
+    pub fn display_updated(&mut self) {
+        if self.layout != MemoryLayout::Vip {
+            return;
+        }
+        for y in 0..display::LORES_HEIGHT {
+            for col in 0..display::LORES_WIDTH / 8 {
+                let byte = (0..8)
+                    .filter(|bit| self.display.is_lit(col * 8 + bit, y))
+                    .fold(0, |acc, bit| acc | (0x80 >> bit));
+                self.memory[VIP_DISPLAY_ADDRESS + y * 8 + col] = byte;
+            }
+        }
+    }
+
+    /// Handles an error caused by the program according to the given policy. Returns
+    /// the error if execution should stop, or records a warning if one was requested.
+    /// When this returns `Ok`, the caller should carry on with the lenient behavior.
+    pub fn report(&mut self, policy: ErrorPolicy, message: String) -> Result {
+        match policy {
+            ErrorPolicy::Strict => Err(Error(message)),
+            ErrorPolicy::Warn => {
+                if self.warnings.len() < MAX_WARNINGS {
+                    self.warnings.push(Error(format!(
+                        "{}; pc=0x{:04X}",
+                        message, self.program_counter
+                    )));
+                }
+                Ok(())
+            }
+            ErrorPolicy::Lenient => Ok(()),
+        }
+    }
+
+    pub fn push_stack(&mut self, addr: u16) -> Result {
+        let sp = usize::from(self.stack_pointer);
+        let depth = match self.layout {
+            MemoryLayout::Separate => self.stack.len(),
+            MemoryLayout::Vip => VIP_STACK_DEPTH,
+        };
+        if sp >= depth {
+            return err("stack overflow");
+        }
+        self.stack[sp] = addr;
+        self.stack_pointer += 1;
+        if self.layout == MemoryLayout::Vip {
+            let a = vip_stack_entry(sp);
+            self.memory[a..a + 2].copy_from_slice(&[(addr >> 8) as u8, addr as u8]);
+        }
+        Ok(())
+    }
+
+    pub fn pop_stack(&mut self) -> std::result::Result<u16, Error> {
+        if self.stack_pointer == 0 {
+            return err("can't return from empty call stack");
+        }
+        self.stack_pointer -= 1;
+        Ok(self.stack[usize::from(self.stack_pointer)])
+    }
+
     pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -67,19 +333,57 @@ impl SystemState {
         }
     }
 
+    /// Switches MegaChip mode on or off, which changes the display resolution and
+    /// clears the screen.
+    pub fn set_megachip(&mut self, enabled: bool) {
+        let (width, height) = if enabled {
+            (display::MEGACHIP_WIDTH, display::MEGACHIP_HEIGHT)
+        } else {
+            (display::LORES_WIDTH, display::LORES_HEIGHT)
+        };
+        self.megachip = enabled;
+        self.display.resize(width, height);
+        self.presented.resize(width, height);
+    }
+
+    /// The display that should be shown to the user.
+    pub fn visible_display(&self) -> &Display {
+        if self.megachip {
+            &self.presented
+        } else {
+            &self.display
+        }
+    }
+
+    /// Mask applied to the address register when it is incremented, which depends on
+    /// whether 24-bit MegaChip addressing is in use.
+    pub fn address_mask(&self) -> u32 {
+        if self.megachip {
+            0xFF_FFFF
+        } else {
+            0xFFFF
+        }
+    }
+
     pub fn get_sprite_location(&self, digit: u8) -> std::result::Result<u16, Error> {
         if digit > 15 {
             return err(&format!("invalid sprite digit: {}", digit));
         }
-        Ok(u16::from(digit * 5))
+        Ok(self.font_address + u16::from(digit * 5))
     }
 
-    fn load_sprites(&mut self) {
-        let fonts = &mut self.memory[0..80];
-        fonts.copy_from_slice(&sprites::HEX_DIGITS);
+    fn load_sprites(&mut self, font: &Font) {
+        let start = usize::from(self.font_address);
+        self.memory[start..start + sprites::FONT_SIZE].copy_from_slice(font.bytes());
     }
 }
 
+/// The address of entry `i` of the call stack with the VIP memory layout, counting
+/// from the bottom of the stack.
+fn vip_stack_entry(i: usize) -> usize {
+    VIP_STACK_TOP - 1 - i * 2
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -97,6 +401,18 @@ mod tests {
         assert!(system.get_sprite_location(20).is_err());
     }
 
+    #[test]
+    fn custom_font_location() {
+        let system = SystemState::with_config(MachineConfig {
+            font: Font::VIP,
+            font_address: 0x50,
+            ..Default::default()
+        });
+        assert_eq!(&system.memory[0x50..0xA0], Font::VIP.bytes());
+        assert_eq!(&system.memory[0..80], &[0; 80][..]);
+        assert_eq!(system.get_sprite_location(1).unwrap(), 0x55);
+    }
+
     #[test]
     fn programs_load() {
         let mut system = SystemState::new();
@@ -124,6 +440,107 @@ mod tests {
         assert_eq!(system.program_counter, 0x202);
     }
 
+    #[test]
+    fn megachip_memory() {
+        let system = SystemState::with_platform(Platform::MegaChip8);
+        assert_eq!(system.memory.len(), 0x100_0000);
+        assert_eq!(&system.memory[0..80], &sprites::HEX_DIGITS[..]);
+    }
+
+    #[test]
+    fn megachip_mode_resizes_display() {
+        let mut system = SystemState::with_platform(Platform::MegaChip8);
+        system.set_megachip(true);
+        assert_eq!(system.visible_display().width(), 256);
+        assert_eq!(system.visible_display().height(), 192);
+        system.set_megachip(false);
+        assert_eq!(system.visible_display().width(), 64);
+        assert_eq!(system.visible_display().height(), 32);
+    }
+
+    fn vip_layout_system() -> SystemState {
+        SystemState::with_config(MachineConfig {
+            layout: MemoryLayout::Vip,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn vip_layout_program_limit() {
+        let mut system = vip_layout_system();
+        assert!(system.load_program(&[0x55; 0xCA0]).is_ok());
+        assert!(system.load_program(&[0x55; 0xCA1]).is_err());
+    }
+
+    #[test]
+    fn vip_layout_stack_in_memory() {
+        let mut system = vip_layout_system();
+        system.push_stack(0x0234).unwrap();
+        system.push_stack(0x0456).unwrap();
+        assert_eq!(&system.memory[0xECC..0xED0], &[0x04, 0x56, 0x02, 0x34]);
+
+        system.write_memory(0xECC, &[0x03, 0x00]);
+        assert_eq!(system.pop_stack().unwrap(), 0x0300);
+        assert_eq!(system.pop_stack().unwrap(), 0x0234);
+        assert!(system.pop_stack().is_err());
+
+        for _ in 0..12 {
+            system.push_stack(0x0200).unwrap();
+        }
+        assert!(system.push_stack(0x0200).is_err());
+        assert_eq!(&system.memory[0xEB6..0xEB8], &[0, 0]);
+    }
+
+    #[test]
+    fn vip_layout_display_in_memory() {
+        let mut system = vip_layout_system();
+        system.display.set(1, 0, true);
+        system.display.set(8, 2, true);
+        system.display_updated();
+        assert_eq!(system.memory[0xF00], 0b0100_0000);
+        assert_eq!(system.memory[0xF11], 0b1000_0000);
+
+        system.write_memory(0xF00, &[0x00]);
+        assert!(!system.display.is_lit(1, 0));
+        assert!(system.display.is_lit(8, 2));
+        system.write_memory(0xFFF, &[0x01]);
+        assert!(system.display.is_lit(63, 31));
+    }
+
+    #[test]
+    fn separate_layout_leaves_memory_alone() {
+        let mut system = SystemState::new();
+        system.push_stack(0x0234).unwrap();
+        system.display.set(0, 0, true);
+        system.display_updated();
+        assert_eq!(&system.memory[0xEA0..0xEA2], &[0, 0]);
+        assert_eq!(system.memory[0xF00], 0);
+
+        system.write_memory(0xF00, &[0xFF]);
+        assert!(system.display.is_lit(0, 0));
+        assert!(!system.display.is_lit(1, 0));
+        assert_eq!(system.lowest_address(), 0x200);
+    }
+
+    #[test]
+    fn memory_writes_wrap() {
+        let mut system = SystemState::new();
+        system.write_memory(0xFFE, &[1, 2, 3]);
+        assert_eq!(&system.memory[0xFFE..], &[1, 2]);
+        assert_eq!(system.memory[0], 3);
+    }
+
+    #[test]
+    fn report_follows_policy() {
+        let mut system = SystemState::new();
+        let message = || "invalid address".to_owned();
+        assert!(system.report(ErrorPolicy::Strict, message()).is_err());
+        assert!(system.report(ErrorPolicy::Lenient, message()).is_ok());
+        assert!(system.warnings.is_empty());
+        assert!(system.report(ErrorPolicy::Warn, message()).is_ok());
+        assert_eq!(system.warnings.len(), 1);
+    }
+
     #[test]
     fn tick_timers() {
         let mut system = SystemState::new();
@@ -0,0 +1,117 @@
+use crate::system::SystemState;
+
+/// How time passes for the emulated machine, which determines when the delay and
+/// sound timers tick.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// Timers tick according to the host time passed to `Emulator::execute_cycle`.
+    #[default]
+    Host,
+    /// Each call to `Emulator::execute_cycle` lasts one cycle at the emulator's clock
+    /// speed, whatever host time is passed. Timers tick after an exact number of
+    /// cycles, so runs are reproducible given the same input.
+    Emulated,
+    /// Each instruction takes as long as it did in the original COSMAC VIP
+    /// interpreter, so programs run at the speed they were written for. Timers tick
+    /// at 60 Hz once a frame's worth of VIP machine cycles has been used, whatever
+    /// the clock speed and timer frequency. Runs are reproducible given the same input.
+    Vip,
+}
+
+/// Machine cycles run by the VIP's CDP1802 during each 60 Hz frame. The CPU is clocked
+/// at 1.76 MHz, and each machine cycle takes 8 clock cycles.
+const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// Machine cycles per frame lost to the display, which reads 1024 bytes of memory by
+/// DMA, and to the interrupt routine that starts it and counts down the timers.
+const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+/// Machine cycles per frame left for the interpreter to run the program.
+pub const VIP_FRAME_CYCLES: u32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+
+/// Machine cycles the interpreter spends fetching and decoding each instruction.
+const VIP_FETCH_CYCLES: u32 = 68;
+/// Machine cycles spent polling the keypad while a program waits on `FX0A`.
+pub const VIP_KEY_WAIT_CYCLES: u32 = 128;
+
+/// The approximate number of machine cycles the VIP interpreter takes to execute an
+/// instruction, given the state before it runs.
+pub fn vip_instruction_cycles(system: &SystemState, opcode: u16) -> u32 {
+    let x = usize::from((opcode >> 8) as u8 & 0xF);
+    let y = usize::from((opcode >> 4) as u8 & 0xF);
+    let nn = opcode as u8;
+    let vx = system.registers[x];
+    let vy = system.registers[y];
+    let skip = |taken: bool, cycles: u32| if taken { cycles + 4 } else { cycles };
+
+    let cycles = match (
+        (opcode >> 12) as u8,
+        (opcode >> 8) as u8 & 0xF,
+        (opcode >> 4) as u8 & 0xF,
+        opcode as u8 & 0xF,
+    ) {
+        (0x0, 0x0, 0xE, 0x0) => 3078,
+        (0x0, 0x0, 0xE, 0xE) => 10,
+        // Machine code subroutines take as long as they take.
+        (0x0, _, _, _) => 0,
+        (0x1, _, _, _) => 12,
+        (0x2, _, _, _) => 26,
+        (0x3, _, _, _) => skip(vx == nn, 10),
+        (0x4, _, _, _) => skip(vx != nn, 10),
+        (0x5, _, _, _) => skip(vx == vy, 14),
+        (0x6, _, _, _) => 6,
+        (0x7, _, _, _) => 10,
+        (0x8, _, _, 0x0) => 12,
+        (0x8, _, _, _) => 44,
+        (0x9, _, _, _) => skip(vx != vy, 14),
+        (0xA, _, _, _) => 12,
+        (0xB, _, _, _) => {
+            // Crossing a page takes an extra instruction.
+            let addr = opcode & 0xFFF;
+            let target = addr + u16::from(system.registers[0]);
+            if target & 0xFF00 != addr & 0xFF00 {
+                24
+            } else {
+                22
+            }
+        }
+        (0xC, _, _, _) => 36,
+        (0xD, _, _, n) => {
+            // Each row of the sprite is shifted into place one bit at a time, and
+            // unaligned rows are split across two bytes of the display.
+            let shift = u32::from(vx % 8);
+            let row = 34 + shift * 8 + if shift > 0 { 20 } else { 0 };
+            26 + u32::from(n) * row
+        }
+        (0xE, _, 0x9, 0xE) => skip(system.keys[usize::from(vx & 0xF)], 14),
+        (0xE, _, 0xA, 0x1) => skip(!system.keys[usize::from(vx & 0xF)], 14),
+        (0xF, _, 0x3, 0x3) => {
+            // Digits are found by repeated subtraction.
+            let digits = u32::from(vx / 100) + u32::from(vx / 10 % 10) + u32::from(vx % 10);
+            80 + digits * 16
+        }
+        (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => 14 + (x as u32 + 1) * 14,
+        (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) => 16,
+        _ => 10,
+    };
+    VIP_FETCH_CYCLES + cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instruction_cycles() {
+        let mut system = SystemState::new();
+        assert_eq!(vip_instruction_cycles(&system, 0x6012), 74);
+        assert_eq!(vip_instruction_cycles(&system, 0x3000), 82);
+        assert_eq!(vip_instruction_cycles(&system, 0x3001), 78);
+
+        // Unaligned sprites are slower.
+        assert_eq!(vip_instruction_cycles(&system, 0xD015), 264);
+        system.registers[0] = 3;
+        assert_eq!(vip_instruction_cycles(&system, 0xD015), 484);
+
+        system.registers[0] = 199;
+        assert_eq!(vip_instruction_cycles(&system, 0xF033), 452);
+    }
+}
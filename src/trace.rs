@@ -0,0 +1,359 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::disasm::{self, OpcodeClass};
+use crate::errors::*;
+use crate::system::SystemState;
+
+/// Selects which instructions are written to a trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only trace instructions at addresses in this inclusive range.
+    pub pc_range: Option<(u16, u16)>,
+    /// Only trace instructions of these classes. All classes are traced if empty.
+    pub classes: Vec<OpcodeClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, class: OpcodeClass) -> bool {
+        let in_range = match self.pc_range {
+            Some((start, end)) => start <= pc && pc <= end,
+            None => true,
+        };
+        in_range && (self.classes.is_empty() || self.classes.contains(&class))
+    }
+}
+
+/// Writes a line for each instruction executed, with the state of the machine before
+/// it ran. See `TraceEntry` for the format.
+pub struct Tracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        Tracer { output, filter }
+    }
+
+    pub(crate) fn trace(&mut self, cycle: u64, system: &SystemState) -> Result {
+        let entry = TraceEntry::new(cycle, system);
+        if !self
+            .filter
+            .matches(entry.pc, OpcodeClass::of(entry.opcode, system.megachip))
+        {
+            return Ok(());
+        }
+        writeln!(self.output, "{}", entry)
+            .map_err(|e| Error(format!("failed to write trace: {}", e)))
+    }
+}
+
+/// One line of a trace: the state of the machine before executing an instruction.
+///
+/// Lines have whitespace-separated fields: the cycle, the program counter and the
+/// opcode, then the instruction's mnemonic, then the registers, `I`, the stack pointer
+/// and the timers, in hexadecimal. For example:
+///
+/// ```text
+/// 00000002 0204 D015 DRW V0, V1, 5    V:0A140000000000000000000000000000 I:020C SP:00 DT:00 ST:00
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub registers: [u8; 16],
+    pub address_register: u32,
+    pub stack_pointer: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    fn new(cycle: u64, system: &SystemState) -> TraceEntry {
+        let len = system.memory.len();
+        let pc = usize::from(system.program_counter) % len;
+        let opcode = (u16::from(system.memory[pc]) << 8) | u16::from(system.memory[(pc + 1) % len]);
+        TraceEntry {
+            cycle,
+            pc: system.program_counter,
+            opcode,
+            mnemonic: disasm::disassemble(opcode, system.megachip),
+            registers: system.registers,
+            address_register: system.address_register,
+            stack_pointer: system.stack_pointer,
+            delay_timer: system.delay_timer,
+            sound_timer: system.sound_timer,
+        }
+    }
+
+    /// The names of the fields that differ from another entry. Mnemonics aren't
+    /// compared, since they vary between emulators.
+    pub fn differences(&self, other: &TraceEntry) -> Vec<String> {
+        let mut fields = Vec::new();
+        let mut compare = |name: &str, same: bool| {
+            if !same {
+                fields.push(name.to_owned());
+            }
+        };
+        compare("cycle", self.cycle == other.cycle);
+        compare("PC", self.pc == other.pc);
+        compare("opcode", self.opcode == other.opcode);
+        for (i, (a, b)) in self
+            .registers
+            .iter()
+            .zip(other.registers.iter())
+            .enumerate()
+        {
+            compare(&format!("V{:X}", i), a == b);
+        }
+        compare("I", self.address_register == other.address_register);
+        compare("SP", self.stack_pointer == other.stack_pointer);
+        compare("DT", self.delay_timer == other.delay_timer);
+        compare("ST", self.sound_timer == other.sound_timer);
+        fields
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:08} {:04X} {:04X} {:<16} V:",
+            self.cycle, self.pc, self.opcode, self.mnemonic
+        )?;
+        for value in self.registers.iter() {
+            write!(f, "{:02X}", value)?;
+        }
+        write!(
+            f,
+            " I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X}",
+            self.address_register, self.stack_pointer, self.delay_timer, self.sound_timer
+        )
+    }
+}
+
+impl FromStr for TraceEntry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<TraceEntry> {
+        let invalid = || Error(format!("invalid trace line: {}", line));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let registers_at = fields
+            .iter()
+            .position(|field| field.starts_with("V:"))
+            .ok_or_else(invalid)?;
+        if registers_at < 3 || fields.len() != registers_at + 5 {
+            return Err(invalid());
+        }
+        let hex = |field: &str, prefix: &str| {
+            field
+                .strip_prefix(prefix)
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .ok_or_else(invalid)
+        };
+        let byte = |field: &str, prefix: &str| {
+            hex(field, prefix).and_then(|value| u8::try_from(value).map_err(|_| invalid()))
+        };
+
+        let digits = &fields[registers_at][2..];
+        if digits.len() != 32 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        let mut registers = [0; 16];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(TraceEntry {
+            cycle: fields[0].parse().map_err(|_| invalid())?,
+            pc: u16::from_str_radix(fields[1], 16).map_err(|_| invalid())?,
+            opcode: u16::from_str_radix(fields[2], 16).map_err(|_| invalid())?,
+            mnemonic: fields[3..registers_at].join(" "),
+            registers,
+            address_register: hex(fields[registers_at + 1], "I:")?,
+            stack_pointer: byte(fields[registers_at + 2], "SP:")?,
+            delay_timer: byte(fields[registers_at + 3], "DT:")?,
+            sound_timer: byte(fields[registers_at + 4], "ST:")?,
+        })
+    }
+}
+
+/// The first point where two traces differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The line number in the first trace, or in the second if the first ended,
+    /// starting from 1.
+    pub line: usize,
+    /// The entry from the first trace, or `None` if it ended first.
+    pub expected: Option<TraceEntry>,
+    /// The entry from the second trace, or `None` if it ended first.
+    pub actual: Option<TraceEntry>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "traces diverge at line {}", self.line)?;
+        if let (Some(expected), Some(actual)) = (&self.expected, &self.actual) {
+            write!(f, " ({} differ)", expected.differences(actual).join(", "))?;
+        }
+        for (marker, entry) in [("<", &self.expected), (">", &self.actual)].iter() {
+            match entry {
+                Some(entry) => write!(f, "\n{} {}", marker, entry)?,
+                None => write!(f, "\n{} end of trace", marker)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the entries of a trace, with their line numbers, skipping blank lines.
+fn entries<R: BufRead>(trace: R) -> impl Iterator<Item = Result<(usize, TraceEntry)>> {
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(i, line)| {
+            let line = line.map_err(|e| Error(format!("failed to read trace: {}", e)))?;
+            Ok((i + 1, line.parse()?))
+        })
+}
+
+/// Finds the first entry where two traces differ, ignoring mnemonics and blank lines.
+/// Returns `None` if the traces are the same.
+///
+/// # Errors
+///
+/// Returns an error if either trace can't be read, or has a line that isn't in the
+/// trace format.
+pub fn compare_traces<A: BufRead, B: BufRead>(
+    expected: A,
+    actual: B,
+) -> Result<Option<Divergence>> {
+    let mut expected = entries(expected);
+    let mut actual = entries(actual);
+    loop {
+        let (line, expected, actual) = match (expected.next(), actual.next()) {
+            (None, None) => return Ok(None),
+            (Some(a), None) => {
+                let (line, a) = a?;
+                (line, Some(a), None)
+            }
+            (None, Some(b)) => {
+                let (line, b) = b?;
+                (line, None, Some(b))
+            }
+            (Some(a), Some(b)) => {
+                let ((line, a), (_, b)) = (a?, b?);
+                if a.differences(&b).is_empty() {
+                    continue;
+                }
+                (line, Some(a), Some(b))
+            }
+        };
+        return Ok(Some(Divergence {
+            line,
+            expected,
+            actual,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn trace(program: &[u8], cycles: usize, filter: TraceFilter) -> String {
+        let mut system = SystemState::new();
+        system.load_program(program).unwrap();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut tracer = Tracer::new(Box::new(SharedBuffer(Rc::clone(&output))), filter);
+        for cycle in 0..cycles {
+            tracer.trace(cycle as u64, &system).unwrap();
+            system.program_counter += 2;
+        }
+        let bytes = output.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn format_round_trips() {
+        let line = "00000002 0204 D015 DRW V0, V1, 5    \
+                    V:0A140000000000000000000000000000 I:020C SP:00 DT:00 ST:00";
+        let entry: TraceEntry = line.parse().unwrap();
+        assert_eq!(entry.mnemonic, "DRW V0, V1, 5");
+        assert_eq!(entry.registers[1], 0x14);
+        assert_eq!(entry.address_register, 0x20C);
+        assert_eq!(entry.to_string(), line);
+        assert!("00000002 0204 D015 DRW V0, V1, 5"
+            .parse::<TraceEntry>()
+            .is_err());
+    }
+
+    #[test]
+    fn filters() {
+        // CLS; LD V0, 1; LD I, 0x300; DRW V0, V0, 1
+        let program = [0x00, 0xE0, 0x60, 0x01, 0xA3, 0x00, 0xD0, 0x01];
+        assert_eq!(
+            trace(&program, 4, TraceFilter::default()).lines().count(),
+            4
+        );
+
+        let display = TraceFilter {
+            classes: vec![OpcodeClass::Display],
+            ..Default::default()
+        };
+        let lines: Vec<String> = trace(&program, 4, display)
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("00000003 0206 D001 DRW V0, V0, 1 "));
+
+        let range = TraceFilter {
+            pc_range: Some((0x202, 0x204)),
+            ..Default::default()
+        };
+        assert_eq!(trace(&program, 4, range).lines().count(), 2);
+    }
+
+    #[test]
+    fn divergence() {
+        let a = "00000000 0200 6001 LD V0, 0x01 V:00000000000000000000000000000000 I:0000 SP:00 DT:00 ST:00\n\
+                 00000001 0202 6102 LD V1, 0x02 V:01000000000000000000000000000000 I:0000 SP:00 DT:00 ST:00\n";
+        let b = "00000000 0200 6001 ld v0, 1 V:00000000000000000000000000000000 I:0000 SP:00 DT:00 ST:00\n\
+                 00000001 0202 6102 LD V1, 0x02 V:02000000000000000000000000000000 I:0000 SP:00 DT:00 ST:00\n";
+        assert_eq!(compare_traces(a.as_bytes(), a.as_bytes()).unwrap(), None);
+
+        let divergence = compare_traces(a.as_bytes(), b.as_bytes()).unwrap().unwrap();
+        assert_eq!(divergence.line, 2);
+        assert!(divergence
+            .to_string()
+            .starts_with("traces diverge at line 2 (V0 differ)"));
+
+        let error = compare_traces(a.as_bytes(), "0200 6001".as_bytes()).unwrap_err();
+        assert!(error.0.starts_with("invalid trace line"));
+        let first_line = &a[..a.find('\n').unwrap()];
+        let divergence = compare_traces(a.as_bytes(), first_line.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!((divergence.line, divergence.actual), (2, None));
+    }
+}
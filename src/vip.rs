@@ -0,0 +1,502 @@
+use std::rc::Rc;
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::display::Display;
+use crate::errors::*;
+use crate::extension::Extensions;
+use crate::opcodes::IOpcodeRunner;
+use crate::system::SystemState;
+
+/// Size of the VIP monitor ROM, which is mapped at 0x8000.
+pub const MONITOR_SIZE: usize = 0x200;
+/// Maximum size of the CHIP-8 interpreter, which is loaded at 0x0000.
+pub const INTERPRETER_SIZE: usize = 0x200;
+
+/// Resolution of the CDP1861 video chip. The CHIP-8 interpreter repeats each row of
+/// its 64x32 display across four scanlines.
+const VIDEO_WIDTH: usize = 64;
+const VIDEO_HEIGHT: usize = 128;
+
+// CDP1861 timing, in machine cycles and scanlines. Each frame is 262 lines of 14
+// machine cycles, and a visible line starts with 8 cycles of DMA.
+const CYCLES_PER_LINE: u32 = 14;
+const LINES_PER_FRAME: u32 = 262;
+const CYCLES_PER_FRAME: u32 = CYCLES_PER_LINE * LINES_PER_FRAME;
+const DMA_CYCLES: u32 = 8;
+const INTERRUPT_LINE: u32 = 78;
+const FIRST_VISIBLE_LINE: u32 = 80;
+const LAST_VISIBLE_LINE: u32 = FIRST_VISIBLE_LINE + VIDEO_HEIGHT as u32;
+
+/// `SEP 4`. The interpreter keeps the address of its fetch and decode loop in R4,
+/// and each instruction's routine returns to it with this, so it marks the boundary
+/// between CHIP-8 instructions wherever the interpreter puts its loop.
+const RETURN_TO_FETCH: u8 = 0xD4;
+
+/// State of a COSMAC VIP, apart from its RAM which is shared with `SystemState`.
+#[derive(Clone)]
+pub struct Vip {
+    cpu: Cdp1802,
+    monitor: Rc<[u8]>,
+    io: VipIo,
+    line_cycle: u32,
+    interrupt_pending: bool,
+    dma_pending: bool,
+    frames: u64,
+    /// Whether the last run ended before the interpreter returned to its fetch loop.
+    mid_instruction: bool,
+}
+
+/// State of the VIP hardware that is visible to the CPU through the bus.
+#[derive(Clone)]
+struct VipIo {
+    // On reset, the monitor ROM is also mapped at 0x0000 until the first access
+    // with A15 set.
+    rom_at_zero: bool,
+    display_enabled: bool,
+    key_latch: u8,
+    line: u32,
+}
+
+impl Vip {
+    fn new(monitor: Rc<[u8]>) -> Vip {
+        Vip {
+            cpu: Cdp1802::new(),
+            monitor,
+            io: VipIo {
+                rom_at_zero: true,
+                display_enabled: false,
+                key_latch: 0,
+                line: 0,
+            },
+            line_cycle: 0,
+            interrupt_pending: false,
+            dma_pending: false,
+            frames: 0,
+            mid_instruction: false,
+        }
+    }
+
+    /// The number of frames the video chip has started since the VIP was switched on.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Whether the interpreter is still running the CHIP-8 instruction it started in
+    /// an earlier cycle, such as when `FX0A` is waiting for a key.
+    pub fn mid_instruction(&self) -> bool {
+        self.mid_instruction
+    }
+
+    /// Runs the CPU until the interpreter returns to its fetch loop, or until a
+    /// frame's worth of cycles has passed if it never does.
+    fn run_instruction(&mut self, system: &mut SystemState) {
+        let mut cycles = 0;
+        self.mid_instruction = true;
+        while cycles < CYCLES_PER_FRAME {
+            if self.interrupt_pending && self.cpu.ie {
+                self.interrupt_pending = false;
+                let used = self.cpu.interrupt();
+                cycles += self.tick(used, system);
+            }
+            if self.dma_pending {
+                self.dma_pending = false;
+                self.dma_line(system);
+                cycles += self.tick(DMA_CYCLES, system);
+            }
+            let mut bus = VipBus::new(&mut self.io, &self.monitor, system);
+            let returning =
+                !self.cpu.idle && self.cpu.p != 4 && bus.read(self.cpu.pc()) == RETURN_TO_FETCH;
+            let used = self.cpu.step(&mut bus);
+            cycles += self.tick(used, system);
+            if returning {
+                self.mid_instruction = false;
+                break;
+            }
+        }
+        self.sync(system);
+    }
+
+    /// Advances the video chip by the given number of machine cycles, returning it.
+    fn tick(&mut self, cycles: u32, system: &mut SystemState) -> u32 {
+        for _ in 0..cycles {
+            self.line_cycle += 1;
+            if self.line_cycle < CYCLES_PER_LINE {
+                continue;
+            }
+            self.line_cycle = 0;
+            let line = (self.io.line + 1) % LINES_PER_FRAME;
+            self.io.line = line;
+            match line {
+                INTERRUPT_LINE => {
+                    self.frames += 1;
+                    self.interrupt_pending = self.io.display_enabled;
+                }
+                FIRST_VISIBLE_LINE => self.interrupt_pending = false,
+                _ => {}
+            }
+            if (FIRST_VISIBLE_LINE..LAST_VISIBLE_LINE).contains(&line) {
+                if self.io.display_enabled {
+                    self.dma_pending = true;
+                } else {
+                    blank_line(&mut system.display, (line - FIRST_VISIBLE_LINE) as usize);
+                }
+            }
+        }
+        cycles
+    }
+
+    fn dma_line(&mut self, system: &mut SystemState) {
+        let y = (self.io.line - FIRST_VISIBLE_LINE) as usize;
+        let mut bytes = [0; VIDEO_WIDTH / 8];
+        let mut bus = VipBus::new(&mut self.io, &self.monitor, system);
+        for byte in bytes.iter_mut() {
+            *byte = self.cpu.dma_out(&mut bus);
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            for bit in 0..8 {
+                let lit = byte & (0x80 >> bit) != 0;
+                system.display.set(i * 8 + bit, y, lit);
+            }
+        }
+    }
+
+    /// Mirrors the interpreter's CHIP-8 state into `SystemState`, so that it can be
+    /// inspected the same way as with the built-in interpreter. The interpreter keeps
+    /// the CHIP-8 program counter in R5, I in RA, and the delay and sound timers in
+    /// R8.1 and R8.0. V0-VF live at offset 0xF0 of the page that R6 points into.
+    /// During an instruction, R5 is already past it, but the program counter is left
+    /// at it, as the built-in interpreter does while `FX0A` waits.
+    fn sync(&self, system: &mut SystemState) {
+        let r = &self.cpu.r;
+        system.program_counter = if self.mid_instruction {
+            r[5].wrapping_sub(2) & 0xFFF
+        } else {
+            r[5] & 0xFFF
+        };
+        system.address_register = u32::from(r[0xA] & 0xFFF);
+        system.delay_timer = (r[8] >> 8) as u8;
+        system.sound_timer = r[8] as u8;
+        let base = usize::from((r[6] & 0x0F00) | 0xF0);
+        system
+            .registers
+            .copy_from_slice(&system.memory[base..base + 16]);
+    }
+}
+
+fn blank_line(display: &mut Display, y: usize) {
+    for x in 0..VIDEO_WIDTH {
+        display.set(x, y, false);
+    }
+}
+
+/// The VIP's view of memory and I/O. RAM is 4 KiB, mirrored up to 0x7FFF, and the
+/// monitor ROM is mirrored from 0x8000 to 0xFFFF.
+struct VipBus<'a> {
+    vip: &'a mut VipIo,
+    monitor: &'a [u8],
+    memory: &'a mut [u8],
+    keys: &'a [bool; 16],
+}
+
+impl<'a> VipBus<'a> {
+    fn new(vip: &'a mut VipIo, monitor: &'a [u8], system: &'a mut SystemState) -> VipBus<'a> {
+        VipBus {
+            vip,
+            monitor,
+            memory: &mut system.memory,
+            keys: &system.keys,
+        }
+    }
+}
+
+impl<'a> Bus for VipBus<'a> {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr & 0x8000 != 0 {
+            self.vip.rom_at_zero = false;
+        }
+        if addr & 0x8000 != 0 || self.vip.rom_at_zero {
+            self.monitor[usize::from(addr) % MONITOR_SIZE]
+        } else {
+            self.memory[usize::from(addr) % self.memory.len()]
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr & 0x8000 == 0 {
+            let len = self.memory.len();
+            self.memory[usize::from(addr) % len] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.vip.display_enabled = false,
+            2 => self.vip.key_latch = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.vip.display_enabled = true;
+        }
+        0xFF
+    }
+
+    fn flag(&self, n: u8) -> bool {
+        match n {
+            // EF1 is asserted for the four lines before and at the end of the display.
+            1 => {
+                let line = self.vip.line;
+                (FIRST_VISIBLE_LINE - 4..FIRST_VISIBLE_LINE).contains(&line)
+                    || (LAST_VISIBLE_LINE - 4..LAST_VISIBLE_LINE).contains(&line)
+            }
+            // EF3 is asserted while the key selected by OUT 2 is held down.
+            3 => self.keys[usize::from(self.vip.key_latch)],
+            _ => false,
+        }
+    }
+}
+
+/// Runs programs by emulating a COSMAC VIP running the original CHIP-8
+/// interpreter, rather than interpreting CHIP-8 instructions directly.
+///
+/// Both the 512-byte monitor ROM and the CHIP-8 interpreter must be supplied by the
+/// user. Execution starts in the monitor, exactly as when the VIP is switched to RUN,
+/// which then jumps to the interpreter at 0x0000.
+pub struct VipRunner {
+    monitor: Rc<[u8]>,
+    interpreter: Rc<[u8]>,
+}
+
+impl VipRunner {
+    pub fn new(monitor: &[u8], interpreter: &[u8]) -> std::result::Result<VipRunner, Error> {
+        if monitor.len() != MONITOR_SIZE {
+            return err(&format!(
+                "monitor ROM must be {} bytes, got {} bytes",
+                MONITOR_SIZE,
+                monitor.len()
+            ));
+        }
+        if interpreter.len() > INTERPRETER_SIZE {
+            return err(&format!(
+                "interpreter must be at most {} bytes, got {} bytes",
+                INTERPRETER_SIZE,
+                interpreter.len()
+            ));
+        }
+        Ok(VipRunner {
+            monitor: Rc::from(monitor),
+            interpreter: Rc::from(interpreter),
+        })
+    }
+}
+
+impl IOpcodeRunner for VipRunner {
+    fn init(&self, system: &mut SystemState) {
+        for byte in system.memory[0..INTERPRETER_SIZE].iter_mut() {
+            *byte = 0;
+        }
+        system.memory[0..self.interpreter.len()].copy_from_slice(&self.interpreter);
+        system.display = Display::new(VIDEO_WIDTH, VIDEO_HEIGHT);
+        system.vip = Some(Box::new(Vip::new(Rc::clone(&self.monitor))));
+    }
+
+    fn step(&self, system: &mut SystemState, _: &mut Extensions) -> Result {
+        let mut vip = system
+            .vip
+            .take()
+            .expect("VIP state is created when the runner is initialized");
+        vip.run_instruction(system);
+        // The interpreter spins in `FX0A` until a key is pressed and released, so an
+        // unfinished `FX0A` with no key held is waiting for one. Key presses are read
+        // by the interpreter itself rather than passed to it.
+        let pc = usize::from(system.program_counter);
+        let opcode = u16::from_be_bytes([system.memory[pc], system.memory[(pc + 1) & 0xFFF]]);
+        system.waiting_for_key = vip.mid_instruction
+            && opcode & 0xF0FF == 0xF00A
+            && !system.keys.iter().any(|&held| held);
+        system.pending_keypress = None;
+        system.vip = Some(vip);
+        Ok(())
+    }
+
+    fn run(&self, _: &mut SystemState, _: &mut Extensions, opcode: u16) -> Result {
+        err(&format!(
+            "the VIP interpreter cannot run individual instructions: 0x{:04X}",
+            opcode
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emulator, ExecutionStatus, MemoryLayout, StopCondition};
+    use std::time::Duration;
+
+    /// A monitor that jumps from the reset vector to the top of ROM, then to RAM.
+    fn monitor() -> Vec<u8> {
+        let mut rom = vec![0; MONITOR_SIZE];
+        rom[0..6].copy_from_slice(&[0xC0, 0x80, 0x03, 0xC0, 0x00, 0x00]);
+        rom
+    }
+
+    /// A program that installs an interrupt routine pointing R0 at 0x0F00, turns the
+    /// display on, and loops forever. Like the VIP's own routine, the interrupt
+    /// routine exits through a RET placed just before its entry point, so that R1 is
+    /// left pointing at the entry point again.
+    const DISPLAY_PROGRAM: [u8; 41] = [
+        0xF8, 0x00, 0xB1, 0xF8, 0x20, 0xA1, // R1 = 0x0020
+        0xF8, 0x00, 0xB2, 0xF8, 0x80, 0xA2, // R2 = 0x0080
+        0xF8, 0x00, 0xB3, 0xF8, 0x13, 0xA3, // R3 = 0x0013
+        0xD3, // SEP 3
+        0xE2, // SEX 2
+        0x69, // INP 1
+        0x30, 0x15, // BR 15
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+        0x70, // RET
+        0xF8, 0x0F, 0xB0, 0xF8, 0x00, 0xA0, // R0 = 0x0F00
+        0x22, // DEC 2
+        0x30, 0x1F, // BR 1F
+    ];
+
+    fn vip_system(interpreter: &[u8]) -> (VipRunner, SystemState) {
+        let runner = VipRunner::new(&monitor(), interpreter).unwrap();
+        let mut system = SystemState::new();
+        runner.init(&mut system);
+        (runner, system)
+    }
+
+    #[test]
+    fn rom_sizes_are_checked() {
+        assert!(VipRunner::new(&[0; 100], &[]).is_err());
+        assert!(VipRunner::new(&monitor(), &[0; 0x201]).is_err());
+    }
+
+    #[test]
+    fn boots_into_interpreter() {
+        let (runner, mut system) = vip_system(&[0x30, 0x00]);
+        assert_eq!(&system.memory[0..2], &[0x30, 0x00]);
+        runner
+            .step(&mut system, &mut Extensions::default())
+            .unwrap();
+        let vip = system.vip.as_ref().unwrap();
+        assert!(!vip.io.rom_at_zero);
+        assert_eq!(vip.cpu.pc() & 0xFFF, 0x0000);
+    }
+
+    #[test]
+    fn instructions_end_when_returning_to_r4() {
+        // Points R4 at a fetch loop at 0x0010, which calls a routine at 0x0020 that
+        // counts in RA and returns with SEP 4.
+        let interpreter = [
+            0xF8, 0x00, 0xB4, 0xF8, 0x10, 0xA4, // R4 = 0x0010
+            0xF8, 0x00, 0xB3, // R3.1 = 0x00
+            0xD4, // SEP 4
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0xF8, 0x20, 0xA3, // R3 = 0x0020
+            0xD3, // SEP 3
+            0x30, 0x10, // BR 10
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0x1A, // INC A
+            0xD4, // SEP 4
+        ];
+        let (runner, mut system) = vip_system(&interpreter);
+        for instructions in 0..3 {
+            runner
+                .step(&mut system, &mut Extensions::default())
+                .unwrap();
+            let cpu = &system.vip.as_ref().unwrap().cpu;
+            assert_eq!(cpu.p, 4);
+            assert_eq!(cpu.r[0xA], instructions);
+        }
+    }
+
+    #[test]
+    fn frames_are_counted_by_the_video_chip() {
+        // BR 00, so each cycle runs for a whole frame.
+        let mut emulator = Emulator::cosmac_vip(&monitor(), &[0x30, 0x00]).unwrap();
+        emulator.load_program(&[0x12, 0x00]).unwrap();
+        emulator.run_until(&[StopCondition::Frames(2)]).unwrap();
+        // The interpreter never returns to a fetch loop, so it is still in the first
+        // instruction.
+        assert_eq!(emulator.cycles(), 1);
+        assert_eq!(emulator.frames(), 2);
+        let vip = emulator.system.vip.as_ref().unwrap();
+        assert_eq!(vip.frames(), 2);
+    }
+
+    #[test]
+    fn waits_for_keys_in_fx0a() {
+        // Runs one CHIP-8 instruction at a time from 0x200, with a routine for every
+        // instruction that loops until key 0 is held, like the interpreter's FX0A.
+        let interpreter = [
+            0xF8, 0x00, 0xB4, 0xF8, 0x10, 0xA4, // R4 = 0x0010
+            0xF8, 0x02, 0xB5, 0xF8, 0x00, 0xA5, // R5 = 0x0200
+            0xD4, // SEP 4
+            0x00, 0x00, 0x00, // padding
+            0x45, 0x45, // LDA 5; LDA 5
+            0xF8, 0x00, 0xB3, 0xF8, 0x20, 0xA3, // R3 = 0x0020
+            0xD3, // SEP 3
+            0x30, 0x10, // BR 10
+            0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0x3E, 0x20, // BN3 20
+            0xD4, // SEP 4
+        ];
+        let mut emulator = Emulator::cosmac_vip(&monitor(), &interpreter).unwrap();
+        // LD V0, K; LD V0, K
+        emulator.load_program(&[0xF0, 0x0A, 0xF0, 0x0A]).unwrap();
+        // The interpreter starts up in the first cycle, and waits in the second.
+        let stop = emulator.run_until(&[StopCondition::WaitingForKey]).unwrap();
+        assert_eq!(stop, StopCondition::WaitingForKey);
+        for _ in 0..3 {
+            let status = emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+            assert_eq!(status, ExecutionStatus::WaitingForKey);
+        }
+        // The wait is one instruction, however many frames it takes.
+        assert_eq!(emulator.cycles(), 2);
+        assert_eq!(emulator.frames(), 4);
+
+        emulator.on_key_down(0);
+        let status = emulator.execute_cycle(Duration::from_secs(0)).unwrap();
+        assert_eq!(status, ExecutionStatus::Executed);
+        assert_eq!(emulator.view().program_counter(), 0x202);
+        assert_eq!(emulator.cycles(), 2);
+    }
+
+    #[test]
+    fn interpreter_manages_memory_layout() {
+        let mut emulator = Emulator::cosmac_vip(&monitor(), &[0x30, 0x00]).unwrap();
+        assert!(emulator.set_memory_layout(MemoryLayout::Vip).is_err());
+        assert!(emulator.set_memory_layout(MemoryLayout::Separate).is_ok());
+    }
+
+    #[test]
+    fn displays_memory_through_dma() {
+        let (runner, mut system) = vip_system(&DISPLAY_PROGRAM);
+        system.memory[0x7F] = 0x23; // X = 2, P = 3 after returning from interrupts
+        system.memory[0xF00] = 0xA5;
+        for _ in 0..3 {
+            runner
+                .step(&mut system, &mut Extensions::default())
+                .unwrap();
+        }
+        assert_eq!(system.display.width(), 64);
+        assert_eq!(system.display.height(), 128);
+        assert_eq!(&system.display[0][0..8], &[1, 0, 1, 0, 0, 1, 0, 1]);
+        assert_eq!(&system.display[1][0..8], &[0; 8]);
+    }
+
+    #[test]
+    fn reads_keypad_through_latch() {
+        let (_, mut system) = vip_system(&[]);
+        system.keys[0x7] = true;
+        let mut vip = system.vip.take().unwrap();
+        let mut bus = VipBus::new(&mut vip.io, &vip.monitor, &mut system);
+        bus.output(2, 0x6);
+        assert!(!bus.flag(3));
+        bus.output(2, 0x7);
+        assert!(bus.flag(3));
+    }
+}